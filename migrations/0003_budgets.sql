@@ -0,0 +1,6 @@
+-- Budgets are stored in seconds to match time_entries.duration_seconds.
+ALTER TABLE projects ADD COLUMN budget_seconds INTEGER;
+ALTER TABLE projects ADD COLUMN budget_amount REAL;
+ALTER TABLE projects ADD COLUMN hourly_rate REAL;
+
+ALTER TABLE tasks ADD COLUMN estimate_seconds INTEGER;
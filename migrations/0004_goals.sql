@@ -0,0 +1,5 @@
+-- Daily time targets per weekday (0 = Monday .. 6 = Sunday)
+CREATE TABLE goals (
+  weekday         INTEGER PRIMARY KEY CHECK (weekday BETWEEN 0 AND 6),
+  target_seconds  INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Set on stop when an entry runs longer than the maximum session length; cleared by `review`.
+ALTER TABLE time_entries ADD COLUMN flagged INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX ix_time_entries_flagged ON time_entries(flagged) WHERE flagged = 1;
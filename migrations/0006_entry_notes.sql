@@ -0,0 +1,2 @@
+-- Free-text description of what was done during a session
+ALTER TABLE time_entries ADD COLUMN note TEXT;
@@ -0,0 +1,53 @@
+-- Full-text index over project/task names and descriptions and entry notes.
+-- kind is 'project', 'task' or 'entry'; ref_id is the id of the indexed row.
+CREATE VIRTUAL TABLE search_index USING fts5(
+  kind UNINDEXED,
+  ref_id UNINDEXED,
+  name,
+  body
+);
+
+INSERT INTO search_index(kind, ref_id, name, body)
+  SELECT 'project', id, name, COALESCE(description, '') FROM projects;
+INSERT INTO search_index(kind, ref_id, name, body)
+  SELECT 'task', id, name, COALESCE(description, '') FROM tasks;
+INSERT INTO search_index(kind, ref_id, name, body)
+  SELECT 'entry', id, '', note FROM time_entries WHERE note IS NOT NULL;
+
+CREATE TRIGGER search_projects_ai AFTER INSERT ON projects BEGIN
+  INSERT INTO search_index(kind, ref_id, name, body)
+    VALUES ('project', new.id, new.name, COALESCE(new.description, ''));
+END;
+CREATE TRIGGER search_projects_au AFTER UPDATE OF name, description ON projects BEGIN
+  DELETE FROM search_index WHERE kind = 'project' AND ref_id = old.id;
+  INSERT INTO search_index(kind, ref_id, name, body)
+    VALUES ('project', new.id, new.name, COALESCE(new.description, ''));
+END;
+CREATE TRIGGER search_projects_ad AFTER DELETE ON projects BEGIN
+  DELETE FROM search_index WHERE kind = 'project' AND ref_id = old.id;
+END;
+
+CREATE TRIGGER search_tasks_ai AFTER INSERT ON tasks BEGIN
+  INSERT INTO search_index(kind, ref_id, name, body)
+    VALUES ('task', new.id, new.name, COALESCE(new.description, ''));
+END;
+CREATE TRIGGER search_tasks_au AFTER UPDATE OF name, description ON tasks BEGIN
+  DELETE FROM search_index WHERE kind = 'task' AND ref_id = old.id;
+  INSERT INTO search_index(kind, ref_id, name, body)
+    VALUES ('task', new.id, new.name, COALESCE(new.description, ''));
+END;
+CREATE TRIGGER search_tasks_ad AFTER DELETE ON tasks BEGIN
+  DELETE FROM search_index WHERE kind = 'task' AND ref_id = old.id;
+END;
+
+CREATE TRIGGER search_entries_ai AFTER INSERT ON time_entries WHEN new.note IS NOT NULL BEGIN
+  INSERT INTO search_index(kind, ref_id, name, body) VALUES ('entry', new.id, '', new.note);
+END;
+CREATE TRIGGER search_entries_au AFTER UPDATE OF note ON time_entries BEGIN
+  DELETE FROM search_index WHERE kind = 'entry' AND ref_id = old.id;
+  INSERT INTO search_index(kind, ref_id, name, body)
+    SELECT 'entry', new.id, '', new.note WHERE new.note IS NOT NULL;
+END;
+CREATE TRIGGER search_entries_ad AFTER DELETE ON time_entries BEGIN
+  DELETE FROM search_index WHERE kind = 'entry' AND ref_id = old.id;
+END;
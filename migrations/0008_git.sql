@@ -0,0 +1,12 @@
+-- Repositories tracked through git hooks, and the task each branch was tracked on
+CREATE TABLE git_repos (
+  repo_path   TEXT PRIMARY KEY,                   -- absolute work tree path
+  project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE
+);
+
+CREATE TABLE git_branches (
+  project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+  branch      TEXT NOT NULL,
+  task_id     TEXT NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+  PRIMARY KEY (project_id, branch)
+);
@@ -0,0 +1,10 @@
+-- Entries built from editor heartbeats rather than the timer
+ALTER TABLE time_entries ADD COLUMN source TEXT NOT NULL DEFAULT 'timer';  -- timer | heartbeat
+
+CREATE INDEX ix_time_entries_heartbeat ON time_entries(task_id, local_date) WHERE source = 'heartbeat';
+
+-- Directories whose files are tracked on a project; the longest matching prefix wins
+CREATE TABLE heartbeat_rules (
+  path_prefix  TEXT PRIMARY KEY,                  -- absolute directory path
+  project_id   TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE
+);
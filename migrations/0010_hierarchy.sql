@@ -0,0 +1,6 @@
+-- Sub-projects and subtasks; a subtask belongs to the same project as its parent
+ALTER TABLE projects ADD COLUMN parent_id TEXT REFERENCES projects(id) ON DELETE CASCADE;
+ALTER TABLE tasks ADD COLUMN parent_id TEXT REFERENCES tasks(id) ON DELETE CASCADE;
+
+CREATE INDEX ix_projects_parent ON projects(parent_id);
+CREATE INDEX ix_tasks_parent ON tasks(parent_id);
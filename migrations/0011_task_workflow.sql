@@ -0,0 +1,7 @@
+-- Task workflow: status, priority and due date
+ALTER TABLE tasks ADD COLUMN status TEXT NOT NULL DEFAULT 'todo'
+  CHECK (status IN ('todo', 'in-progress', 'blocked', 'done'));
+ALTER TABLE tasks ADD COLUMN priority INTEGER NOT NULL DEFAULT 1;  -- 0 low, 1 normal, 2 high, 3 urgent
+ALTER TABLE tasks ADD COLUMN due_date TEXT;                        -- YYYY-MM-DD
+
+CREATE INDEX ix_tasks_status ON tasks(project_id, status);
@@ -0,0 +1,159 @@
+-- Change log: every row change made by the services layer, grouped into operations.
+-- Services open an operation (audit_ops row) at the start of each write transaction; the
+-- triggers below file the before/after snapshot of each changed row under the latest one.
+-- A migration that adds a column to an audited table must recreate that table's triggers.
+CREATE TABLE audit_ops (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  action      TEXT NOT NULL,                      -- e.g. 'task delete'
+  target      TEXT NOT NULL,                      -- what the action was given, usually an id
+  created_at  TEXT NOT NULL,                      -- RFC3339
+  reverts     INTEGER REFERENCES audit_ops(id),   -- set on undo operations
+  undone_by   INTEGER REFERENCES audit_ops(id)    -- set on operations reverted by undo
+);
+
+CREATE TABLE audit_changes (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  op_id       INTEGER REFERENCES audit_ops(id) ON DELETE CASCADE,
+  table_name  TEXT NOT NULL,
+  action      TEXT NOT NULL,                      -- insert | update | delete
+  before      TEXT,                               -- JSON object of the row, NULL on insert
+  after       TEXT                                -- JSON object of the row, NULL on delete
+);
+
+CREATE INDEX ix_audit_changes_op ON audit_changes(op_id);
+
+CREATE TRIGGER audit_projects_ai AFTER INSERT ON projects BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'projects', 'insert', NULL,
+            json_object('id', new.id, 'name', new.name, 'description', new.description, 'archived', new.archived, 'created_at', new.created_at, 'budget_seconds', new.budget_seconds, 'budget_amount', new.budget_amount, 'hourly_rate', new.hourly_rate, 'parent_id', new.parent_id));
+END;
+CREATE TRIGGER audit_projects_au AFTER UPDATE ON projects BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'projects', 'update',
+            json_object('id', old.id, 'name', old.name, 'description', old.description, 'archived', old.archived, 'created_at', old.created_at, 'budget_seconds', old.budget_seconds, 'budget_amount', old.budget_amount, 'hourly_rate', old.hourly_rate, 'parent_id', old.parent_id),
+            json_object('id', new.id, 'name', new.name, 'description', new.description, 'archived', new.archived, 'created_at', new.created_at, 'budget_seconds', new.budget_seconds, 'budget_amount', new.budget_amount, 'hourly_rate', new.hourly_rate, 'parent_id', new.parent_id));
+END;
+CREATE TRIGGER audit_projects_ad AFTER DELETE ON projects BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'projects', 'delete',
+            json_object('id', old.id, 'name', old.name, 'description', old.description, 'archived', old.archived, 'created_at', old.created_at, 'budget_seconds', old.budget_seconds, 'budget_amount', old.budget_amount, 'hourly_rate', old.hourly_rate, 'parent_id', old.parent_id), NULL);
+END;
+
+CREATE TRIGGER audit_tasks_ai AFTER INSERT ON tasks BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'tasks', 'insert', NULL,
+            json_object('id', new.id, 'project_id', new.project_id, 'name', new.name, 'description', new.description, 'archived', new.archived, 'created_at', new.created_at, 'estimate_seconds', new.estimate_seconds, 'parent_id', new.parent_id, 'status', new.status, 'priority', new.priority, 'due_date', new.due_date));
+END;
+CREATE TRIGGER audit_tasks_au AFTER UPDATE ON tasks BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'tasks', 'update',
+            json_object('id', old.id, 'project_id', old.project_id, 'name', old.name, 'description', old.description, 'archived', old.archived, 'created_at', old.created_at, 'estimate_seconds', old.estimate_seconds, 'parent_id', old.parent_id, 'status', old.status, 'priority', old.priority, 'due_date', old.due_date),
+            json_object('id', new.id, 'project_id', new.project_id, 'name', new.name, 'description', new.description, 'archived', new.archived, 'created_at', new.created_at, 'estimate_seconds', new.estimate_seconds, 'parent_id', new.parent_id, 'status', new.status, 'priority', new.priority, 'due_date', new.due_date));
+END;
+CREATE TRIGGER audit_tasks_ad AFTER DELETE ON tasks BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'tasks', 'delete',
+            json_object('id', old.id, 'project_id', old.project_id, 'name', old.name, 'description', old.description, 'archived', old.archived, 'created_at', old.created_at, 'estimate_seconds', old.estimate_seconds, 'parent_id', old.parent_id, 'status', old.status, 'priority', old.priority, 'due_date', old.due_date), NULL);
+END;
+
+CREATE TRIGGER audit_time_entries_ai AFTER INSERT ON time_entries BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'time_entries', 'insert', NULL,
+            json_object('id', new.id, 'task_id', new.task_id, 'start_time', new.start_time, 'end_time', new.end_time, 'duration_seconds', new.duration_seconds, 'local_date', new.local_date, 'created_at', new.created_at, 'flagged', new.flagged, 'note', new.note, 'source', new.source));
+END;
+CREATE TRIGGER audit_time_entries_au AFTER UPDATE ON time_entries BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'time_entries', 'update',
+            json_object('id', old.id, 'task_id', old.task_id, 'start_time', old.start_time, 'end_time', old.end_time, 'duration_seconds', old.duration_seconds, 'local_date', old.local_date, 'created_at', old.created_at, 'flagged', old.flagged, 'note', old.note, 'source', old.source),
+            json_object('id', new.id, 'task_id', new.task_id, 'start_time', new.start_time, 'end_time', new.end_time, 'duration_seconds', new.duration_seconds, 'local_date', new.local_date, 'created_at', new.created_at, 'flagged', new.flagged, 'note', new.note, 'source', new.source));
+END;
+CREATE TRIGGER audit_time_entries_ad AFTER DELETE ON time_entries BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'time_entries', 'delete',
+            json_object('id', old.id, 'task_id', old.task_id, 'start_time', old.start_time, 'end_time', old.end_time, 'duration_seconds', old.duration_seconds, 'local_date', old.local_date, 'created_at', old.created_at, 'flagged', old.flagged, 'note', old.note, 'source', old.source), NULL);
+END;
+
+CREATE TRIGGER audit_active_timer_ai AFTER INSERT ON active_timer BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'active_timer', 'insert', NULL,
+            json_object('singleton', new.singleton, 'time_entry_id', new.time_entry_id));
+END;
+CREATE TRIGGER audit_active_timer_au AFTER UPDATE ON active_timer BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'active_timer', 'update',
+            json_object('singleton', old.singleton, 'time_entry_id', old.time_entry_id),
+            json_object('singleton', new.singleton, 'time_entry_id', new.time_entry_id));
+END;
+CREATE TRIGGER audit_active_timer_ad AFTER DELETE ON active_timer BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'active_timer', 'delete',
+            json_object('singleton', old.singleton, 'time_entry_id', old.time_entry_id), NULL);
+END;
+
+CREATE TRIGGER audit_goals_ai AFTER INSERT ON goals BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'goals', 'insert', NULL,
+            json_object('weekday', new.weekday, 'target_seconds', new.target_seconds));
+END;
+CREATE TRIGGER audit_goals_au AFTER UPDATE ON goals BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'goals', 'update',
+            json_object('weekday', old.weekday, 'target_seconds', old.target_seconds),
+            json_object('weekday', new.weekday, 'target_seconds', new.target_seconds));
+END;
+CREATE TRIGGER audit_goals_ad AFTER DELETE ON goals BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'goals', 'delete',
+            json_object('weekday', old.weekday, 'target_seconds', old.target_seconds), NULL);
+END;
+
+CREATE TRIGGER audit_git_repos_ai AFTER INSERT ON git_repos BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_repos', 'insert', NULL,
+            json_object('repo_path', new.repo_path, 'project_id', new.project_id));
+END;
+CREATE TRIGGER audit_git_repos_au AFTER UPDATE ON git_repos BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_repos', 'update',
+            json_object('repo_path', old.repo_path, 'project_id', old.project_id),
+            json_object('repo_path', new.repo_path, 'project_id', new.project_id));
+END;
+CREATE TRIGGER audit_git_repos_ad AFTER DELETE ON git_repos BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_repos', 'delete',
+            json_object('repo_path', old.repo_path, 'project_id', old.project_id), NULL);
+END;
+
+CREATE TRIGGER audit_git_branches_ai AFTER INSERT ON git_branches BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_branches', 'insert', NULL,
+            json_object('project_id', new.project_id, 'branch', new.branch, 'task_id', new.task_id));
+END;
+CREATE TRIGGER audit_git_branches_au AFTER UPDATE ON git_branches BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_branches', 'update',
+            json_object('project_id', old.project_id, 'branch', old.branch, 'task_id', old.task_id),
+            json_object('project_id', new.project_id, 'branch', new.branch, 'task_id', new.task_id));
+END;
+CREATE TRIGGER audit_git_branches_ad AFTER DELETE ON git_branches BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'git_branches', 'delete',
+            json_object('project_id', old.project_id, 'branch', old.branch, 'task_id', old.task_id), NULL);
+END;
+
+CREATE TRIGGER audit_heartbeat_rules_ai AFTER INSERT ON heartbeat_rules BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'heartbeat_rules', 'insert', NULL,
+            json_object('path_prefix', new.path_prefix, 'project_id', new.project_id));
+END;
+CREATE TRIGGER audit_heartbeat_rules_au AFTER UPDATE ON heartbeat_rules BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'heartbeat_rules', 'update',
+            json_object('path_prefix', old.path_prefix, 'project_id', old.project_id),
+            json_object('path_prefix', new.path_prefix, 'project_id', new.project_id));
+END;
+CREATE TRIGGER audit_heartbeat_rules_ad AFTER DELETE ON heartbeat_rules BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'heartbeat_rules', 'delete',
+            json_object('path_prefix', old.path_prefix, 'project_id', old.project_id), NULL);
+END;
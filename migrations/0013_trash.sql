@@ -0,0 +1,31 @@
+-- Trash: deleted projects, tasks and entries are moved here instead of being dropped. `rows`
+-- holds every row the delete removed, cascades included, parents first, so a restore can put
+-- them back as they were. `trash purge` drops items for good.
+CREATE TABLE trash (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  kind        TEXT NOT NULL CHECK (kind IN ('project', 'task', 'entry')),
+  item_id     TEXT NOT NULL UNIQUE,               -- id of the deleted project, task or entry
+  name        TEXT NOT NULL,                      -- what `trash list` shows
+  deleted_at  TEXT NOT NULL,                      -- RFC3339
+  rows        TEXT NOT NULL                       -- JSON array of {"table": ..., "row": {...}}
+);
+
+CREATE INDEX ix_trash_deleted_at ON trash(deleted_at);
+
+-- Logged like the other tables so that undo keeps the trash in step with what it restores
+CREATE TRIGGER audit_trash_ai AFTER INSERT ON trash BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'trash', 'insert', NULL,
+            json_object('id', new.id, 'kind', new.kind, 'item_id', new.item_id, 'name', new.name, 'deleted_at', new.deleted_at, 'rows', new.rows));
+END;
+CREATE TRIGGER audit_trash_au AFTER UPDATE ON trash BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'trash', 'update',
+            json_object('id', old.id, 'kind', old.kind, 'item_id', old.item_id, 'name', old.name, 'deleted_at', old.deleted_at, 'rows', old.rows),
+            json_object('id', new.id, 'kind', new.kind, 'item_id', new.item_id, 'name', new.name, 'deleted_at', new.deleted_at, 'rows', new.rows));
+END;
+CREATE TRIGGER audit_trash_ad AFTER DELETE ON trash BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'trash', 'delete',
+            json_object('id', old.id, 'kind', old.kind, 'item_id', old.item_id, 'name', old.name, 'deleted_at', old.deleted_at, 'rows', old.rows), NULL);
+END;
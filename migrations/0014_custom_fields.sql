@@ -0,0 +1,61 @@
+-- Custom fields: typed metadata declared once per database and set on projects, tasks or
+-- entries. A value row points at exactly one item, so deleting (or trashing) the item takes
+-- its values with it.
+CREATE TABLE custom_fields (
+  name        TEXT PRIMARY KEY,
+  target      TEXT NOT NULL CHECK (target IN ('project', 'task', 'entry')),
+  type        TEXT NOT NULL CHECK (type IN ('text', 'number', 'date', 'enum')),
+  options     TEXT,                               -- JSON array of allowed values for enum fields
+  created_at  TEXT NOT NULL
+);
+
+CREATE TABLE custom_values (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  field       TEXT NOT NULL REFERENCES custom_fields(name) ON DELETE CASCADE,
+  project_id  TEXT REFERENCES projects(id) ON DELETE CASCADE,
+  task_id     TEXT REFERENCES tasks(id) ON DELETE CASCADE,
+  entry_id    TEXT REFERENCES time_entries(id) ON DELETE CASCADE,
+  value       TEXT NOT NULL,                      -- numbers as decimal text, dates as YYYY-MM-DD
+  CHECK ((project_id IS NOT NULL) + (task_id IS NOT NULL) + (entry_id IS NOT NULL) = 1)
+);
+
+CREATE UNIQUE INDEX ux_custom_values_project ON custom_values(field, project_id) WHERE project_id IS NOT NULL;
+CREATE UNIQUE INDEX ux_custom_values_task ON custom_values(field, task_id) WHERE task_id IS NOT NULL;
+CREATE UNIQUE INDEX ux_custom_values_entry ON custom_values(field, entry_id) WHERE entry_id IS NOT NULL;
+CREATE INDEX ix_custom_values_project ON custom_values(project_id);
+CREATE INDEX ix_custom_values_task ON custom_values(task_id);
+CREATE INDEX ix_custom_values_entry ON custom_values(entry_id);
+
+CREATE TRIGGER audit_custom_fields_ai AFTER INSERT ON custom_fields BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_fields', 'insert', NULL,
+            json_object('name', new.name, 'target', new.target, 'type', new.type, 'options', new.options, 'created_at', new.created_at));
+END;
+CREATE TRIGGER audit_custom_fields_au AFTER UPDATE ON custom_fields BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_fields', 'update',
+            json_object('name', old.name, 'target', old.target, 'type', old.type, 'options', old.options, 'created_at', old.created_at),
+            json_object('name', new.name, 'target', new.target, 'type', new.type, 'options', new.options, 'created_at', new.created_at));
+END;
+CREATE TRIGGER audit_custom_fields_ad AFTER DELETE ON custom_fields BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_fields', 'delete',
+            json_object('name', old.name, 'target', old.target, 'type', old.type, 'options', old.options, 'created_at', old.created_at), NULL);
+END;
+
+CREATE TRIGGER audit_custom_values_ai AFTER INSERT ON custom_values BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_values', 'insert', NULL,
+            json_object('id', new.id, 'field', new.field, 'project_id', new.project_id, 'task_id', new.task_id, 'entry_id', new.entry_id, 'value', new.value));
+END;
+CREATE TRIGGER audit_custom_values_au AFTER UPDATE ON custom_values BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_values', 'update',
+            json_object('id', old.id, 'field', old.field, 'project_id', old.project_id, 'task_id', old.task_id, 'entry_id', old.entry_id, 'value', old.value),
+            json_object('id', new.id, 'field', new.field, 'project_id', new.project_id, 'task_id', new.task_id, 'entry_id', new.entry_id, 'value', new.value));
+END;
+CREATE TRIGGER audit_custom_values_ad AFTER DELETE ON custom_values BEGIN
+  INSERT INTO audit_changes(op_id, table_name, action, before, after)
+    VALUES ((SELECT MAX(id) FROM audit_ops), 'custom_values', 'delete',
+            json_object('id', old.id, 'field', old.field, 'project_id', old.project_id, 'task_id', old.task_id, 'entry_id', old.entry_id, 'value', old.value), NULL);
+END;
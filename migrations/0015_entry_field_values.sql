@@ -0,0 +1,8 @@
+-- Custom field values that apply to each time entry: its own, its task's and its project's.
+-- Reports filter on this view so the inheritance rule lives in one place.
+CREATE VIEW entry_field_values AS
+SELECT e.id AS entry_id, v.field, v.value
+FROM time_entries e
+JOIN tasks t ON t.id = e.task_id
+JOIN custom_values v
+  ON v.entry_id = e.id OR v.task_id = e.task_id OR v.project_id = t.project_id;
@@ -0,0 +1,3 @@
+-- Operations nobody typed a command for (heartbeats, git hooks, the daemon's idle stop) are
+-- flagged so `undo` steps over them to the user's own last change.
+ALTER TABLE audit_ops ADD COLUMN automatic INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,127 @@
+//! Running-timer output for status bars, one update per call.
+
+use crate::{
+    cli::BarFormat,
+    services::{timer::ActiveTimer, timeutil::*},
+};
+use serde_json::json;
+use time::OffsetDateTime;
+
+const COLOR_RUNNING: &str = "#a3be8c";
+const COLOR_OVERTIME: &str = "#bf616a";
+
+/// Render the running timer (or its absence) in the protocol `format` expects.
+/// A timer past `max_session` seconds is marked as overtime.
+pub fn render(
+    format: &BarFormat,
+    timer: Option<&ActiveTimer>,
+    now: OffsetDateTime,
+    max_session: i64,
+) -> String {
+    let Some(timer) = timer else {
+        return match format {
+            BarFormat::Waybar => json!({
+                "text": "",
+                "tooltip": "no timer running",
+                "class": "stopped",
+                "alt": "stopped",
+            })
+            .to_string(),
+            // An empty update hides the block/module
+            BarFormat::Polybar | BarFormat::I3blocks | BarFormat::Tmux => String::new(),
+        };
+    };
+
+    let start = parse_rfc3339(&timer.start_time).ok();
+    let elapsed = start
+        .map(|start| (now - start).whole_seconds().max(0))
+        .unwrap_or(0);
+    let overtime = max_session > 0 && elapsed > max_session;
+    let full = format!(
+        "{} / {} {}",
+        timer.project_name,
+        timer.task_name,
+        format_duration(elapsed)
+    );
+    let short = format!("{} {}", timer.task_name, format_duration(elapsed));
+    let color = if overtime {
+        COLOR_OVERTIME
+    } else {
+        COLOR_RUNNING
+    };
+
+    match format {
+        BarFormat::Waybar => {
+            let mut tooltip = format!("{} / {}", timer.project_name, timer.task_name);
+            if let Some(start) = start {
+                tooltip.push_str(&format!("\nsince {}", local_time_str(start)));
+            }
+            if let Some(note) = timer.note.as_deref().filter(|n| !n.is_empty()) {
+                tooltip.push('\n');
+                tooltip.push_str(note);
+            }
+            let class = if overtime { "overtime" } else { "running" };
+            json!({ "text": full, "tooltip": tooltip, "class": class, "alt": class }).to_string()
+        }
+        BarFormat::Polybar => format!("%{{F{color}}}{full}%{{F-}}"),
+        // full_text, short_text and color lines
+        BarFormat::I3blocks => format!("{full}\n{short}\n{color}"),
+        // `#` starts a tmux format sequence
+        BarFormat::Tmux => format!("#[fg={color}]{}#[default]", full.replace('#', "##")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn timer() -> ActiveTimer {
+        ActiveTimer {
+            entry_id: "e1".into(),
+            task_id: "t1".into(),
+            task_name: "API".into(),
+            project_name: "Acme".into(),
+            start_time: "2024-01-15T08:00:00Z".into(),
+            note: Some("login bug".into()),
+        }
+    }
+
+    const NOW: OffsetDateTime = datetime!(2024-01-15 9:05 UTC);
+
+    #[test]
+    fn test_waybar() {
+        let out: serde_json::Value =
+            serde_json::from_str(&render(&BarFormat::Waybar, Some(&timer()), NOW, 36000)).unwrap();
+        assert_eq!(out["text"], "Acme / API 1h 05m");
+        assert_eq!(out["tooltip"], "Acme / API\nsince 09:00\nlogin bug");
+        assert_eq!(out["class"], "running");
+
+        let out: serde_json::Value =
+            serde_json::from_str(&render(&BarFormat::Waybar, Some(&timer()), NOW, 3600)).unwrap();
+        assert_eq!(out["class"], "overtime");
+
+        let out: serde_json::Value =
+            serde_json::from_str(&render(&BarFormat::Waybar, None, NOW, 36000)).unwrap();
+        assert_eq!(out["class"], "stopped");
+        assert_eq!(out["text"], "");
+    }
+
+    #[test]
+    fn test_line_formats() {
+        let t = timer();
+        assert_eq!(
+            render(&BarFormat::I3blocks, Some(&t), NOW, 36000),
+            "Acme / API 1h 05m\nAPI 1h 05m\n#a3be8c"
+        );
+        assert_eq!(
+            render(&BarFormat::Tmux, Some(&t), NOW, 36000),
+            "#[fg=#a3be8c]Acme / API 1h 05m#[default]"
+        );
+        assert_eq!(
+            render(&BarFormat::Polybar, Some(&t), NOW, 3600),
+            "%{F#bf616a}Acme / API 1h 05m%{F-}"
+        );
+        assert_eq!(render(&BarFormat::Tmux, None, NOW, 36000), "");
+    }
+}
@@ -1,3 +1,7 @@
+use crate::{
+    config::OutputFormat,
+    models::{FieldTarget, FieldType, Priority, TaskSort, TaskStatus},
+};
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
@@ -5,6 +9,15 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[arg(long)]
     pub db: Option<String>,
+    /// Use the settings of this profile from the configuration file; defaults to RUSTYTIME_PROFILE
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Output of reports and lists; defaults to the `output` setting, else text
+    #[arg(long, global = true, value_enum)]
+    pub output: Option<OutputFormat>,
+    /// Use this workspace instead of the one picked with `workspace use`
+    #[arg(long, global = true)]
+    pub workspace: Option<String>,
     #[command(subcommand)]
     pub cmd: Command,
 }
@@ -21,16 +34,135 @@ pub enum Command {
         #[command(subcommand)]
         cmd: TaskCmd,
     },
+    /// Declare custom fields and set them on projects, tasks or entries
+    Field {
+        #[command(subcommand)]
+        cmd: FieldCmd,
+    },
     // Timer
     Start {
-        task_id: String,
+        /// Task id, or the index of a task listed by `recent`; omit to pick interactively
+        task_id: Option<String>,
+        /// What you are working on; stored as the entry's note
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+        /// Reassign the running entry to this task instead of starting a new one
+        #[arg(long)]
+        switch_from_last: bool,
+    },
+    /// Resume the task of the last stopped entry
+    Continue,
+    /// List the most recently tracked tasks; pass an index to `start`
+    Recent {
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: i64,
+    },
+    /// Append a line to the note of the running entry
+    Note { text: String },
+    /// Search projects, tasks and entry notes; lists the matching entries
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Show the running timer
+    Status {
+        /// Emit the format a status bar expects instead of plain text
+        #[arg(long, value_enum)]
+        bar: Option<BarFormat>,
+        /// Keep re-emitting every SECONDS (default 5)
+        #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "5")]
+        watch: Option<u64>,
+    },
+    /// Compact running-timer segment for shell prompts, read from a state file kept by the timer
+    Prompt {
+        /// Placeholders: {project}, {task}, {elapsed}
+        #[arg(long, default_value = "{task} {elapsed}")]
+        format: String,
+        /// Print the snippet that adds the segment to a shell prompt instead
+        #[arg(long, value_enum)]
+        init: Option<PromptShell>,
+    },
+    /// Discard the running timer without recording an entry
+    Cancel,
+    Stop {
+        /// Stop at an earlier time instead of now (HH:MM, YYYY-MM-DD HH:MM or RFC3339)
+        #[arg(long)]
+        at: Option<String>,
+    },
+    /// Revert the last COUNT changes not yet undone, newest first
+    Undo {
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// List recent changes, newest first
+    History {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: i64,
+        /// Also list the rows each change touched and the fields it changed
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// List, restore or purge deleted projects, tasks and entries
+    Trash {
+        #[command(subcommand)]
+        cmd: TrashCmd,
+    },
+    /// List entries flagged as longer than the maximum session, or resolve one
+    Review {
+        #[command(subcommand)]
+        cmd: Option<ReviewCmd>,
     },
-    Stop,
     // Reports
     Report {
         #[command(subcommand)]
         cmd: ReportCmd,
     },
+    // Goals
+    Goal {
+        #[command(subcommand)]
+        cmd: GoalCmd,
+    },
+    /// Record editor activity; heartbeats close together become one time entry
+    Heartbeat {
+        #[command(subcommand)]
+        cmd: HeartbeatCmd,
+    },
+    /// Track time by git repository and branch
+    Git {
+        #[command(subcommand)]
+        cmd: GitCmd,
+    },
+    /// Show or change settings in the configuration file
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCmd,
+    },
+    /// Keep separate databases, e.g. for personal and employer time, and switch between them
+    Workspace {
+        #[command(subcommand)]
+        cmd: WorkspaceCmd,
+    },
+    /// Interactive terminal dashboard
+    Tui,
+    /// Print a completion script; bash, zsh and fish also complete task and project ids
+    Completions { shell: clap_complete::Shell },
+    /// Completion candidates for the words typed so far (used by the completion scripts)
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        words: Vec<String>,
+    },
+    /// Run the background daemon; timer commands are forwarded to it while it is up
+    Daemon,
+    /// Serve a local JSON API; the OpenAPI document is at /openapi.json
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        bind: String,
+        /// Require `Authorization: Bearer <token>`; defaults to RUSTYTIME_API_TOKEN
+        #[arg(long)]
+        token: Option<String>,
+    },
     // Export
     Export {
         format: ExportFormat,
@@ -49,7 +181,17 @@ pub enum ProjectCmd {
         name: String,
         #[arg(long)]
         desc: Option<String>,
+        #[arg(long)]
+        budget_hours: Option<f64>,
+        #[arg(long)]
+        budget_amount: Option<f64>,
+        #[arg(long)]
+        rate: Option<f64>,
+        /// Create as a sub-project of this project
+        #[arg(long)]
+        parent: Option<String>,
     },
+    /// List projects as a tree of sub-projects
     List,
     Edit {
         id: String,
@@ -57,10 +199,30 @@ pub enum ProjectCmd {
         name: Option<String>,
         #[arg(long)]
         desc: Option<String>,
+        #[arg(long)]
+        budget_hours: Option<f64>,
+        #[arg(long)]
+        budget_amount: Option<f64>,
+        #[arg(long)]
+        rate: Option<f64>,
+        /// Move under this project
+        #[arg(long, conflicts_with = "top_level")]
+        parent: Option<String>,
+        /// Move to the top level
+        #[arg(long)]
+        top_level: bool,
+    },
+    /// Merge duplicate projects into the first one, moving their tasks and time, then delete them
+    Merge {
+        /// Project to keep
+        id: String,
+        #[arg(required = true)]
+        duplicates: Vec<String>,
     },
     Archive {
         id: String,
     },
+    /// Move the project to the trash with its sub-projects, tasks and time entries
     Delete {
         id: String,
     },
@@ -73,9 +235,36 @@ pub enum TaskCmd {
         name: String,
         #[arg(long)]
         desc: Option<String>,
+        /// Estimated effort in hours
+        #[arg(long)]
+        estimate: Option<f64>,
+        /// Create as a subtask of this task (of the same project)
+        #[arg(long)]
+        parent: Option<String>,
+        #[arg(long, value_enum)]
+        priority: Option<Priority>,
+        /// Due date (YYYY-MM-DD)
+        #[arg(long)]
+        due: Option<String>,
     },
+    /// List the tasks of a project as a tree of subtasks
     List {
         project_id: String,
+        /// Only tasks with these statuses (comma separated)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        status: Vec<TaskStatus>,
+        /// Only tasks with these priorities (comma separated)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        priority: Vec<Priority>,
+        /// Only tasks due on or before this date (YYYY-MM-DD)
+        #[arg(long)]
+        due_by: Option<String>,
+        /// Only tasks past their due date that are not done
+        #[arg(long)]
+        overdue: bool,
+        /// Order of tasks among their siblings
+        #[arg(long, value_enum, default_value = "created")]
+        sort: TaskSort,
     },
     Edit {
         id: String,
@@ -83,39 +272,308 @@ pub enum TaskCmd {
         name: Option<String>,
         #[arg(long)]
         desc: Option<String>,
+        /// Estimated effort in hours
+        #[arg(long)]
+        estimate: Option<f64>,
+        /// Make a subtask of this task (of the same project)
+        #[arg(long, conflicts_with = "top_level")]
+        parent: Option<String>,
+        /// Make a top-level task
+        #[arg(long)]
+        top_level: bool,
+        #[arg(long, value_enum)]
+        priority: Option<Priority>,
+        /// Due date (YYYY-MM-DD)
+        #[arg(long, conflicts_with = "no_due")]
+        due: Option<String>,
+        /// Clear the due date
+        #[arg(long)]
+        no_due: bool,
+    },
+    /// Move a task to another workflow status
+    Status {
+        id: String,
+        #[arg(value_enum)]
+        status: TaskStatus,
+    },
+    /// Mark a task done
+    Done {
+        id: String,
+    },
+    /// Move a task and its subtasks to another project
+    Move {
+        id: String,
+        project_id: String,
+    },
+    /// Merge duplicate tasks into the first one, moving their time and subtasks, then delete them
+    Merge {
+        /// Task to keep
+        id: String,
+        #[arg(required = true)]
+        duplicates: Vec<String>,
     },
     Archive {
         id: String,
     },
+    /// Move the task to the trash with its subtasks and time entries
     Delete {
         id: String,
     },
 }
 
+#[derive(Subcommand)]
+pub enum FieldCmd {
+    /// Declare a field
+    Add {
+        name: String,
+        /// What the field is set on
+        #[arg(long = "on", value_enum)]
+        target: FieldTarget,
+        #[arg(long = "type", value_enum, default_value_t = FieldType::Text)]
+        field_type: FieldType,
+        /// Allowed values of an enum field, comma separated
+        #[arg(long, value_delimiter = ',')]
+        options: Vec<String>,
+    },
+    List,
+    /// Drop a field and all its values
+    Remove {
+        name: String,
+    },
+    /// Set a field on the project, task or entry ID
+    Set {
+        name: String,
+        id: String,
+        value: String,
+    },
+    Unset {
+        name: String,
+        id: String,
+    },
+    /// Fields set on a project, task or entry
+    Show {
+        id: String,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum ReportCmd {
     Daily {
         #[arg(long)]
-        date: Option<String>,
-    }, // YYYY-MM-DD
+        date: Option<String>, // YYYY-MM-DD
+        /// Only count entries with this custom field value on them, their task or their project
+        #[arg(long = "field", value_name = "NAME=VALUE")]
+        fields: Vec<String>,
+    },
+    /// Time on a project with subtotals for its tasks and sub-projects, rolled up to parents
     Project {
         project_id: String,
         #[arg(long)]
         from: Option<String>,
         #[arg(long)]
         to: Option<String>,
+        /// Expand this many levels; deeper time is included in the subtotals (default all)
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Only count entries with this custom field value on them, their task or their project
+        #[arg(long = "field", value_name = "NAME=VALUE")]
+        fields: Vec<String>,
     },
+    /// Time on a task with subtotals for its subtasks, and its entries
     Task {
         task_id: String,
         #[arg(long)]
         from: Option<String>,
         #[arg(long)]
         to: Option<String>,
+        /// Expand this many levels; deeper time is included in the subtotals (default all)
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Only count entries with this custom field value on them, their task or their project
+        #[arg(long = "field", value_name = "NAME=VALUE")]
+        fields: Vec<String>,
     },
+    /// Progress against daily goals, with overtime balance and streaks (defaults to this week)
+    Goals {
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Spent vs. remaining budget, for all budgeted projects or one project and its tasks
+    Budget { project_id: Option<String> },
+    /// Daily totals summed over several workspaces, read-only (defaults to all, this week)
+    Workspaces {
+        names: Vec<String>,
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ReviewCmd {
+    List,
+    /// Accept the entry as recorded
+    Keep {
+        id: String,
+    },
+    /// Move the end of the entry back
+    Trim {
+        id: String,
+        /// New end time (HH:MM on the entry's day, YYYY-MM-DD HH:MM or RFC3339)
+        #[arg(long)]
+        end: String,
+    },
+    /// Split the entry in two
+    Split {
+        id: String,
+        /// Split point (HH:MM on the entry's day, YYYY-MM-DD HH:MM or RFC3339)
+        #[arg(long)]
+        at: String,
+    },
+    /// Move the entry to the trash
+    Discard {
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashCmd {
+    List,
+    /// Put a project, task or entry back with everything deleted along with it
+    Restore {
+        id: String,
+    },
+    /// Drop items deleted longer ago than the given age for good
+    Purge {
+        /// Age such as 30d, 2w or 12h
+        #[arg(long, default_value = "30d")]
+        older_than: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WorkspaceCmd {
+    /// Workspaces and their databases; the current one is marked with `*`
+    List,
+    /// Register a workspace and create its database
+    Create {
+        name: String,
+        /// Database file; defaults to workspaces/NAME.db next to the default database
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Switch to a workspace (in the profile's settings with --profile); `default` switches back
+    Use { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCmd {
+    /// Effective settings and where each comes from
+    List,
+    Get {
+        key: String,
+    },
+    /// Write a setting, into the profile's table with --profile
+    Set {
+        key: String,
+        value: String,
+    },
+    Unset {
+        key: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GoalCmd {
+    /// Set the daily target in hours, e.g. `goal set workdays 7.5`
+    Set {
+        /// mon..sun, workdays, weekend or all; comma separated
+        days: String,
+        hours: f64,
+    },
+    Clear {
+        days: String,
+    },
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum HeartbeatCmd {
+    Send {
+        /// The file being edited
+        #[arg(long)]
+        file: String,
+        /// Project name or id; found from the rules when omitted
+        #[arg(long)]
+        project: Option<String>,
+        /// Task name within the project (default `coding`)
+        #[arg(long)]
+        task: Option<String>,
+        /// When the activity happened (Unix seconds, HH:MM, YYYY-MM-DD HH:MM or RFC3339; default now)
+        #[arg(long)]
+        time: Option<String>,
+    },
+    /// Map directories to projects
+    Rule {
+        #[command(subcommand)]
+        cmd: RuleCmd,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RuleCmd {
+    /// Track files under PATH on a project
+    Add {
+        path: String,
+        project_id: String,
+    },
+    List,
+    Remove {
+        path: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GitCmd {
+    /// Map the current repository to a project and install post-checkout/post-commit hooks
+    InstallHooks {
+        /// Project to track the repository on; defaults to one named after the repository
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// List mapped repositories
+    List,
+    /// Run by the installed hooks
+    #[command(hide = true)]
+    Hook {
+        name: String,
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone)]
+pub enum BarFormat {
+    Waybar,
+    Polybar,
+    I3blocks,
+    Tmux,
+}
+
+#[derive(clap::ValueEnum, Clone)]
+pub enum PromptShell {
+    Bash,
+    Zsh,
+    Fish,
+    Starship,
 }
 
 #[derive(clap::ValueEnum, Clone)]
 pub enum ExportFormat {
     Csv,
     Json,
+    Ics,
 }
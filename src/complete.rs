@@ -0,0 +1,166 @@
+//! Shell completion scripts, with task and project ids completed from the database.
+//!
+//! The generated scripts call the hidden `__complete` subcommand with the words typed so far;
+//! it prints `value<TAB>description` lines when the word under the cursor is a task or project id,
+//! and nothing otherwise, in which case the static clap completion takes over.
+
+use crate::{cli::Cli, services};
+use clap::CommandFactory;
+use clap_complete::Shell;
+use sqlx::SqlitePool;
+
+#[derive(Debug, PartialEq)]
+pub enum Slot {
+    Projects,
+    Tasks,
+}
+
+fn takes_value(cmd: &clap::Command, matches: impl Fn(&clap::Arg) -> bool) -> bool {
+    cmd.get_arguments()
+        .any(|a| matches(a) && a.get_action().takes_values())
+}
+
+/// What the last of `words` (everything after the binary name) completes to, if it is an id
+pub fn slot(words: &[String]) -> Option<Slot> {
+    let (_current, before) = words.split_last()?;
+    let root = Cli::command();
+    let mut cmd = &root;
+    let mut path: Vec<&str> = vec![];
+    let mut positional = 0;
+    let mut iter = before.iter();
+    while let Some(word) = iter.next() {
+        if let Some(long) = word.strip_prefix("--") {
+            if !long.contains('=') && takes_value(cmd, |a| a.get_long() == Some(long)) {
+                iter.next();
+            }
+        } else if let Some(short) = word.strip_prefix('-').filter(|s| !s.is_empty()) {
+            let flag = short.chars().next();
+            if short.len() == 1 && takes_value(cmd, |a| a.get_short() == flag) {
+                iter.next();
+            }
+        } else if let Some(sub) = cmd.find_subcommand(word) {
+            path.push(sub.get_name());
+            cmd = sub;
+        } else {
+            positional += 1;
+        }
+    }
+
+    // A positional taking many values takes every word after it
+    let arg = cmd.get_positionals().nth(positional).or_else(|| {
+        cmd.get_positionals()
+            .last()
+            .filter(|a| a.get_num_args().is_some_and(|n| n.max_values() > 1))
+    })?;
+    match (arg.get_id().as_str(), path.first()) {
+        ("task_id", _) | ("id" | "duplicates", Some(&"task")) => Some(Slot::Tasks),
+        ("project_id", _) | ("id" | "duplicates", Some(&"project")) => Some(Slot::Projects),
+        _ => None,
+    }
+}
+
+/// Candidates for a slot as `(value, description)`, leaving out archived projects and tasks
+pub async fn candidates(pool: &SqlitePool, slot: &Slot) -> anyhow::Result<Vec<(String, String)>> {
+    let mut out = vec![];
+    for project in services::project::list(pool).await? {
+        if project.archived {
+            continue;
+        }
+        match slot {
+            Slot::Projects => out.push((project.id, project.name)),
+            Slot::Tasks => {
+                for task in services::task::list(pool, &project.id).await? {
+                    if !task.archived {
+                        out.push((task.id, format!("{} / {}", project.name, task.name)));
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+const BASH_DYNAMIC: &str = r#"
+_rustytime_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" line
+    local -a matches=()
+    while IFS= read -r line; do
+        [[ $line == "$cur"* ]] && matches+=("${line/$'\t'/  -- }")
+    done < <(rustytime __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)
+    if [[ ${#matches[@]} -eq 0 ]]; then
+        _rustytime "$@"
+    elif [[ ${#matches[@]} -eq 1 ]]; then
+        COMPREPLY=("${matches[0]%%  -- *}")
+    else
+        COMPREPLY=("${matches[@]}")
+    fi
+}
+complete -F _rustytime_dynamic -o nosort -o bashdefault -o default rustytime
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+_rustytime_dynamic() {
+    local -a candidates
+    candidates=(${(f)"$(rustytime __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} )); then
+        candidates=("${(@)candidates//:/\\:}")
+        candidates=("${(@)candidates/$'\t'/:}")
+        _describe 'id' candidates
+    else
+        _rustytime "$@"
+    fi
+}
+compdef _rustytime_dynamic rustytime
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+function __rustytime_dynamic
+    set -l words (commandline -opc)
+    rustytime __complete -- $words[2..-1] (commandline -ct) 2>/dev/null
+end
+complete -c rustytime -f -a '(__rustytime_dynamic)'
+"#;
+
+/// The completion script for `shell`: clap's static completion plus dynamic ids for bash, zsh and fish
+pub fn script(shell: Shell) -> String {
+    let mut buf = vec![];
+    clap_complete::generate(shell, &mut Cli::command(), "rustytime", &mut buf);
+    let mut script = String::from_utf8_lossy(&buf).into_owned();
+    script.push_str(match shell {
+        Shell::Bash => BASH_DYNAMIC,
+        Shell::Zsh => ZSH_DYNAMIC,
+        Shell::Fish => FISH_DYNAMIC,
+        _ => "",
+    });
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(s: &str) -> Vec<String> {
+        let mut w: Vec<String> = s.split_whitespace().map(String::from).collect();
+        if s.ends_with(' ') {
+            w.push(String::new());
+        }
+        w
+    }
+
+    #[test]
+    fn test_slot() {
+        assert_eq!(slot(&words("start ")), Some(Slot::Tasks));
+        assert_eq!(slot(&words("start -m hello 01a")), Some(Slot::Tasks));
+        assert_eq!(slot(&words("task add ")), Some(Slot::Projects));
+        assert_eq!(slot(&words("task add 01a ")), None);
+        assert_eq!(slot(&words("task edit --name x ")), Some(Slot::Tasks));
+        assert_eq!(slot(&words("project archive ")), Some(Slot::Projects));
+        assert_eq!(slot(&words("report budget ")), Some(Slot::Projects));
+        assert_eq!(slot(&words("task move 01a ")), Some(Slot::Projects));
+        assert_eq!(slot(&words("task merge 01a 01b ")), Some(Slot::Tasks));
+        assert_eq!(slot(&words("project merge 01a ")), Some(Slot::Projects));
+        assert_eq!(slot(&words("review keep ")), None);
+        assert_eq!(slot(&words("sta")), None);
+        assert_eq!(slot(&words("--db x.db start ")), Some(Slot::Tasks));
+    }
+}
@@ -0,0 +1,571 @@
+//! Configuration file with profiles.
+//!
+//! `config.toml` lives in the XDG config directory (`$XDG_CONFIG_HOME/rustytime/`, usually
+//! `~/.config/rustytime/`) unless `RUSTYTIME_CONFIG` points elsewhere. Top-level keys are the
+//! defaults; a `[profiles.NAME]` table overrides them while that profile is selected with
+//! `--profile` or `RUSTYTIME_PROFILE`. Aliases and workspaces from both are merged, the
+//! profile's winning.
+
+use crate::{
+    services::{goals, timeutil},
+    workspace,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use toml_edit::DocumentMut;
+
+/// Plain settings, in the order `config list` shows them
+pub const KEYS: [&str; 6] = [
+    "db",
+    "timezone",
+    "week_start",
+    "rounding",
+    "output",
+    "workspace",
+];
+
+/// Tables of named entries, set as `TABLE.NAME`
+pub const TABLES: [&str; 2] = ["aliases", "workspaces"];
+
+/// Global options that take a value, skipped when looking for the command to expand
+const VALUE_FLAGS: [&str; 4] = ["--db", "--profile", "--output", "--workspace"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// One layer of settings: the top level of the file or a profile
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Settings {
+    /// Database path or `sqlite:` URL
+    pub db: Option<String>,
+    /// IANA time zone for local dates and times
+    pub timezone: Option<String>,
+    /// First day of the week for weekly views
+    pub week_start: Option<String>,
+    /// Round report durations to this many minutes; 0 is off
+    pub rounding: Option<i64>,
+    /// Default for `--output`
+    pub output: Option<OutputFormat>,
+    /// Workspace commands use; see [`crate::workspace`]
+    pub workspace: Option<String>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Workspace databases by name
+    #[serde(default)]
+    pub workspaces: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// These settings with every value `over` sets taking precedence
+    fn overlay(&self, over: &Settings) -> Settings {
+        let mut aliases = self.aliases.clone();
+        aliases.extend(over.aliases.clone());
+        let mut workspaces = self.workspaces.clone();
+        workspaces.extend(over.workspaces.clone());
+        Settings {
+            db: over.db.clone().or_else(|| self.db.clone()),
+            timezone: over.timezone.clone().or_else(|| self.timezone.clone()),
+            week_start: over.week_start.clone().or_else(|| self.week_start.clone()),
+            rounding: over.rounding.or(self.rounding),
+            output: over.output.or(self.output),
+            workspace: over.workspace.clone().or_else(|| self.workspace.clone()),
+            aliases,
+            workspaces,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "db" => self.db.clone(),
+            "timezone" => self.timezone.clone(),
+            "week_start" => self.week_start.clone(),
+            "rounding" => self.rounding.map(|r| r.to_string()),
+            "output" => self.output.map(|o| output_name(o).to_string()),
+            "workspace" => self.workspace.clone(),
+            _ => match table_key(key)? {
+                ("aliases", name) => self.aliases.get(name).cloned(),
+                (_, name) => self.workspaces.get(name).cloned(),
+            },
+        }
+    }
+}
+
+/// Split `TABLE.NAME` into its parts when `TABLE` is one of [`TABLES`]
+fn table_key(key: &str) -> Option<(&str, &str)> {
+    key.split_once('.')
+        .filter(|(table, name)| TABLES.contains(table) && !name.is_empty())
+}
+
+fn output_name(output: OutputFormat) -> &'static str {
+    match output {
+        OutputFormat::Text => "text",
+        OutputFormat::Json => "json",
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct File {
+    #[serde(flatten)]
+    base: Settings,
+    #[serde(default)]
+    profiles: BTreeMap<String, Settings>,
+}
+
+/// The configuration in effect
+#[derive(Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    pub profile: Option<String>,
+    file: File,
+    /// The top level with the profile applied
+    pub settings: Settings,
+}
+
+/// Where the configuration file is read from
+pub fn path() -> PathBuf {
+    if let Some(path) = std::env::var_os("RUSTYTIME_CONFIG") {
+        return PathBuf::from(path);
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| PathBuf::from(".config"))
+        .join("rustytime")
+        .join("config.toml")
+}
+
+/// The settings of `text` with `profile` applied; a profile the file lacks adds nothing
+fn parse(text: &str, profile: Option<&str>) -> anyhow::Result<(File, Settings)> {
+    let file: File = toml_edit::de::from_str(text)?;
+    let settings = match profile.and_then(|name| file.profiles.get(name)) {
+        Some(over) => file.base.overlay(over),
+        None => file.base.clone(),
+    };
+    Ok((file, settings))
+}
+
+/// Read the configuration at `path` (a missing file is empty) with `profile` applied. Whether
+/// the profile exists is left to [`Config::check_profile`], so `config set` can create it.
+pub fn load(path: &Path, profile: Option<&str>) -> anyhow::Result<Config> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (file, settings) =
+        parse(&text, profile).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    Ok(Config {
+        path: path.to_path_buf(),
+        profile: profile.map(String::from),
+        file,
+        settings,
+    })
+}
+
+/// Where a setting's value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Profile(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "config"),
+            Source::Profile(name) => write!(f, "profile {name}"),
+        }
+    }
+}
+
+impl Config {
+    /// Fail when the selected profile is not in the file
+    pub fn check_profile(&self) -> anyhow::Result<()> {
+        match &self.profile {
+            Some(name) if !self.file.profiles.contains_key(name) => {
+                let known: Vec<&str> = self.file.profiles.keys().map(String::as_str).collect();
+                anyhow::bail!(
+                    "no profile '{name}' in {} (known: {})",
+                    self.path.display(),
+                    known.join(", ")
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The effective value of `key` and where it is set; `None` when left at the default
+    pub fn get(&self, key: &str) -> (Option<String>, Source) {
+        if let Some(name) = &self.profile
+            && let Some(value) = self.file.profiles.get(name).and_then(|p| p.get(key))
+        {
+            return (Some(value), Source::Profile(name.clone()));
+        }
+        match self.file.base.get(key) {
+            Some(value) => (Some(value), Source::File),
+            None => (None, Source::Default),
+        }
+    }
+
+    /// Apply time zone and week start for this process
+    pub fn apply(&self) -> anyhow::Result<()> {
+        if let Some(tz) = &self.settings.timezone {
+            timeutil::set_timezone(timeutil::find_timezone(tz)?);
+        }
+        if let Some(day) = &self.settings.week_start {
+            timeutil::set_week_start(parse_week_start(day)?);
+        }
+        Ok(())
+    }
+}
+
+fn parse_week_start(s: &str) -> anyhow::Result<time::Weekday> {
+    match goals::parse_days(s)?.as_slice() {
+        [day] => Ok(*day),
+        _ => anyhow::bail!("week_start takes a single weekday, got '{s}'"),
+    }
+}
+
+/// The value to store for `key`, or why it is not acceptable
+fn check(key: &str, value: &str) -> anyhow::Result<toml_edit::Value> {
+    let value = value.trim();
+    match key {
+        "db" if !value.is_empty() => Ok(value.into()),
+        "timezone" => {
+            timeutil::find_timezone(value)?;
+            Ok(value.into())
+        }
+        "week_start" => {
+            let day = parse_week_start(value)?;
+            Ok(day.to_string().to_lowercase().into())
+        }
+        "rounding" => match value.parse::<i64>() {
+            Ok(minutes @ 0..=1440) => Ok(minutes.into()),
+            _ => anyhow::bail!("rounding takes minutes between 0 and 1440, got '{value}'"),
+        },
+        "output" => match value {
+            "text" | "json" => Ok(value.into()),
+            _ => anyhow::bail!("output is text or json, got '{value}'"),
+        },
+        "workspace" => {
+            workspace::check_name(value)?;
+            Ok(value.into())
+        }
+        _ => match table_key(key) {
+            Some(("aliases", _)) if value.is_empty() => anyhow::bail!("an alias needs a command"),
+            Some(("workspaces", name)) => {
+                workspace::check_name(name)?;
+                if name == workspace::DEFAULT {
+                    anyhow::bail!("'{name}' is the database used without a workspace");
+                }
+                if value.is_empty() {
+                    anyhow::bail!("a workspace needs a database path");
+                }
+                Ok(value.into())
+            }
+            Some(_) => Ok(value.into()),
+            None if KEYS.contains(&key) => anyhow::bail!("{key} cannot be empty"),
+            None => anyhow::bail!(
+                "unknown setting '{key}'; settings are {} and aliases.NAME, workspaces.NAME",
+                KEYS.join(", ")
+            ),
+        },
+    }
+}
+
+/// The table holding `key` in the file, under `[profiles.NAME]` when a profile is given
+fn table<'a>(
+    doc: &'a mut DocumentMut,
+    profile: Option<&str>,
+    key: &'a str,
+) -> anyhow::Result<(&'a mut toml_edit::Table, &'a str)> {
+    let mut path: Vec<&str> = vec![];
+    if let Some(profile) = profile {
+        path.extend(["profiles", profile]);
+    }
+    let mut parts: Vec<&str> = key.splitn(2, '.').collect();
+    let leaf = parts.pop().unwrap_or(key);
+    path.extend(parts);
+
+    let mut table = doc.as_table_mut();
+    for part in path {
+        let item = table.entry(part).or_insert_with(|| {
+            let mut t = toml_edit::Table::new();
+            t.set_implicit(true);
+            toml_edit::Item::Table(t)
+        });
+        table = item
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("'{part}' in the configuration file is not a table"))?;
+    }
+    Ok((table, leaf))
+}
+
+fn edit(
+    text: &str,
+    profile: Option<&str>,
+    key: &str,
+    value: Option<&str>,
+) -> anyhow::Result<String> {
+    let mut doc: DocumentMut = text.parse()?;
+    let value = match value {
+        Some(value) => Some(check(key, value)?),
+        None if KEYS.contains(&key) || table_key(key).is_some() => None,
+        None => anyhow::bail!("unknown setting '{key}'"),
+    };
+    let (table, leaf) = table(&mut doc, profile, key)?;
+    match value {
+        Some(value) => {
+            table.insert(leaf, toml_edit::Item::Value(value));
+        }
+        None => {
+            table.remove(leaf);
+        }
+    }
+    Ok(doc.to_string())
+}
+
+/// Write `key = value` to the file at `path`, in the profile's table when one is given. Other
+/// settings, comments and layout are kept.
+pub fn set(path: &Path, profile: Option<&str>, key: &str, value: &str) -> anyhow::Result<()> {
+    write(path, profile, key, Some(value))
+}
+
+/// Remove `key` from the file at `path`, or from the profile's table
+pub fn unset(path: &Path, profile: Option<&str>, key: &str) -> anyhow::Result<()> {
+    write(path, profile, key, None)
+}
+
+fn write(path: &Path, profile: Option<&str>, key: &str, value: Option<&str>) -> anyhow::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let text = edit(&text, profile, key, value)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// The profile named on the command line (`--profile NAME` or `--profile=NAME`), looked up
+/// before parsing since aliases depend on it
+pub fn profile_arg(args: &[String]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--profile" {
+            return args.next().cloned();
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// Replace the command word with its alias, unless it names a built-in command. Alias values
+/// are split on whitespace and are not expanded again.
+pub fn expand_aliases(
+    args: Vec<String>,
+    aliases: &BTreeMap<String, String>,
+    builtins: &[String],
+) -> Vec<String> {
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            i += 2;
+        } else if arg.starts_with('-') {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    let Some(alias) = args
+        .get(i)
+        .filter(|word| !builtins.contains(word))
+        .and_then(|word| aliases.get(word))
+    else {
+        return args;
+    };
+    let mut out = args[..i].to_vec();
+    out.extend(alias.split_whitespace().map(String::from));
+    out.extend_from_slice(&args[i + 1..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+# defaults
+timezone = "Europe/Warsaw"
+rounding = 15
+
+[aliases]
+s = "start"
+d = "report daily"
+
+[profiles.work]
+db = "/data/work.db"
+timezone = "Europe/London"
+
+[profiles.work.aliases]
+d = "report daily --field cost-center=cc-100"
+"#;
+
+    fn strings(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_profile_overrides_top_level() {
+        let (_, base) = parse(FILE, None).unwrap();
+        assert_eq!(base.timezone.as_deref(), Some("Europe/Warsaw"));
+        assert_eq!(base.db, None);
+        assert_eq!(base.rounding, Some(15));
+
+        let (_, work) = parse(FILE, Some("work")).unwrap();
+        assert_eq!(work.timezone.as_deref(), Some("Europe/London"));
+        assert_eq!(work.db.as_deref(), Some("/data/work.db"));
+        assert_eq!(work.rounding, Some(15));
+        assert_eq!(work.aliases["s"], "start");
+        assert_eq!(work.aliases["d"], "report daily --field cost-center=cc-100");
+
+        assert_eq!(parse("", None).unwrap().1, Settings::default());
+    }
+
+    fn config(profile: &str) -> Config {
+        let (file, settings) = parse(FILE, Some(profile)).unwrap();
+        Config {
+            path: PathBuf::new(),
+            profile: Some(profile.into()),
+            file,
+            settings,
+        }
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let home = config("home");
+        assert!(home.check_profile().is_err());
+        assert_eq!(home.settings.timezone.as_deref(), Some("Europe/Warsaw"));
+        assert!(config("work").check_profile().is_ok());
+    }
+
+    #[test]
+    fn test_get_reports_source() {
+        let config = config("work");
+        assert_eq!(
+            config.get("timezone"),
+            (Some("Europe/London".into()), Source::Profile("work".into()))
+        );
+        assert_eq!(config.get("rounding"), (Some("15".into()), Source::File));
+        assert_eq!(config.get("output"), (None, Source::Default));
+        assert_eq!(
+            config.get("aliases.s"),
+            (Some("start".into()), Source::File)
+        );
+    }
+
+    #[test]
+    fn test_edit_keeps_comments_and_validates() {
+        let text = edit(FILE, Some("work"), "rounding", Some("30")).unwrap();
+        assert!(text.contains("# defaults"));
+        let (_, work) = parse(&text, Some("work")).unwrap();
+        assert_eq!(work.rounding, Some(30));
+        assert_eq!(parse(&text, None).unwrap().1.rounding, Some(15));
+
+        let text = edit("", Some("home"), "aliases.x", Some("stop")).unwrap();
+        assert_eq!(parse(&text, Some("home")).unwrap().1.aliases["x"], "stop");
+        let text = edit(&text, None, "week_start", Some("sun")).unwrap();
+        assert_eq!(
+            parse(&text, None).unwrap().1.week_start.as_deref(),
+            Some("sunday")
+        );
+
+        for (key, value) in [
+            ("db", ""),
+            ("timezone", "Mars/Olympus"),
+            ("rounding", "-5"),
+            ("output", "yaml"),
+            ("colour", "red"),
+            ("week_start", "weekend"),
+            ("aliases.", "start"),
+        ] {
+            assert!(
+                edit(FILE, None, key, Some(value)).is_err(),
+                "{key} = {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unset() {
+        let text = edit(FILE, None, "rounding", None).unwrap();
+        assert_eq!(parse(&text, None).unwrap().1.rounding, None);
+        assert!(edit(FILE, None, "colour", None).is_err());
+    }
+
+    #[test]
+    fn test_profile_arg() {
+        assert_eq!(
+            profile_arg(&strings("rt --profile work start")),
+            Some("work".into())
+        );
+        assert_eq!(
+            profile_arg(&strings("rt start --profile=home")),
+            Some("home".into())
+        );
+        assert_eq!(profile_arg(&strings("rt start -- --profile x")), None);
+        assert_eq!(profile_arg(&strings("rt start")), None);
+    }
+
+    #[test]
+    fn test_expand_aliases() {
+        let aliases: BTreeMap<String, String> = [
+            ("d".to_string(), "report daily".to_string()),
+            ("stop".to_string(), "cancel".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let builtins = strings("start stop report");
+
+        assert_eq!(
+            expand_aliases(
+                strings("rt --db x.db d --date 2024-01-01"),
+                &aliases,
+                &builtins
+            ),
+            strings("rt --db x.db report daily --date 2024-01-01")
+        );
+        assert_eq!(
+            expand_aliases(strings("rt stop"), &aliases, &builtins),
+            strings("rt stop")
+        );
+        assert_eq!(
+            expand_aliases(strings("rt start d"), &aliases, &builtins),
+            strings("rt start d")
+        );
+        assert_eq!(
+            expand_aliases(strings("rt"), &aliases, &builtins),
+            strings("rt")
+        );
+    }
+}
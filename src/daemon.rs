@@ -0,0 +1,506 @@
+//! Optional background daemon owning the database.
+//!
+//! It speaks newline-delimited JSON-RPC 2.0 on a Unix socket next to the database file, so the CLI
+//! can forward timer commands without reopening the pool and rerunning migrations. While running it
+//! stops timers left going when the user walks away and sends reminders.
+
+use crate::services::{self, heartbeat::Heartbeat, timer::ActiveTimer, timeutil::*};
+use anyhow::{Context, bail};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+use std::{
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        UnixListener, UnixStream,
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    signal::unix::{SignalKind, signal},
+};
+
+const DEFAULT_IDLE_MINUTES: i64 = 15;
+const DEFAULT_REMIND_MINUTES: i64 = 30;
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The daemon socket: `RUSTYTIME_SOCKET`, or the database path with a `.sock` extension
+pub fn socket_path(db_path: &Path) -> PathBuf {
+    std::env::var_os("RUSTYTIME_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| db_path.with_extension("sock"))
+}
+
+fn minutes_from_env(var: &str, default: i64) -> i64 {
+    std::env::var(var)
+        .ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(default)
+        * 60
+}
+
+// ---- protocol ----
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError {
+            code: -32000,
+            message: e.to_string(),
+        }
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError {
+        code: -32602,
+        message: format!("invalid params: {e}"),
+    })
+}
+
+#[derive(Deserialize)]
+struct StartParams {
+    /// Task id or recent index, as accepted by `start`
+    task: String,
+    note: Option<String>,
+    #[serde(default)]
+    switch: bool,
+}
+
+#[derive(Deserialize)]
+struct StopParams {
+    /// Any format accepted by `stop --at`
+    at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NoteParams {
+    text: String,
+}
+
+/// The task a timer was started on, with any budget warnings
+#[derive(Debug, Deserialize)]
+pub struct Started {
+    pub task_id: String,
+    pub warnings: Vec<String>,
+}
+
+async fn started(pool: &SqlitePool, task_id: String) -> anyhow::Result<Value> {
+    let warnings = services::budget::overruns(pool, &task_id).await?;
+    Ok(json!({ "task_id": task_id, "warnings": warnings }))
+}
+
+async fn dispatch(pool: &SqlitePool, method: &str, p: Value) -> Result<Value, RpcError> {
+    let result = match method {
+        "ping" => json!("pong"),
+        "status" => json!(services::timer::current(pool).await?),
+        "start" => {
+            let p: StartParams = params(p)?;
+            let task_id = services::timer::resolve_task(pool, &p.task).await?;
+            if p.switch {
+                services::timer::switch(pool, &task_id, p.note.as_deref()).await?;
+            } else {
+                services::timer::start(pool, &task_id, p.note.as_deref()).await?;
+            }
+            started(pool, task_id).await?
+        }
+        "continue" => {
+            let task_id = services::timer::resume_last(pool).await?;
+            started(pool, task_id).await?
+        }
+        "stop" => {
+            let p: StopParams = params(p)?;
+            let stopped = match p.at {
+                Some(at) => services::timer::stop_at(pool, &at).await?,
+                None => services::timer::stop(pool, None).await?,
+            };
+            json!(stopped)
+        }
+        "cancel" => json!(services::timer::cancel(pool).await?),
+        "heartbeat" => {
+            let heartbeat: Heartbeat = params(p)?;
+            json!(services::heartbeat::record(pool, &heartbeat).await?)
+        }
+        "note" => {
+            let p: NoteParams = params(p)?;
+            services::timer::note(pool, &p.text).await?;
+            Value::Null
+        }
+        _ => {
+            return Err(RpcError {
+                code: -32601,
+                message: format!("unknown method '{method}'"),
+            });
+        }
+    };
+    Ok(result)
+}
+
+async fn handle_line(pool: &SqlitePool, line: &str) -> Value {
+    let req: Request = match serde_json::from_str(line) {
+        Ok(req) => req,
+        Err(e) => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": format!("parse error: {e}") },
+            });
+        }
+    };
+    match dispatch(pool, &req.method, req.params).await {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": req.id, "result": result }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": req.id,
+            "error": { "code": e.code, "message": e.message },
+        }),
+    }
+}
+
+async fn serve_conn(pool: SqlitePool, stream: UnixStream) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut resp = serde_json::to_vec(&handle_line(&pool, &line).await)?;
+        resp.push(b'\n');
+        write.write_all(&resp).await?;
+    }
+    Ok(())
+}
+
+// ---- client ----
+
+/// A connection to a running daemon
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the daemon listening on `socket`; `None` when there is none
+    pub async fn connect(socket: &Path) -> Option<Client> {
+        let stream = UnixStream::connect(socket).await.ok()?;
+        let (read, write) = stream.into_split();
+        Some(Client {
+            lines: BufReader::new(read).lines(),
+            write,
+            next_id: 1,
+        })
+    }
+
+    pub async fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut req = serde_json::to_vec(
+            &json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+        )?;
+        req.push(b'\n');
+        self.write.write_all(&req).await?;
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .context("daemon closed the connection")?;
+        let mut resp: Value = serde_json::from_str(&line)?;
+        if let Some(message) = resp["error"]["message"].as_str() {
+            bail!("{message}");
+        }
+        Ok(serde_json::from_value(resp["result"].take())?)
+    }
+}
+
+// ---- idle detection and reminders ----
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Stop the running timer when the user went idle
+    StopIdle {
+        at: OffsetDateTime,
+    },
+    Remind(String),
+}
+
+struct Watch {
+    /// Stop the timer after this many idle seconds; 0 disables
+    idle_threshold: i64,
+    /// Remind every this many seconds while no timer runs; 0 disables
+    remind_every: i64,
+    max_session: i64,
+    /// Since when no timer has been running, or the last reminder about it
+    quiet_since: Option<OffsetDateTime>,
+    /// Entry already reminded about for running past the maximum session
+    long_session_warned: Option<String>,
+}
+
+impl Watch {
+    fn from_env() -> Self {
+        Watch {
+            idle_threshold: minutes_from_env("RUSTYTIME_IDLE_MINUTES", DEFAULT_IDLE_MINUTES),
+            remind_every: minutes_from_env("RUSTYTIME_REMIND_MINUTES", DEFAULT_REMIND_MINUTES),
+            max_session: services::timer::max_session_seconds(),
+            quiet_since: None,
+            long_session_warned: None,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        now: OffsetDateTime,
+        timer: Option<&ActiveTimer>,
+        idle_seconds: Option<i64>,
+    ) -> Vec<Action> {
+        let Some(timer) = timer else {
+            self.long_session_warned = None;
+            return match self.quiet_since {
+                Some(since)
+                    if self.remind_every > 0
+                        && (now - since).whole_seconds() >= self.remind_every =>
+                {
+                    self.quiet_since = Some(now);
+                    vec![Action::Remind("no timer running".to_string())]
+                }
+                Some(_) => vec![],
+                None => {
+                    self.quiet_since = Some(now);
+                    vec![]
+                }
+            };
+        };
+        self.quiet_since = None;
+        let Ok(start) = parse_rfc3339(&timer.start_time) else {
+            return vec![];
+        };
+
+        // Only input that stopped after the timer started counts: a timer started remotely
+        // while the user was already away is left alone
+        if let Some(idle) = idle_seconds
+            && self.idle_threshold > 0
+            && idle >= self.idle_threshold
+        {
+            let at = now - time::Duration::seconds(idle);
+            if at > start {
+                return vec![Action::StopIdle { at }];
+            }
+        }
+
+        let elapsed = (now - start).whole_seconds();
+        if self.max_session > 0
+            && elapsed >= self.max_session
+            && self.long_session_warned.as_deref() != Some(timer.entry_id.as_str())
+        {
+            self.long_session_warned = Some(timer.entry_id.clone());
+            return vec![Action::Remind(format!(
+                "{} / {} has been running for {}",
+                timer.project_name,
+                timer.task_name,
+                format_duration(elapsed)
+            ))];
+        }
+        vec![]
+    }
+}
+
+/// Seconds since the last keyboard/mouse input, from `RUSTYTIME_IDLE_COMMAND`
+/// (a shell command printing milliseconds, default `xprintidle`)
+async fn idle_seconds() -> Option<i64> {
+    let cmd = std::env::var("RUSTYTIME_IDLE_COMMAND").unwrap_or_else(|_| "xprintidle".into());
+    let out = tokio::process::Command::new("sh")
+        .args(["-c", &cmd])
+        .output()
+        .await
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let ms: i64 = String::from_utf8_lossy(&out.stdout).trim().parse().ok()?;
+    Some(ms / 1000)
+}
+
+/// Log a message and show it as a desktop notification when `notify-send` is available
+async fn notify(message: &str) {
+    eprintln!("{message}");
+    let _ = tokio::process::Command::new("notify-send")
+        .args(["rustytime", message])
+        .status()
+        .await;
+}
+
+async fn watch_once(pool: &SqlitePool, watch: &mut Watch) -> anyhow::Result<()> {
+    let timer = services::timer::current(pool).await?;
+    let idle = match &timer {
+        Some(_) if watch.idle_threshold > 0 => idle_seconds().await,
+        _ => None,
+    };
+    for action in watch.tick(now_utc(), timer.as_ref(), idle) {
+        match action {
+            Action::StopIdle { at } => {
+                if let Some(stopped) = services::timer::stop_idle(pool, at).await? {
+                    notify(&format!(
+                        "idle since {}; stopped after {}",
+                        local_time_str(at),
+                        format_duration(stopped.duration_seconds)
+                    ))
+                    .await;
+                }
+            }
+            Action::Remind(message) => notify(&message).await,
+        }
+    }
+    Ok(())
+}
+
+async fn watch(pool: SqlitePool) {
+    let mut watch = Watch::from_env();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = watch_once(&pool, &mut watch).await {
+            eprintln!("watch: {e}");
+        }
+    }
+}
+
+/// Bind the socket inside a fresh 0700 directory, restrict it to the owner and only then move
+/// it into place, so no other user can connect in between
+fn bind_private(socket: &Path) -> anyhow::Result<UnixListener> {
+    let staging = socket.with_extension("sock.d");
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = UnixListener::bind(&staged)
+        .map_err(anyhow::Error::from)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, socket)?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_dir_all(&staging);
+    bound
+}
+
+/// Run the daemon in the foreground until interrupted or terminated
+pub async fn run(pool: SqlitePool, socket: &Path) -> anyhow::Result<()> {
+    if UnixStream::connect(socket).await.is_ok() {
+        bail!("a daemon is already listening on {}", socket.display());
+    }
+    // Left behind by a daemon that did not shut down cleanly
+    let _ = std::fs::remove_file(socket);
+    let listener =
+        bind_private(socket).with_context(|| format!("binding socket {}", socket.display()))?;
+    eprintln!("listening on {}", socket.display());
+
+    tokio::spawn(watch(pool.clone()));
+    let mut terminate = signal(SignalKind::terminate())?;
+    let result = loop {
+        tokio::select! {
+            conn = listener.accept() => {
+                let stream = match conn {
+                    Ok((stream, _)) => stream,
+                    Err(e) => break Err(e.into()),
+                };
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_conn(pool, stream).await {
+                        eprintln!("connection: {e}");
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break Ok(()),
+            _ = terminate.recv() => break Ok(()),
+        }
+    };
+    let _ = std::fs::remove_file(socket);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn watch() -> Watch {
+        Watch {
+            idle_threshold: 15 * 60,
+            remind_every: 30 * 60,
+            max_session: 10 * 3600,
+            quiet_since: None,
+            long_session_warned: None,
+        }
+    }
+
+    fn timer(start: &str) -> ActiveTimer {
+        ActiveTimer {
+            entry_id: "e1".into(),
+            task_id: "t1".into(),
+            task_name: "API".into(),
+            project_name: "Acme".into(),
+            start_time: start.into(),
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_idle_stops_at_last_input() {
+        let mut w = watch();
+        let t = timer("2024-01-15T08:00:00Z");
+        let now = datetime!(2024-01-15 10:00 UTC);
+        assert_eq!(w.tick(now, Some(&t), Some(5 * 60)), vec![]);
+        assert_eq!(
+            w.tick(now, Some(&t), Some(20 * 60)),
+            vec![Action::StopIdle {
+                at: datetime!(2024-01-15 9:40 UTC)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_idle_before_start_is_ignored() {
+        let mut w = watch();
+        let t = timer("2024-01-15T09:55:00Z");
+        let now = datetime!(2024-01-15 10:00 UTC);
+        assert_eq!(w.tick(now, Some(&t), Some(20 * 60)), vec![]);
+    }
+
+    #[test]
+    fn test_reminders() {
+        let mut w = watch();
+        let t0 = datetime!(2024-01-15 9:00 UTC);
+        assert_eq!(w.tick(t0, None, None), vec![]);
+        assert_eq!(w.tick(t0 + time::Duration::minutes(20), None, None), vec![]);
+        assert_eq!(
+            w.tick(t0 + time::Duration::minutes(30), None, None),
+            vec![Action::Remind("no timer running".into())]
+        );
+
+        let t = timer("2024-01-15T08:00:00Z");
+        let late = datetime!(2024-01-15 18:30 UTC);
+        assert_eq!(w.tick(late, Some(&t), None).len(), 1);
+        assert_eq!(w.tick(late, Some(&t), None), vec![]);
+    }
+}
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
-use sqlx::SqlitePool;
+use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
 use std::{
     env,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 fn is_sqlite_url(s: &str) -> bool {
@@ -17,25 +18,23 @@ fn url_to_fs_path(url: &str) -> Option<PathBuf> {
     } else if let Some(trimmed) = url.strip_prefix("sqlite://") {
         // sqlite://relative.db -> relative.db
         Some(PathBuf::from(trimmed))
-    } else if let Some(trimmed) = url.strip_prefix("sqlite:") {
-        // Handle sqlite:path format as well
-        Some(PathBuf::from(trimmed))
     } else {
-        None
+        // Handle sqlite:path format as well
+        url.strip_prefix("sqlite:").map(PathBuf::from)
     }
 }
 
 fn ensure_parent_exists(p: &Path) -> Result<()> {
-    if let Some(parent) = p.parent() {
-        if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)
-                .with_context(|| format!("creating DB directory {:?}", parent))?;
-        }
+    if let Some(parent) = p.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("creating DB directory {:?}", parent))?;
     }
     Ok(())
 }
 
-fn default_db_path() -> PathBuf {
+pub fn default_db_path() -> PathBuf {
     // First check if we have a build-time configured path
     if let Some(build_path) = option_env!("RUSTYTIME_BUILD_DB_PATH") {
         return PathBuf::from(build_path);
@@ -48,41 +47,95 @@ fn default_db_path() -> PathBuf {
     base.join("rustytime.db")
 }
 
-pub fn resolve_db_url(cli_db: Option<&str>) -> (String, PathBuf) {
-    // Priority: RUSTYTIME_DATABASE_URL (if sqlite:*), then --db, then build-time configured path, then runtime default path
-    if let Ok(env_url) = env::var("RUSTYTIME_DATABASE_URL") {
-        if is_sqlite_url(&env_url) {
-            let path = url_to_fs_path(&env_url).unwrap_or_else(|| default_db_path());
-            return (env_url, path);
-        }
+fn path_to_url(p: &Path) -> String {
+    if p.is_absolute() {
+        format!("sqlite:///{}", p.display())
+    } else {
+        format!("sqlite:{}", p.display())
+    }
+}
+
+/// Turn a `--db`-style value (a `sqlite:` URL or a path) into `(url, path)`
+pub fn from_value(value: &str) -> (String, PathBuf) {
+    let value = &expand_home(value);
+    if is_sqlite_url(value) {
+        let path = url_to_fs_path(value).unwrap_or_else(default_db_path);
+        (value.to_string(), path)
+    } else {
+        let p = PathBuf::from(value);
+        (path_to_url(&p), p)
+    }
+}
+
+/// A leading `~/` in a configured path means the home directory
+fn expand_home(value: &str) -> String {
+    match (value.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).display().to_string(),
+        _ => value.to_string(),
+    }
+}
+
+fn resolve(
+    env_url: Option<String>,
+    cli_db: Option<&str>,
+    config_db: Option<&str>,
+) -> (String, PathBuf) {
+    // Priority: RUSTYTIME_DATABASE_URL (if sqlite:*), then --db, then the config file, then build-time configured path, then runtime default path
+    if let Some(env_url) = env_url.filter(|u| is_sqlite_url(u)) {
+        let path = url_to_fs_path(&env_url).unwrap_or_else(default_db_path);
+        return (env_url, path);
     }
     if let Some(db_path) = cli_db {
-        if is_sqlite_url(db_path) {
-            let path = url_to_fs_path(db_path).unwrap_or_else(|| default_db_path());
-            return (db_path.to_string(), path);
-        } else {
-            let p = PathBuf::from(db_path);
-            let url = if p.is_absolute() {
-                format!("sqlite:///{}", db_path)
-            } else {
-                format!("sqlite:{}", db_path)
-            };
-            return (url, p);
-        }
+        return from_value(db_path);
+    }
+    if let Some(db_path) = config_db {
+        return from_value(db_path);
     }
     // fallback: build-time configured path or ~/.local/share/rustytime/rustytime.db (on macOS: ~/Library/Application Support/…)
     let p = default_db_path();
-    let url = if p.is_absolute() {
-        format!("sqlite:///{}", p.display())
-    } else {
-        format!("sqlite:{}", p.display())
-    };
-    (url, p)
+    (path_to_url(&p), p)
+}
+
+/// The database to open as `(url, path)`; `config_db` is the `db` setting of the config file
+pub fn resolve_db_url(cli_db: Option<&str>, config_db: Option<&str>) -> (String, PathBuf) {
+    resolve(env::var("RUSTYTIME_DATABASE_URL").ok(), cli_db, config_db)
 }
 
 pub async fn open_db_resolved(db_url: &str, fs_path: &Path) -> Result<SqlitePool> {
     ensure_parent_exists(fs_path)?;
-    let pool = SqlitePool::connect(db_url).await?;
+    let options = SqliteConnectOptions::from_str(db_url)?.create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await?;
     sqlx::migrate!().run(&pool).await?;
     Ok(pool)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_precedence() {
+        let env_url = Some("sqlite:/env.db".to_string());
+        assert_eq!(
+            resolve(env_url, Some("/cli.db"), Some("/config.db")),
+            ("sqlite:/env.db".to_string(), PathBuf::from("/env.db"))
+        );
+        assert_eq!(
+            resolve(
+                Some("postgres://x".into()),
+                Some("/cli.db"),
+                Some("/config.db")
+            ),
+            ("sqlite:////cli.db".to_string(), PathBuf::from("/cli.db"))
+        );
+        assert_eq!(
+            resolve(None, None, Some("work.db")),
+            ("sqlite:work.db".to_string(), PathBuf::from("work.db"))
+        );
+        assert_eq!(
+            resolve(None, None, Some("sqlite:///data/work.db")).1,
+            PathBuf::from("/data/work.db")
+        );
+        assert_eq!(resolve(None, None, None).1, default_db_path());
+    }
+}
@@ -1,88 +1,1104 @@
+mod bar;
 mod cli;
+mod complete;
+mod config;
+mod daemon;
 mod db;
 mod models;
+mod picker;
+mod prompt;
+mod server;
+#[cfg(test)]
+mod testutil;
+mod tui;
+mod workspace;
 mod services {
+    pub mod audit;
+    pub mod budget;
     pub mod export;
+    pub mod fields;
+    pub mod git;
+    pub mod goals;
+    pub mod heartbeat;
     pub mod project;
     pub mod report;
+    pub mod review;
+    pub mod search;
     pub mod task;
     pub mod timer;
     pub mod timeutil;
+    pub mod trash;
+    pub mod tree;
 }
 
-use clap::Parser;
-use cli::{Cli, Command, ExportFormat, ProjectCmd, ReportCmd, TaskCmd};
+use clap::{CommandFactory, Parser};
+use cli::{
+    BarFormat, Cli, Command, ConfigCmd, ExportFormat, FieldCmd, GitCmd, GoalCmd, HeartbeatCmd,
+    ProjectCmd, ReportCmd, ReviewCmd, RuleCmd, TaskCmd, TrashCmd, WorkspaceCmd,
+};
+use config::OutputFormat;
+use models::TaskStatus;
+use serde_json::json;
+use services::{
+    budget::BudgetStatus,
+    heartbeat::Heartbeat,
+    report::{EntryLine, TreeLine},
+    timer::{ActiveTimer, Stopped},
+    timeutil::{
+        format_date, format_duration, local_date, local_date_str, local_time_str, now_utc,
+        parse_age, parse_date, parse_local_datetime, parse_rfc3339, round_seconds, today_local,
+        week_start,
+    },
+};
+use std::{collections::BTreeMap, time::Duration};
+
+fn print_entry(entry: &EntryLine) {
+    let duration = entry
+        .duration_seconds
+        .map(format_duration)
+        .unwrap_or_else(|| "running".to_string());
+    let start = parse_rfc3339(&entry.start_time)
+        .map(local_time_str)
+        .unwrap_or_default();
+    println!(
+        "  {} {start} {} / {} ({duration}) [{}]",
+        entry.local_date, entry.project_name, entry.task_name, entry.id
+    );
+    if let Some(note) = entry.note.as_deref().filter(|n| !n.is_empty()) {
+        for line in note.lines() {
+            println!("    {line}");
+        }
+    }
+}
+
+/// Print a tree report; sub-projects end in `/`
+fn print_tree(lines: &[TreeLine]) {
+    for line in lines {
+        let slash = if line.kind == "project" { "/" } else { "" };
+        let mut out = format!(
+            "{}{}{slash}: {}",
+            "  ".repeat(line.depth),
+            line.name,
+            format_duration(line.total_seconds)
+        );
+        if line.own_seconds > 0 && line.own_seconds != line.total_seconds {
+            out.push_str(&format!(" (own {})", format_duration(line.own_seconds)));
+        }
+        println!("{out} [{}]", line.id);
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Apply the `rounding` setting to the durations of a report
+fn round_report(lines: &mut [TreeLine], entries: &mut [EntryLine], minutes: i64) {
+    for line in lines {
+        line.own_seconds = round_seconds(line.own_seconds, minutes);
+        line.total_seconds = round_seconds(line.total_seconds, minutes);
+    }
+    for entry in entries {
+        entry.duration_seconds = entry.duration_seconds.map(|d| round_seconds(d, minutes));
+    }
+}
+
+/// Names a command line can start with, which aliases cannot take over
+fn builtin_commands() -> Vec<String> {
+    let mut names = vec!["help".to_string()];
+    for cmd in Cli::command().get_subcommands() {
+        names.push(cmd.get_name().to_string());
+        names.extend(cmd.get_all_aliases().map(String::from));
+    }
+    names
+}
+
+fn config_command(config: &config::Config, cmd: ConfigCmd) -> anyhow::Result<()> {
+    let profile = config.profile.as_deref();
+    match cmd {
+        ConfigCmd::List => {
+            println!("# {}", config.path.display());
+            let settings = &config.settings;
+            let aliases = settings.aliases.keys().map(|a| format!("aliases.{a}"));
+            let workspaces = settings
+                .workspaces
+                .keys()
+                .map(|w| format!("workspaces.{w}"));
+            for key in config::KEYS
+                .map(String::from)
+                .into_iter()
+                .chain(aliases)
+                .chain(workspaces)
+            {
+                let (value, source) = config.get(&key);
+                match value {
+                    Some(value) => println!("{key} = {value} ({source})"),
+                    None => println!("{key} unset"),
+                }
+            }
+        }
+        ConfigCmd::Get { key } => match config.get(&key) {
+            (Some(value), _) => println!("{value}"),
+            (None, _) => anyhow::bail!("{key} is not set"),
+        },
+        ConfigCmd::Set { key, value } => {
+            if let Some(alias) = key.strip_prefix("aliases.")
+                && builtin_commands().iter().any(|b| b == alias)
+            {
+                anyhow::bail!("'{alias}' is a built-in command and cannot be an alias");
+            }
+            config::set(&config.path, profile, &key, &value)?;
+        }
+        ConfigCmd::Unset { key } => config::unset(&config.path, profile, &key)?,
+    }
+    Ok(())
+}
+
+fn print_budget(status: &BudgetStatus) {
+    let mut line = format!(
+        "{}: spent {}",
+        status.name,
+        format_duration(status.spent_seconds)
+    );
+    if let (Some(budget), Some(remaining)) = (status.budget_seconds, status.remaining_seconds()) {
+        line.push_str(&format!(
+            " of {}, remaining {}",
+            format_duration(budget),
+            format_duration(remaining)
+        ));
+    }
+    if let (Some(budget), Some(spent), Some(remaining)) = (
+        status.budget_amount,
+        status.spent_amount(),
+        status.remaining_amount(),
+    ) {
+        line.push_str(&format!(
+            ", amount {spent:.2} of {budget:.2}, remaining {remaining:.2}"
+        ));
+    }
+    if let Some(pct) = status.percent_used() {
+        line.push_str(&format!(" ({pct:.1}% used)"));
+    }
+    if status.is_over() {
+        line.push_str(" OVER BUDGET");
+    }
+    println!("{line}");
+}
+
+/// Quote for a POSIX shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn print_started(task_id: &str, switched: bool, warnings: &[String]) {
+    if switched {
+        println!("switched to {task_id}");
+    } else {
+        println!("started {task_id}");
+    }
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+}
+
+fn print_stopped(stopped: Option<Stopped>) {
+    match stopped {
+        Some(stopped) => {
+            println!(
+                "stopped after {}",
+                format_duration(stopped.duration_seconds)
+            );
+            if stopped.flagged {
+                eprintln!(
+                    "warning: entry {} is longer than the maximum session; see `rustytime review`",
+                    stopped.entry_id
+                );
+            }
+        }
+        None => println!("no timer running"),
+    }
+}
+
+fn print_cancelled(cancelled: Option<String>) {
+    match cancelled {
+        Some(id) => println!("cancelled {id}"),
+        None => println!("no timer running"),
+    }
+}
+
+fn print_status(timer: Option<ActiveTimer>) {
+    let Some(timer) = timer else {
+        println!("no timer running");
+        return;
+    };
+    let since = parse_rfc3339(&timer.start_time)
+        .map(local_time_str)
+        .unwrap_or_default();
+    println!(
+        "{} / {} {} (since {since}) [{}]",
+        timer.project_name,
+        timer.task_name,
+        format_duration(timer.elapsed_seconds()),
+        timer.entry_id
+    );
+    if let Some(note) = timer.note.as_deref().filter(|n| !n.is_empty()) {
+        for line in note.lines() {
+            println!("    {line}");
+        }
+    }
+}
+
+fn emit_status(timer: Option<ActiveTimer>, format: Option<&BarFormat>) {
+    match format {
+        Some(format) => println!(
+            "{}",
+            bar::render(
+                format,
+                timer.as_ref(),
+                now_utc(),
+                services::timer::max_session_seconds()
+            )
+        ),
+        None => print_status(timer),
+    }
+}
+
+/// A heartbeat from `heartbeat send`, with the file made absolute
+fn heartbeat(
+    file: &str,
+    project: &Option<String>,
+    task: &Option<String>,
+    time: &Option<String>,
+) -> anyhow::Result<Heartbeat> {
+    Ok(Heartbeat {
+        file: std::path::absolute(file)?.to_string_lossy().into_owned(),
+        project: project.clone(),
+        task: task.clone(),
+        time: time.clone(),
+    })
+}
+
+/// Run a timer command through the daemon. `Ok(false)` when the command needs the database directly.
+async fn forward(client: &mut daemon::Client, cmd: &Command) -> anyhow::Result<bool> {
+    match cmd {
+        Command::Start {
+            task_id: Some(task),
+            message,
+            switch_from_last,
+        } => {
+            let started: daemon::Started = client
+                .call(
+                    "start",
+                    json!({ "task": task, "note": message, "switch": switch_from_last }),
+                )
+                .await?;
+            print_started(&started.task_id, *switch_from_last, &started.warnings);
+        }
+        Command::Continue => {
+            let started: daemon::Started = client.call("continue", json!({})).await?;
+            print_started(&started.task_id, false, &started.warnings);
+        }
+        Command::Status { bar, watch } => loop {
+            emit_status(client.call("status", json!({})).await?, bar.as_ref());
+            let Some(secs) = watch else { break };
+            tokio::time::sleep(Duration::from_secs(*secs)).await;
+        },
+        Command::Note { text } => client.call("note", json!({ "text": text })).await?,
+        Command::Cancel => print_cancelled(client.call("cancel", json!({})).await?),
+        Command::Stop { at } => {
+            print_stopped(client.call("stop", json!({ "at": at })).await?);
+        }
+        Command::Heartbeat {
+            cmd:
+                HeartbeatCmd::Send {
+                    file,
+                    project,
+                    task,
+                    time,
+                },
+        } => {
+            let heartbeat = heartbeat(file, project, task, time)?;
+            client.call::<String>("heartbeat", json!(heartbeat)).await?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+async fn workspace_command(
+    config: &config::Config,
+    current: &str,
+    cmd: WorkspaceCmd,
+) -> anyhow::Result<()> {
+    match cmd {
+        WorkspaceCmd::List => {
+            for (name, db) in workspace::list(&config.settings) {
+                let marker = if name == current { "*" } else { " " };
+                println!("{marker} {name} {db}");
+            }
+        }
+        WorkspaceCmd::Create { name, path } => {
+            workspace::check_name(&name)?;
+            if name == workspace::DEFAULT || config.settings.workspaces.contains_key(&name) {
+                anyhow::bail!("workspace {name} already exists");
+            }
+            let path = path.unwrap_or_else(|| workspace::default_path(&name).display().to_string());
+            let (db_url, fs_path) = db::from_value(&path);
+            db::open_db_resolved(&db_url, &fs_path).await?.close().await;
+            config::set(&config.path, None, &format!("workspaces.{name}"), &path)?;
+            println!("created {name} at {}", fs_path.display());
+        }
+        WorkspaceCmd::Use { name } => {
+            let profile = config.profile.as_deref();
+            if name == workspace::DEFAULT && profile.is_none() {
+                config::unset(&config.path, None, "workspace")?;
+            } else if name == workspace::DEFAULT {
+                // Spelled out so the profile overrides a top-level `workspace`
+                config::set(&config.path, profile, "workspace", workspace::DEFAULT)?;
+            } else {
+                workspace::db(&config.settings, &name)?;
+                config::set(&config.path, profile, "workspace", &name)?;
+            }
+            println!("using {name}");
+        }
+    }
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
-    let cli = Cli::parse();
-    let (db_url, fs_path) = db::resolve_db_url(cli.db.as_deref());
+    let args: Vec<String> = std::env::args().collect();
+    let profile = config::profile_arg(&args)
+        .or_else(|| std::env::var("RUSTYTIME_PROFILE").ok())
+        .filter(|p| !p.is_empty());
+    let config = config::load(&config::path(), profile.as_deref())?;
+    let cli = Cli::parse_from(config::expand_aliases(
+        args,
+        &config.settings.aliases,
+        &builtin_commands(),
+    ));
+    if let Command::Config { cmd } = cli.cmd {
+        return config_command(&config, cmd);
+    }
+    config.check_profile()?;
+    config.apply()?;
+    let output = cli
+        .output
+        .or(config.settings.output)
+        .unwrap_or(OutputFormat::Text);
+    let rounding = config.settings.rounding.unwrap_or(0);
+    let current = workspace::current(cli.workspace.as_deref(), &config.settings);
+    if let Command::Workspace { cmd } = cli.cmd {
+        return workspace_command(&config, current, cmd).await;
+    }
+    let workspace_db = workspace::db(&config.settings, current)?;
+    let (db_url, fs_path) = db::resolve_db_url(
+        cli.db.as_deref(),
+        workspace_db.as_deref().or(config.settings.db.as_deref()),
+    );
+    if let Command::Completions { shell } = cli.cmd {
+        print!("{}", complete::script(shell));
+        return Ok(());
+    }
+    if let Command::Prompt { format, init } = &cli.cmd {
+        if let Some(shell) = init {
+            print!("{}", prompt::init(shell));
+            return Ok(());
+        }
+        if let Some(timer) = services::timer::read_state(&fs_path) {
+            println!("{}", prompt::render(format, timer.as_ref(), now_utc()));
+            return Ok(());
+        }
+    }
+    let socket = daemon::socket_path(&fs_path);
+    if let Some(mut client) = daemon::Client::connect(&socket).await
+        && forward(&mut client, &cli.cmd).await?
+    {
+        return Ok(());
+    }
     let pool = db::open_db_resolved(&db_url, &fs_path).await?;
 
     match cli.cmd {
         Command::Project { cmd } => match cmd {
-            ProjectCmd::Add { name, desc } => {
-                let id = services::project::add(&pool, &name, desc.as_deref()).await?;
-                println!("{id}");
+            ProjectCmd::Add {
+                name,
+                desc,
+                budget_hours,
+                budget_amount,
+                rate,
+                parent,
+            } => {
+                let project = services::project::NewProject {
+                    name,
+                    description: desc,
+                    parent_id: parent,
+                    budget_hours,
+                    budget_amount,
+                    hourly_rate: rate,
+                };
+                println!("{}", services::project::create(&pool, &project).await?);
             }
             ProjectCmd::List => {
                 let projects = services::project::list(&pool).await?;
-                for project in projects {
-                    println!("Projects: ");
+                if output == OutputFormat::Json {
+                    return print_json(&projects);
+                }
+                println!("Projects: ");
+                for (depth, project) in
+                    services::tree::walk(&projects, |p| &p.id, |p| p.parent_id.as_deref())
+                {
                     println!(
-                        "id: {}, name: {}, description: {}",
+                        "{}id: {}, name: {}, description: {}",
+                        "  ".repeat(depth),
                         project.id,
                         project.name,
-                        project.description.unwrap()
+                        project.description.as_deref().unwrap_or_default()
                     );
                 }
             }
-            ProjectCmd::Edit { id, name, desc } => { /* update */ }
+            ProjectCmd::Edit {
+                id,
+                name,
+                desc,
+                budget_hours,
+                budget_amount,
+                rate,
+                parent,
+                top_level,
+            } => {
+                services::project::edit(&pool, &id, name.as_deref(), desc.as_deref()).await?;
+                if parent.is_some() || top_level {
+                    services::project::set_parent(&pool, &id, parent.as_deref()).await?;
+                }
+                services::project::set_budget(&pool, &id, budget_hours, budget_amount, rate)
+                    .await?;
+            }
+            ProjectCmd::Merge { id, duplicates } => {
+                let moved = services::project::merge(&pool, &id, &duplicates).await?;
+                println!(
+                    "merged {} project(s), {moved} entries moved",
+                    duplicates.len()
+                );
+            }
             ProjectCmd::Archive { id } => services::project::archive(&pool, &id).await?,
             ProjectCmd::Delete { id } => services::project::delete(&pool, &id).await?,
         },
+        Command::Field { cmd } => match cmd {
+            FieldCmd::Add {
+                name,
+                target,
+                field_type,
+                options,
+            } => services::fields::add(&pool, &name, target, field_type, &options).await?,
+            FieldCmd::List => {
+                for field in services::fields::list(&pool).await? {
+                    let options = if field.options.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", field.options.join(", "))
+                    };
+                    println!(
+                        "{} ({} on {}){options}",
+                        field.name,
+                        field.field_type.as_str(),
+                        field.target.as_str()
+                    );
+                }
+            }
+            FieldCmd::Remove { name } => services::fields::remove(&pool, &name).await?,
+            FieldCmd::Set { name, id, value } => {
+                services::fields::set(&pool, &name, &id, &value).await?
+            }
+            FieldCmd::Unset { name, id } => services::fields::unset(&pool, &name, &id).await?,
+            FieldCmd::Show { id } => {
+                for (name, value) in services::fields::values(&pool, &id).await? {
+                    println!("{name}: {value}");
+                }
+            }
+        },
         Command::Task { cmd } => match cmd {
             TaskCmd::Add {
                 project_id,
                 name,
                 desc,
-            } => {}
-            TaskCmd::List { project_id } => {
-                let tasks = services::task::list(&pool, &project_id).await?;
+                estimate,
+                parent,
+                priority,
+                due,
+            } => {
+                let task = services::task::NewTask {
+                    name,
+                    description: desc,
+                    parent_id: parent,
+                    estimate_hours: estimate,
+                    priority,
+                    due_date: due,
+                };
+                println!(
+                    "{}",
+                    services::task::create(&pool, &project_id, &task).await?
+                );
+            }
+            TaskCmd::List {
+                project_id,
+                status,
+                priority,
+                due_by,
+                overdue,
+                sort,
+            } => {
+                let filter = services::task::Filter {
+                    statuses: status,
+                    priorities: priority,
+                    due_by: due_by
+                        .as_deref()
+                        .map(parse_date)
+                        .transpose()?
+                        .map(format_date),
+                    overdue,
+                };
+                let today = format_date(today_local());
+                let mut tasks: Vec<_> = services::task::list(&pool, &project_id)
+                    .await?
+                    .into_iter()
+                    .filter(|t| filter.matches(t, &today))
+                    .collect();
+                services::task::sort(&mut tasks, sort);
+                if output == OutputFormat::Json {
+                    return print_json(&tasks);
+                }
                 println!("Tasks for Project: {}", project_id);
 
-                for task in tasks {
-                    println!(
-                        "id: {}, name: {}, description: {}, created_at: {}",
+                for (depth, task) in
+                    services::tree::walk(&tasks, |t| &t.id, |t| t.parent_id.as_deref())
+                {
+                    let mut line = format!(
+                        "{}id: {}, name: {}, description: {}, created_at: {}, status: {}, priority: {}",
+                        "  ".repeat(depth),
                         task.id,
                         task.name,
-                        task.description.unwrap(),
-                        task.created_at
-                    )
+                        task.description.as_deref().unwrap_or_default(),
+                        task.created_at,
+                        task.status.as_str(),
+                        task.priority.as_str()
+                    );
+                    if let Some(due) = &task.due_date {
+                        line.push_str(&format!(", due: {due}"));
+                    }
+                    if services::task::is_overdue(task, &today) {
+                        line.push_str(" OVERDUE");
+                    }
+                    println!("{line}");
+                }
+            }
+            TaskCmd::Edit {
+                id,
+                name,
+                desc,
+                estimate,
+                parent,
+                top_level,
+                priority,
+                due,
+                no_due,
+            } => {
+                services::task::edit(&pool, &id, name.as_deref(), desc.as_deref()).await?;
+                if parent.is_some() || top_level {
+                    services::task::set_parent(&pool, &id, parent.as_deref()).await?;
+                }
+                if let Some(priority) = priority {
+                    services::task::set_priority(&pool, &id, priority).await?;
+                }
+                if due.is_some() || no_due {
+                    services::task::set_due(&pool, &id, due.as_deref()).await?;
+                }
+                if let Some(hours) = estimate {
+                    services::task::set_estimate(&pool, &id, hours).await?;
+                }
+            }
+            TaskCmd::Status { id, status } => {
+                services::task::set_status(&pool, &id, status).await?
+            }
+            TaskCmd::Done { id } => {
+                services::task::set_status(&pool, &id, TaskStatus::Done).await?
+            }
+            TaskCmd::Move { id, project_id } => {
+                services::task::move_to(&pool, &id, &project_id).await?
+            }
+            TaskCmd::Merge { id, duplicates } => {
+                let moved = services::task::merge(&pool, &id, &duplicates).await?;
+                println!("merged {} task(s), {moved} entries moved", duplicates.len());
+            }
+            TaskCmd::Archive { id } => services::task::archive(&pool, &id).await?,
+            TaskCmd::Delete { id } => services::task::delete(&pool, &id).await?,
+        },
+        Command::Start {
+            task_id,
+            message,
+            switch_from_last,
+        } => {
+            let task_id = match task_id {
+                Some(reference) => services::timer::resolve_task(&pool, &reference).await?,
+                None if picker::is_interactive() => {
+                    let tasks = services::task::pickable(&pool).await?;
+                    match picker::pick_task(&tasks)? {
+                        Some(task_id) => task_id,
+                        None => return Ok(()),
+                    }
                 }
+                None => anyhow::bail!(
+                    "no task given; pass a task id or recent index (the picker needs a terminal)"
+                ),
+            };
+            if switch_from_last {
+                services::timer::switch(&pool, &task_id, message.as_deref()).await?;
+            } else {
+                services::timer::start(&pool, &task_id, message.as_deref()).await?;
             }
-            TaskCmd::Edit { id, name, desc } => {}
-            TaskCmd::Archive { id } => {}
-            TaskCmd::Delete { id } => {}
+            let warnings = services::budget::overruns(&pool, &task_id).await?;
+            print_started(&task_id, switch_from_last, &warnings);
+        }
+        Command::Continue => {
+            let task_id = services::timer::resume_last(&pool).await?;
+            let warnings = services::budget::overruns(&pool, &task_id).await?;
+            print_started(&task_id, false, &warnings);
+        }
+        Command::Recent { limit } => {
+            for (i, task) in services::timer::recent(&pool, limit)
+                .await?
+                .iter()
+                .enumerate()
+            {
+                let last = parse_rfc3339(&task.last_start)
+                    .map(|t| format!("{} {}", local_date_str(t), local_time_str(t)))
+                    .unwrap_or_default();
+                println!(
+                    "{:>2}. {} / {} (last {last}) [{}]",
+                    i + 1,
+                    task.project_name,
+                    task.task_name,
+                    task.task_id
+                );
+            }
+        }
+        Command::Note { text } => services::timer::note(&pool, &text).await?,
+        Command::Search { query, limit } => {
+            for entry in services::search::entries(&pool, &query, limit).await? {
+                print_entry(&entry);
+            }
+        }
+        Command::Status { bar, watch } => loop {
+            emit_status(services::timer::current(&pool).await?, bar.as_ref());
+            let Some(secs) = watch else { break };
+            tokio::time::sleep(Duration::from_secs(secs)).await;
         },
-        Command::Start { task_id } => {
-            services::timer::start(&pool, &task_id).await?;
-            println!("started {task_id}");
+        Command::Prompt { format, .. } => {
+            // No state file yet (or it could not be read): answer from the database and recreate it
+            services::timer::write_state(&pool).await?;
+            let timer = services::timer::current(&pool).await?;
+            println!("{}", prompt::render(&format, timer.as_ref(), now_utc()));
         }
-        Command::Stop => {
-            services::timer::stop(&pool).await?;
-            println!("stopped");
+        Command::Cancel => print_cancelled(services::timer::cancel(&pool).await?),
+        Command::Stop { at } => {
+            let stopped = match at {
+                Some(at) => services::timer::stop_at(&pool, &at).await?,
+                None => services::timer::stop(&pool, None).await?,
+            };
+            print_stopped(stopped);
+        }
+        Command::Undo { count } => {
+            let reverted = services::audit::undo(&pool, count).await?;
+            if reverted.is_empty() {
+                println!("nothing to undo");
+            }
+            for op in reverted {
+                println!(
+                    "undid #{} {} {} ({} changes)",
+                    op.id, op.action, op.target, op.changes
+                );
+            }
+        }
+        Command::History { limit, verbose } => {
+            for op in services::audit::history(&pool, limit).await? {
+                let state = match (op.reverts, op.undone_by) {
+                    (_, Some(by)) => format!(" [undone by #{by}]"),
+                    (Some(of), None) => format!(" [reverts #{of}]"),
+                    (None, None) => String::new(),
+                };
+                let state = if op.automatic {
+                    format!(" [automatic]{state}")
+                } else {
+                    state
+                };
+                println!(
+                    "#{} {} {} ({} changes){state}",
+                    op.id,
+                    op.created_at,
+                    op.label(),
+                    op.changes
+                );
+                if verbose {
+                    for change in services::audit::changes(&pool, op.id).await? {
+                        println!("  {} {} {}", change.action, change.table_name, change.key());
+                        for (column, old, new) in change.diff() {
+                            println!(
+                                "    {column}: {} -> {}",
+                                services::audit::display(&old),
+                                services::audit::display(&new)
+                            );
+                        }
+                    }
+                }
+            }
         }
+        Command::Trash { cmd } => match cmd {
+            TrashCmd::List => {
+                for item in services::trash::list(&pool).await? {
+                    println!(
+                        "{} {} {}, deleted {} ({} rows)",
+                        item.kind, item.item_id, item.name, item.deleted_at, item.rows
+                    );
+                }
+            }
+            TrashCmd::Restore { id } => {
+                let item = services::trash::restore(&pool, &id).await?;
+                println!("restored {} {} ({} rows)", item.kind, item.name, item.rows);
+            }
+            TrashCmd::Purge { older_than } => {
+                let purged = services::trash::purge(&pool, parse_age(&older_than)?).await?;
+                println!("purged {purged} item(s)");
+            }
+        },
+        Command::Review { cmd } => match cmd.unwrap_or(ReviewCmd::List) {
+            ReviewCmd::List => {
+                for entry in services::review::flagged(&pool).await? {
+                    println!(
+                        "id: {}, {} / {}, {} -> {} ({})",
+                        entry.id,
+                        entry.project_name,
+                        entry.task_name,
+                        entry.start_time,
+                        entry.end_time,
+                        format_duration(entry.duration_seconds)
+                    );
+                }
+            }
+            ReviewCmd::Keep { id } => services::review::keep(&pool, &id).await?,
+            ReviewCmd::Trim { id, end } => {
+                let base = local_date(services::review::start_time(&pool, &id).await?);
+                services::review::trim(&pool, &id, parse_local_datetime(&end, base)?).await?;
+            }
+            ReviewCmd::Split { id, at } => {
+                let base = local_date(services::review::start_time(&pool, &id).await?);
+                let new_id =
+                    services::review::split(&pool, &id, parse_local_datetime(&at, base)?).await?;
+                println!("{new_id}");
+            }
+            ReviewCmd::Discard { id } => services::review::discard(&pool, &id).await?,
+        },
         Command::Report { cmd } => match cmd {
-            ReportCmd::Daily { date } => { /* print (date,total) */ }
+            ReportCmd::Daily { date, fields } => {
+                let fields = services::fields::filter(&pool, &fields).await?;
+                let days =
+                    services::report::daily(&pool, date.as_deref(), fields.as_deref()).await?;
+                let mut entries = match &date {
+                    Some(date) => {
+                        services::report::entries(
+                            &pool,
+                            None,
+                            Some(date),
+                            Some(date),
+                            fields.as_deref(),
+                        )
+                        .await?
+                    }
+                    None => vec![],
+                };
+                round_report(&mut [], &mut entries, rounding);
+                let days: Vec<_> = days
+                    .into_iter()
+                    .map(|(date, total)| (date, round_seconds(total, rounding)))
+                    .collect();
+                if output == OutputFormat::Json {
+                    let days: Vec<_> = days
+                        .iter()
+                        .map(|(date, total)| json!({ "date": date, "total_seconds": total }))
+                        .collect();
+                    print_json(&json!({ "days": days, "entries": entries }))?;
+                } else {
+                    for (date, total) in days {
+                        println!("{date}: {}", format_duration(total));
+                    }
+                    for entry in entries {
+                        print_entry(&entry);
+                    }
+                }
+            }
             ReportCmd::Project {
                 project_id,
                 from,
                 to,
-            } => { /* print secs */ }
-            ReportCmd::Task { task_id, from, to } => { /* print secs */ }
+                depth,
+                fields,
+            } => {
+                let fields = services::fields::filter(&pool, &fields).await?;
+                let mut lines = services::report::project_tree(
+                    &pool,
+                    &project_id,
+                    from.as_deref(),
+                    to.as_deref(),
+                    fields.as_deref(),
+                )
+                .await?;
+                lines.retain(|l| depth.is_none_or(|d| l.depth <= d));
+                round_report(&mut lines, &mut [], rounding);
+                match output {
+                    OutputFormat::Json => print_json(&lines)?,
+                    OutputFormat::Text => print_tree(&lines),
+                }
+            }
+            ReportCmd::Task {
+                task_id,
+                from,
+                to,
+                depth,
+                fields,
+            } => {
+                let fields = services::fields::filter(&pool, &fields).await?;
+                let mut lines = services::report::task_tree(
+                    &pool,
+                    &task_id,
+                    from.as_deref(),
+                    to.as_deref(),
+                    fields.as_deref(),
+                )
+                .await?;
+                lines.retain(|l| depth.is_none_or(|d| l.depth <= d));
+                let mut entries = services::report::entries(
+                    &pool,
+                    Some(&task_id),
+                    from.as_deref(),
+                    to.as_deref(),
+                    fields.as_deref(),
+                )
+                .await?;
+                round_report(&mut lines, &mut entries, rounding);
+                match output {
+                    OutputFormat::Json => {
+                        print_json(&json!({ "tree": lines, "entries": entries }))?
+                    }
+                    OutputFormat::Text => {
+                        print_tree(&lines);
+                        for entry in entries {
+                            print_entry(&entry);
+                        }
+                    }
+                }
+            }
+            ReportCmd::Goals { from, to } => {
+                let to = match to {
+                    Some(to) => parse_date(&to)?,
+                    None => today_local(),
+                };
+                let from = match from {
+                    Some(from) => parse_date(&from)?,
+                    None => week_start(to),
+                };
+                let report = services::goals::report(&pool, from, to).await?;
+                for day in &report.days {
+                    println!(
+                        "{} {}: {} / {} ({}){}",
+                        format_date(day.date),
+                        &day.date.weekday().to_string()[..3],
+                        format_duration(day.tracked_seconds),
+                        format_duration(day.target_seconds),
+                        format_duration(day.balance()),
+                        if day.pending {
+                            " in progress"
+                        } else if day.target_seconds > 0 && day.met() {
+                            " ✓"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                println!("balance: {}", format_duration(report.balance_seconds));
+                println!(
+                    "streak: {} day(s), longest {} day(s)",
+                    report.current_streak, report.longest_streak
+                );
+            }
+            ReportCmd::Budget { project_id } => match project_id {
+                Some(project_id) => {
+                    print_budget(&services::budget::project_status(&pool, &project_id).await?);
+                    for task in services::budget::tasks(&pool, &project_id).await? {
+                        print!("  ");
+                        print_budget(&task);
+                    }
+                }
+                None => {
+                    for project in services::budget::projects(&pool).await? {
+                        print_budget(&project);
+                    }
+                }
+            },
+            ReportCmd::Workspaces { names, from, to } => {
+                let to = match to {
+                    Some(to) => parse_date(&to)?,
+                    None => today_local(),
+                };
+                let from = match from {
+                    Some(from) => parse_date(&from)?,
+                    None => week_start(to),
+                };
+                let all = workspace::list(&config.settings);
+                let selected: Vec<(String, String)> = if names.is_empty() {
+                    // Databases never opened have nothing to report
+                    all.into_iter()
+                        .filter(|(_, db)| db::from_value(db).1.exists())
+                        .collect()
+                } else {
+                    names
+                        .iter()
+                        .map(|name| {
+                            all.iter().find(|(w, _)| w == name).cloned().ok_or_else(|| {
+                                anyhow::anyhow!("no workspace {name}; see `workspace list`")
+                            })
+                        })
+                        .collect::<anyhow::Result<_>>()?
+                };
+                let mut per_workspace = vec![];
+                for (name, db) in selected {
+                    let pool = workspace::open_read_only(&name, &db).await?;
+                    let days =
+                        services::report::daily_range(&pool, &format_date(from), &format_date(to))
+                            .await?;
+                    pool.close().await;
+                    per_workspace.push((name, days));
+                }
+                let mut days = workspace::merge(per_workspace);
+                let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+                for day in &mut days {
+                    for (name, seconds) in &mut day.workspaces {
+                        *seconds = round_seconds(*seconds, rounding);
+                        *totals.entry(name.clone()).or_default() += *seconds;
+                    }
+                    day.total_seconds = day.workspaces.values().sum();
+                }
+                let breakdown = |seconds: &BTreeMap<String, i64>| {
+                    let parts: Vec<_> = seconds
+                        .iter()
+                        .map(|(name, s)| format!("{name} {}", format_duration(*s)))
+                        .collect();
+                    if parts.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({})", parts.join(", "))
+                    }
+                };
+                match output {
+                    OutputFormat::Json => print_json(&json!({ "days": days, "totals": totals }))?,
+                    OutputFormat::Text => {
+                        for day in &days {
+                            println!(
+                                "{}: {}{}",
+                                day.date,
+                                format_duration(day.total_seconds),
+                                breakdown(&day.workspaces)
+                            );
+                        }
+                        println!(
+                            "total: {}{}",
+                            format_duration(totals.values().sum()),
+                            breakdown(&totals)
+                        );
+                    }
+                }
+            }
+        },
+        Command::Goal { cmd } => match cmd {
+            GoalCmd::Set { days, hours } => {
+                services::goals::set(&pool, &services::goals::parse_days(&days)?, hours).await?
+            }
+            GoalCmd::Clear { days } => {
+                services::goals::clear(&pool, &services::goals::parse_days(&days)?).await?
+            }
+            GoalCmd::List => {
+                for (day, target) in services::goals::list(&pool).await? {
+                    println!("{day}: {}", format_duration(target));
+                }
+            }
+        },
+        Command::Completions { .. } | Command::Config { .. } | Command::Workspace { .. } => {
+            unreachable!("handled before opening the database")
+        }
+        Command::Complete { words } => {
+            if let Some(slot) = complete::slot(&words) {
+                for (value, description) in complete::candidates(&pool, &slot).await? {
+                    println!("{value}\t{description}");
+                }
+            }
+        }
+        Command::Heartbeat { cmd } => match cmd {
+            HeartbeatCmd::Send {
+                file,
+                project,
+                task,
+                time,
+            } => {
+                let heartbeat = heartbeat(&file, &project, &task, &time)?;
+                services::heartbeat::record(&pool, &heartbeat).await?;
+            }
+            HeartbeatCmd::Rule { cmd } => match cmd {
+                RuleCmd::Add { path, project_id } => {
+                    let path = std::path::absolute(&path)?;
+                    services::heartbeat::add_rule(&pool, &path.to_string_lossy(), &project_id)
+                        .await?;
+                }
+                RuleCmd::List => {
+                    for rule in services::heartbeat::rules(&pool).await? {
+                        println!("{} -> {}", rule.path_prefix, rule.project_name);
+                    }
+                }
+                RuleCmd::Remove { path } => {
+                    let path = std::path::absolute(&path)?;
+                    services::heartbeat::remove_rule(&pool, &path.to_string_lossy()).await?;
+                }
+            },
+        },
+        Command::Git { cmd } => match cmd {
+            GitCmd::InstallHooks { project } => {
+                let repo = services::git::repo_root(&std::env::current_dir()?)?;
+                let project_id = services::git::map_repo(&pool, &repo, project.as_deref()).await?;
+                let mut command = shell_quote(&std::env::current_exe()?.to_string_lossy());
+                if let Some(db) = &cli.db {
+                    command.push_str(&format!(" --db {}", shell_quote(db)));
+                }
+                for hook in services::git::install_hooks(&repo, &command)? {
+                    println!("installed {}", hook.display());
+                }
+                println!("{} -> project {project_id}", repo.display());
+            }
+            GitCmd::List => {
+                for repo in services::git::repos(&pool).await? {
+                    println!("{} -> {}", repo.repo_path, repo.project_name);
+                }
+            }
+            GitCmd::Hook { name, args } => {
+                let dir = std::env::current_dir()?;
+                if let Some(message) = services::git::run_hook(&pool, &dir, &name, &args).await? {
+                    eprintln!("{message}");
+                }
+            }
         },
+        Command::Tui => tui::run(&pool).await?,
+        Command::Daemon => daemon::run(pool.clone(), &socket).await?,
+        Command::Serve { bind, token } => {
+            let token = token.or_else(|| std::env::var("RUSTYTIME_API_TOKEN").ok());
+            server::serve(pool.clone(), &bind, token).await?;
+        }
         Command::Export {
             format,
             out,
@@ -94,7 +1110,14 @@ async fn main() -> anyhow::Result<()> {
                     services::export::export_json(&pool, &out, from.as_deref(), to.as_deref())
                         .await?
                 }
-                ExportFormat::Csv => { /* write CSV */ }
+                ExportFormat::Csv => {
+                    services::export::export_csv(&pool, &out, from.as_deref(), to.as_deref())
+                        .await?
+                }
+                ExportFormat::Ics => {
+                    services::export::export_ics(&pool, &out, from.as_deref(), to.as_deref())
+                        .await?
+                }
             }
             println!("exported -> {out}");
         }
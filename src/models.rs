@@ -7,6 +7,10 @@ pub struct Project {
     pub description: Option<String>,
     pub archived: bool,
     pub created_at: String,
+    pub budget_seconds: Option<i64>,
+    pub budget_amount: Option<f64>,
+    pub hourly_rate: Option<f64>,
+    pub parent_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,8 +21,94 @@ pub struct Task {
     pub description: Option<String>,
     pub archived: bool,
     pub created_at: String,
+    pub estimate_seconds: Option<i64>,
+    pub parent_id: Option<String>,
+    pub status: TaskStatus,
+    pub priority: Priority,
+    /// YYYY-MM-DD
+    pub due_date: Option<String>,
 }
 
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(rename_all = "kebab-case")]
+pub enum TaskStatus {
+    Todo,
+    InProgress,
+    Blocked,
+    Done,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(i32)]
+pub enum Priority {
+    Low = 0,
+    Normal = 1,
+    High = 2,
+    Urgent = 3,
+}
+
+/// Orderings offered by `task list`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum TaskSort {
+    /// Oldest first
+    Created,
+    /// Todo, in progress, blocked, done
+    Status,
+    /// Most urgent first
+    Priority,
+    /// Earliest due date first, tasks without one last
+    Due,
+}
+
+impl TaskStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaskStatus::Todo => "todo",
+            TaskStatus::InProgress => "in-progress",
+            TaskStatus::Blocked => "blocked",
+            TaskStatus::Done => "done",
+        }
+    }
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TimeEntry {
     pub id: String,
@@ -29,3 +119,60 @@ pub struct TimeEntry {
     pub local_date: String,
     pub created_at: String,
 }
+
+/// A user-defined field, set on one kind of item
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub target: FieldTarget,
+    pub field_type: FieldType,
+    /// Allowed values of an enum field
+    pub options: Vec<String>,
+    pub created_at: String,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum FieldTarget {
+    Project,
+    Task,
+    Entry,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Number,
+    /// YYYY-MM-DD
+    Date,
+    /// One of a fixed list of options
+    Enum,
+}
+
+impl FieldTarget {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldTarget::Project => "project",
+            FieldTarget::Task => "task",
+            FieldTarget::Entry => "entry",
+        }
+    }
+}
+
+impl FieldType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Number => "number",
+            FieldType::Date => "date",
+            FieldType::Enum => "enum",
+        }
+    }
+}
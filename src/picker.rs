@@ -0,0 +1,36 @@
+use crate::services::{task::PickableTask, timeutil::*};
+use dialoguer::{FuzzySelect, theme::ColorfulTheme};
+use std::io::IsTerminal;
+
+/// Whether we can prompt: both stdin and stdout are attached to a terminal
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+fn label(task: &PickableTask) -> String {
+    let usage = match task.last_used.as_deref().map(parse_rfc3339) {
+        Some(Ok(t)) => format!(
+            "last {} {}, {} entries",
+            local_date_str(t),
+            local_time_str(t),
+            task.entries
+        ),
+        _ => "never tracked".to_string(),
+    };
+    format!("{} / {}  ({usage})", task.project_name, task.name)
+}
+
+/// Fuzzy-find a task; `None` when the user aborts with Esc or q
+pub fn pick_task(tasks: &[PickableTask]) -> anyhow::Result<Option<String>> {
+    if tasks.is_empty() {
+        anyhow::bail!("no active tasks to pick from; add one with `rustytime task add`");
+    }
+    let labels: Vec<String> = tasks.iter().map(label).collect();
+    let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Start task")
+        .items(&labels)
+        .default(0)
+        .max_length(15)
+        .interact_opt()?;
+    Ok(choice.map(|i| tasks[i].id.clone()))
+}
@@ -0,0 +1,95 @@
+//! Compact running-timer segment for shell prompts.
+
+use crate::{
+    cli::PromptShell,
+    services::{timer::ActiveTimer, timeutil::parse_rfc3339},
+};
+use time::OffsetDateTime;
+
+/// Elapsed time as `H:MM`
+fn compact_elapsed(secs: i64) -> String {
+    format!("{}:{:02}", secs / 3600, (secs % 3600) / 60)
+}
+
+/// Fill `{project}`, `{task}` and `{elapsed}` in `template`; empty when no timer runs
+pub fn render(template: &str, timer: Option<&ActiveTimer>, now: OffsetDateTime) -> String {
+    let Some(timer) = timer else {
+        return String::new();
+    };
+    let elapsed = parse_rfc3339(&timer.start_time)
+        .map(|start| (now - start).whole_seconds().max(0))
+        .unwrap_or(0);
+    template
+        .replace("{project}", &timer.project_name)
+        .replace("{task}", &timer.task_name)
+        .replace("{elapsed}", &compact_elapsed(elapsed))
+}
+
+/// Snippet wiring `rustytime prompt` into a shell prompt
+pub fn init(shell: &PromptShell) -> &'static str {
+    match shell {
+        PromptShell::Bash => {
+            r#"# eval "$(rustytime prompt --init bash)" in ~/.bashrc
+__rustytime_prompt() {
+    local s
+    s="$(rustytime prompt 2>/dev/null)"
+    [ -n "$s" ] && printf '[%s] ' "$s"
+}
+PS1='$(__rustytime_prompt)'"$PS1"
+"#
+        }
+        PromptShell::Zsh => {
+            r#"# eval "$(rustytime prompt --init zsh)" in ~/.zshrc
+setopt prompt_subst
+__rustytime_prompt() {
+    local s
+    s="$(rustytime prompt 2>/dev/null)"
+    [[ -n $s ]] && print -rn -- "[$s] "
+}
+PROMPT='$(__rustytime_prompt)'"$PROMPT"
+"#
+        }
+        PromptShell::Fish => {
+            r#"# rustytime prompt --init fish | source   in ~/.config/fish/config.fish
+functions -q __rustytime_fish_prompt; or functions -c fish_prompt __rustytime_fish_prompt
+function fish_prompt
+    set -l s (rustytime prompt 2>/dev/null)
+    test -n "$s"; and printf '[%s] ' $s
+    __rustytime_fish_prompt
+end
+"#
+        }
+        PromptShell::Starship => {
+            r#"# add to ~/.config/starship.toml
+[custom.rustytime]
+command = "rustytime prompt"
+when = true
+shell = ["sh"]
+format = "[⏱ $output]($style) "
+style = "green"
+"#
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_render() {
+        let timer = ActiveTimer {
+            entry_id: "e1".into(),
+            task_id: "t1".into(),
+            task_name: "API".into(),
+            project_name: "Acme".into(),
+            start_time: "2024-01-15T08:00:00Z".into(),
+            note: None,
+        };
+        let now = datetime!(2024-01-15 9:05 UTC);
+        assert_eq!(render("{task} {elapsed}", Some(&timer), now), "API 1:05");
+        assert_eq!(render("{project}/{task}", Some(&timer), now), "Acme/API");
+        assert_eq!(render("{task} {elapsed}", None, now), "");
+    }
+}
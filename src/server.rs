@@ -0,0 +1,346 @@
+use crate::services::{self, heartbeat::Heartbeat, timeutil::*};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+const OPENAPI: &str = include_str!("openapi.json");
+
+#[derive(Clone)]
+struct AppState {
+    pool: SqlitePool,
+    token: Option<Arc<str>>,
+}
+
+/// Service errors become `{"error": "..."}` bodies; database failures are 500s
+struct ApiError(StatusCode, String);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Errors of a service rejecting its input, such as an unparsable date or a duplicate name;
+/// other database failures stay 500s
+fn bad_request(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db)) if db.kind() != sqlx::error::ErrorKind::Other => {
+            ApiError(StatusCode::BAD_REQUEST, e.to_string())
+        }
+        Some(_) => e.into(),
+        None => ApiError(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+/// 400 unless every given date is `YYYY-MM-DD`
+fn check_dates(dates: &[Option<&str>]) -> Result<(), ApiError> {
+    for date in dates.iter().flatten() {
+        parse_date(date).map_err(bad_request)?;
+    }
+    Ok(())
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+/// 404 unless project `id` exists
+async fn require_project(pool: &SqlitePool, id: &str) -> Result<(), ApiError> {
+    let found = sqlx::query_scalar!("SELECT name FROM projects WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+    match found {
+        Some(_) => Ok(()),
+        None => Err(ApiError(StatusCode::NOT_FOUND, format!("no project {id}"))),
+    }
+}
+
+/// 404 unless task `id` exists
+async fn require_task(pool: &SqlitePool, id: &str) -> Result<(), ApiError> {
+    let found = sqlx::query_scalar!("SELECT name FROM tasks WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+    match found {
+        Some(_) => Ok(()),
+        None => Err(ApiError(StatusCode::NOT_FOUND, format!("no task {id}"))),
+    }
+}
+
+/// Compare without stopping at the first differing byte, so response times do not reveal how
+/// much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether an `Authorization` header value carries the expected bearer token
+fn authorized(header: Option<&str>, token: &str) -> bool {
+    header
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+async fn require_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if let Some(token) = &state.token {
+        let header = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok());
+        if !authorized(header, token) {
+            return ApiError(
+                StatusCode::UNAUTHORIZED,
+                "missing or invalid bearer token".into(),
+            )
+            .into_response();
+        }
+    }
+    next.run(req).await
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EntriesQuery {
+    task_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DailyQuery {
+    date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StartTimer {
+    task_id: String,
+    note: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct StopTimer {
+    /// Stop at an earlier time, in any format accepted by `stop --at`
+    at: Option<String>,
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+async fn list_projects(State(s): State<AppState>) -> ApiResult {
+    Ok(Json(json!(services::project::list(&s.pool).await?)))
+}
+
+async fn create_project(
+    State(s): State<AppState>,
+    Json(p): Json<services::project::NewProject>,
+) -> ApiResult {
+    let id = services::project::create(&s.pool, &p)
+        .await
+        .map_err(bad_request)?;
+    Ok(Json(json!({ "id": id })))
+}
+
+async fn list_tasks(State(s): State<AppState>, Path(project_id): Path<String>) -> ApiResult {
+    require_project(&s.pool, &project_id).await?;
+    Ok(Json(json!(
+        services::task::list(&s.pool, &project_id).await?
+    )))
+}
+
+async fn create_task(
+    State(s): State<AppState>,
+    Path(project_id): Path<String>,
+    Json(t): Json<services::task::NewTask>,
+) -> ApiResult {
+    require_project(&s.pool, &project_id).await?;
+    let id = services::task::create(&s.pool, &project_id, &t)
+        .await
+        .map_err(bad_request)?;
+    Ok(Json(json!({ "id": id })))
+}
+
+async fn list_entries(State(s): State<AppState>, Query(q): Query<EntriesQuery>) -> ApiResult {
+    check_dates(&[q.from.as_deref(), q.to.as_deref()])?;
+    let entries = services::report::entries(
+        &s.pool,
+        q.task_id.as_deref(),
+        q.from.as_deref(),
+        q.to.as_deref(),
+        None,
+    )
+    .await
+    .map_err(bad_request)?;
+    Ok(Json(json!(entries)))
+}
+
+async fn timer_status(State(s): State<AppState>) -> ApiResult {
+    Ok(Json(json!(services::timer::current(&s.pool).await?)))
+}
+
+async fn timer_start(State(s): State<AppState>, Json(req): Json<StartTimer>) -> ApiResult {
+    require_task(&s.pool, &req.task_id).await?;
+    services::timer::start(&s.pool, &req.task_id, req.note.as_deref())
+        .await
+        .map_err(bad_request)?;
+    let warnings = services::budget::overruns(&s.pool, &req.task_id).await?;
+    Ok(Json(json!({
+        "timer": services::timer::current(&s.pool).await?,
+        "warnings": warnings,
+    })))
+}
+
+async fn timer_stop(State(s): State<AppState>, body: Option<Json<StopTimer>>) -> ApiResult {
+    let Json(req) = body.unwrap_or_default();
+    let stopped = match req.at {
+        Some(at) => services::timer::stop_at(&s.pool, &at).await,
+        None => services::timer::stop(&s.pool, None).await,
+    }
+    .map_err(bad_request)?;
+    Ok(Json(json!(stopped)))
+}
+
+async fn heartbeat(State(s): State<AppState>, Json(hb): Json<Heartbeat>) -> ApiResult {
+    let entry_id = services::heartbeat::record(&s.pool, &hb)
+        .await
+        .map_err(bad_request)?;
+    Ok(Json(json!({ "entry_id": entry_id })))
+}
+
+async fn report_daily(State(s): State<AppState>, Query(q): Query<DailyQuery>) -> ApiResult {
+    check_dates(&[q.date.as_deref()])?;
+    let days: Vec<Value> = services::report::daily(&s.pool, q.date.as_deref(), None)
+        .await
+        .map_err(bad_request)?
+        .into_iter()
+        .map(|(date, total)| json!({ "date": date, "total_seconds": total }))
+        .collect();
+    Ok(Json(json!(days)))
+}
+
+async fn report_project(
+    State(s): State<AppState>,
+    Path(id): Path<String>,
+    Query(r): Query<Range>,
+) -> ApiResult {
+    require_project(&s.pool, &id).await?;
+    check_dates(&[r.from.as_deref(), r.to.as_deref()])?;
+    let total = services::report::project(&s.pool, &id, r.from.as_deref(), r.to.as_deref())
+        .await
+        .map_err(bad_request)?;
+    Ok(Json(json!({ "project_id": id, "total_seconds": total })))
+}
+
+async fn report_task(
+    State(s): State<AppState>,
+    Path(id): Path<String>,
+    Query(r): Query<Range>,
+) -> ApiResult {
+    require_task(&s.pool, &id).await?;
+    check_dates(&[r.from.as_deref(), r.to.as_deref()])?;
+    let total = services::report::task(&s.pool, &id, r.from.as_deref(), r.to.as_deref())
+        .await
+        .map_err(bad_request)?;
+    Ok(Json(json!({ "task_id": id, "total_seconds": total })))
+}
+
+async fn report_budget(State(s): State<AppState>) -> ApiResult {
+    Ok(Json(json!(services::budget::projects(&s.pool).await?)))
+}
+
+fn router(state: AppState) -> Router {
+    let api = Router::new()
+        .route("/projects", get(list_projects).post(create_project))
+        .route("/projects/{id}/tasks", get(list_tasks).post(create_task))
+        .route("/entries", get(list_entries))
+        .route("/timer", get(timer_status))
+        .route("/timer/start", post(timer_start))
+        .route("/timer/stop", post(timer_stop))
+        .route("/heartbeats", post(heartbeat))
+        .route("/reports/daily", get(report_daily))
+        .route("/reports/projects/{id}", get(report_project))
+        .route("/reports/tasks/{id}", get(report_task))
+        .route("/reports/budget", get(report_budget))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .merge(api)
+        .with_state(state)
+}
+
+/// Serve the JSON API until interrupted; without a token only loopback addresses are allowed
+pub async fn serve(pool: SqlitePool, bind: &str, token: Option<String>) -> anyhow::Result<()> {
+    let state = AppState {
+        pool,
+        token: token.map(Arc::from),
+    };
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    let addr = listener.local_addr()?;
+    if state.token.is_none() && !addr.ip().is_loopback() {
+        anyhow::bail!(
+            "refusing to serve on {addr} without a token; pass --token or set RUSTYTIME_API_TOKEN"
+        );
+    }
+    eprintln!("listening on http://{addr}");
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorized() {
+        assert!(authorized(Some("Bearer s3cret"), "s3cret"));
+        assert!(!authorized(Some("Bearer wrong"), "s3cret"));
+        assert!(!authorized(Some("s3cret"), "s3cret"));
+        assert!(!authorized(None, "s3cret"));
+        assert!(!authorized(Some("Bearer s3cre"), "s3cret"));
+        assert!(!authorized(Some("Bearer "), "s3cret"));
+    }
+
+    #[test]
+    fn test_openapi_documents_every_route() {
+        let doc: Value = serde_json::from_str(OPENAPI).unwrap();
+        let paths = doc["paths"].as_object().unwrap();
+        for path in [
+            "/projects",
+            "/projects/{id}/tasks",
+            "/entries",
+            "/timer",
+            "/timer/start",
+            "/timer/stop",
+            "/heartbeats",
+            "/reports/daily",
+            "/reports/projects/{id}",
+            "/reports/tasks/{id}",
+            "/reports/budget",
+        ] {
+            assert!(paths.contains_key(path), "{path} missing from openapi.json");
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Change log and undo.
+//!
+//! Every write transaction of the services layer starts with [`begin`], which records an
+//! operation; triggers (see migration 0012) then log a before/after snapshot of each row the
+//! transaction changes, cascades included. [`undo`] replays those snapshots backwards. Writes
+//! made without a user command go through [`begin_automatic`] and are left alone by [`undo`].
+//! Committing a [`Tx`] also refreshes the timer state file, so the prompt never shows a
+//! running timer, project or task name that a write has since changed.
+
+use crate::services::{self, timeutil::*};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::ops::{Deref, DerefMut};
+
+/// An audited write transaction, opened by [`begin`]
+pub struct Tx {
+    inner: sqlx::Transaction<'static, sqlx::Sqlite>,
+    pool: SqlitePool,
+}
+
+impl Tx {
+    /// Commit, then refresh the state file. The change is durable once the commit succeeds,
+    /// so a state file that can't be refreshed (it is removed then) does not fail the write.
+    pub async fn commit(self) -> anyhow::Result<()> {
+        self.inner.commit().await?;
+        let _ = services::timer::write_state(&self.pool).await;
+        Ok(())
+    }
+}
+
+impl Deref for Tx {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.inner
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.inner
+    }
+}
+
+/// Audited tables and their primary key columns
+const KEYS: [(&str, &[&str]); 11] = [
+    ("projects", &["id"]),
+    ("tasks", &["id"]),
+    ("time_entries", &["id"]),
+    ("active_timer", &["singleton"]),
+    ("goals", &["weekday"]),
+    ("git_repos", &["repo_path"]),
+    ("git_branches", &["project_id", "branch"]),
+    ("heartbeat_rules", &["path_prefix"]),
+    ("trash", &["id"]),
+    ("custom_fields", &["name"]),
+    ("custom_values", &["id"]),
+];
+
+/// A logged operation
+#[derive(Debug, Serialize)]
+pub struct Op {
+    pub id: i64,
+    pub action: String,
+    pub target: String,
+    pub created_at: String,
+    pub reverts: Option<i64>,
+    pub undone_by: Option<i64>,
+    /// Made by a heartbeat, git hook or the daemon rather than a command
+    pub automatic: bool,
+    pub changes: i64,
+}
+
+impl Op {
+    /// The action followed by its target, if any
+    pub fn label(&self) -> String {
+        if self.target.is_empty() {
+            self.action.clone()
+        } else {
+            format!("{} {}", self.action, self.target)
+        }
+    }
+}
+
+/// A row change of an operation
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub table_name: String,
+    pub action: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Change {
+    fn row(&self, json: Option<&str>) -> anyhow::Result<Option<Map<String, Value>>> {
+        json.map(|j| serde_json::from_str(j).map_err(anyhow::Error::from))
+            .transpose()
+    }
+
+    /// The primary key of the changed row, e.g. `id=01a...`
+    pub fn key(&self) -> String {
+        let row = self
+            .row(self.after.as_deref().or(self.before.as_deref()))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        key_columns(&self.table_name)
+            .unwrap_or(&[])
+            .iter()
+            .map(|c| format!("{c}={}", display(row.get(*c).unwrap_or(&Value::Null))))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The columns an update changed, as `(column, before, after)`
+    pub fn diff(&self) -> Vec<(String, Value, Value)> {
+        let (Ok(Some(before)), Ok(Some(after))) = (
+            self.row(self.before.as_deref()),
+            self.row(self.after.as_deref()),
+        ) else {
+            return vec![];
+        };
+        diff(&before, &after)
+    }
+}
+
+fn key_columns(table: &str) -> Option<&'static [&'static str]> {
+    KEYS.iter().find(|(t, _)| *t == table).map(|(_, k)| *k)
+}
+
+/// A JSON value as shown in `history`
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn diff(before: &Map<String, Value>, after: &Map<String, Value>) -> Vec<(String, Value, Value)> {
+    after
+        .iter()
+        .filter_map(|(column, new)| {
+            let old = before.get(column).unwrap_or(&Value::Null);
+            (old != new).then(|| (column.clone(), old.clone(), new.clone()))
+        })
+        .collect()
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// The statement restoring a row change, with its parameters
+fn revert_sql(
+    table: &str,
+    keys: &[&str],
+    before: Option<&Map<String, Value>>,
+    after: Option<&Map<String, Value>>,
+) -> anyhow::Result<(String, Vec<Value>)> {
+    let where_key = |row: &Map<String, Value>| {
+        let clause = keys
+            .iter()
+            .map(|k| format!("{} = ?", quote(k)))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let params = keys
+            .iter()
+            .map(|k| row.get(*k).cloned().unwrap_or(Value::Null))
+            .collect::<Vec<_>>();
+        (clause, params)
+    };
+    match (before, after) {
+        // Inserted: delete it
+        (None, Some(after)) => {
+            let (clause, params) = where_key(after);
+            Ok((
+                format!("DELETE FROM {} WHERE {clause}", quote(table)),
+                params,
+            ))
+        }
+        // Deleted: put it back
+        (Some(before), None) => {
+            let columns = before.keys().map(|c| quote(c)).collect::<Vec<_>>();
+            let marks = vec!["?"; columns.len()].join(", ");
+            Ok((
+                format!(
+                    "INSERT INTO {} ({}) VALUES ({marks})",
+                    quote(table),
+                    columns.join(", ")
+                ),
+                before.values().cloned().collect(),
+            ))
+        }
+        // Updated: restore every column, finding the row by its key after the update
+        (Some(before), Some(after)) => {
+            let sets = before
+                .keys()
+                .map(|c| format!("{} = ?", quote(c)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let (clause, key_params) = where_key(after);
+            let mut params: Vec<Value> = before.values().cloned().collect();
+            params.extend(key_params);
+            Ok((
+                format!("UPDATE {} SET {sets} WHERE {clause}", quote(table)),
+                params,
+            ))
+        }
+        (None, None) => anyhow::bail!("change of {table} has no snapshot"),
+    }
+}
+
+/// Start a write transaction logged as `action` on `target`
+pub async fn begin(pool: &SqlitePool, action: &str, target: &str) -> anyhow::Result<Tx> {
+    open(pool, action, target, false).await
+}
+
+/// Start a write transaction nobody typed a command for, such as a heartbeat. It is logged
+/// like any other, but [`undo`] skips it.
+pub async fn begin_automatic(pool: &SqlitePool, action: &str, target: &str) -> anyhow::Result<Tx> {
+    open(pool, action, target, true).await
+}
+
+async fn open(
+    pool: &SqlitePool,
+    action: &str,
+    target: &str,
+    automatic: bool,
+) -> anyhow::Result<Tx> {
+    let mut inner = pool.begin().await?;
+    sqlx::query("INSERT INTO audit_ops(action, target, created_at, automatic) VALUES(?, ?, ?, ?)")
+        .bind(action)
+        .bind(target)
+        .bind(to_rfc3339(now_utc()))
+        .bind(automatic)
+        .execute(&mut *inner)
+        .await?;
+    Ok(Tx {
+        inner,
+        pool: pool.clone(),
+    })
+}
+
+/// The latest operations, newest first
+pub async fn history(pool: &SqlitePool, limit: i64) -> anyhow::Result<Vec<Op>> {
+    let rows = sqlx::query_as!(
+        Op,
+        r#"SELECT o.id as "id!", o.action, o.target, o.created_at, o.reverts, o.undone_by,
+                  o.automatic as "automatic: bool",
+                  (SELECT COUNT(*) FROM audit_changes c WHERE c.op_id = o.id) as "changes!: i64"
+           FROM audit_ops o
+           ORDER BY o.id DESC
+           LIMIT ?"#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// The row changes of an operation, in the order they happened
+pub async fn changes(pool: &SqlitePool, op_id: i64) -> anyhow::Result<Vec<Change>> {
+    let rows = sqlx::query_as!(
+        Change,
+        "SELECT table_name, action, before, after FROM audit_changes WHERE op_id = ? ORDER BY id",
+        op_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn bind_json<'q>(
+    q: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    value: Value,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    match value {
+        Value::Null => q.bind(None::<String>),
+        Value::Bool(b) => q.bind(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => q.bind(i),
+            None => q.bind(n.as_f64()),
+        },
+        Value::String(s) => q.bind(s),
+        other => q.bind(other.to_string()),
+    }
+}
+
+/// Run a statement built from row snapshots
+async fn execute(tx: &mut Tx, sql: &str, params: Vec<Value>) -> Result<(), sqlx::Error> {
+    let mut q = sqlx::query(sql);
+    for param in params {
+        q = bind_json(q, param);
+    }
+    q.execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// Put a deleted row back from its snapshot
+pub async fn insert_row(tx: &mut Tx, table: &str, row: &Map<String, Value>) -> anyhow::Result<()> {
+    let keys = key_columns(table).ok_or_else(|| anyhow::anyhow!("unknown table {table}"))?;
+    let (sql, params) = revert_sql(table, keys, Some(row), None)?;
+    execute(tx, &sql, params).await?;
+    Ok(())
+}
+
+/// Revert the last `count` operations not yet undone, newest first, skipping automatic ones.
+/// Each revert is itself logged as an `undo` operation. Returns the reverted operations.
+pub async fn undo(pool: &SqlitePool, count: usize) -> anyhow::Result<Vec<Op>> {
+    let mut reverted = vec![];
+    for _ in 0..count {
+        let op = sqlx::query_as!(
+            Op,
+            r#"SELECT o.id as "id!", o.action, o.target, o.created_at, o.reverts, o.undone_by,
+                      o.automatic as "automatic: bool",
+                      (SELECT COUNT(*) FROM audit_changes c WHERE c.op_id = o.id) as "changes!: i64"
+               FROM audit_ops o
+               WHERE o.reverts IS NULL AND o.undone_by IS NULL AND NOT o.automatic
+               ORDER BY o.id DESC
+               LIMIT 1"#
+        )
+        .fetch_optional(pool)
+        .await?;
+        let Some(op) = op else {
+            break;
+        };
+        let changes = changes(pool, op.id).await?;
+
+        let mut tx = begin(pool, "undo", &format!("#{} {}", op.id, op.label())).await?;
+        let undo_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM audit_ops")
+            .fetch_one(&mut *tx)
+            .await?;
+        // Rows come back in reverse order, so parents may follow their children until commit
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *tx)
+            .await?;
+        for change in changes.iter().rev() {
+            let keys = key_columns(&change.table_name)
+                .ok_or_else(|| anyhow::anyhow!("unknown table {}", change.table_name))?;
+            let (sql, params) = revert_sql(
+                &change.table_name,
+                keys,
+                change.row(change.before.as_deref())?.as_ref(),
+                change.row(change.after.as_deref())?.as_ref(),
+            )?;
+            execute(&mut tx, &sql, params)
+                .await
+                .map_err(|e| anyhow::anyhow!("cannot undo #{} {}: {e}", op.id, op.action))?;
+        }
+        sqlx::query("UPDATE audit_ops SET reverts = ? WHERE id = ?")
+            .bind(op.id)
+            .bind(undo_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE audit_ops SET undone_by = ? WHERE id = ?")
+            .bind(undo_id)
+            .bind(op.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        reverted.push(op);
+    }
+    Ok(reverted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use serde_json::json;
+
+    fn row(v: Value) -> Map<String, Value> {
+        v.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_revert_sql() {
+        let before = row(json!({"project_id": "p", "branch": "main", "task_id": "t1"}));
+        let after = row(json!({"project_id": "p", "branch": "main", "task_id": "t2"}));
+        let keys = ["project_id", "branch"];
+
+        let (sql, params) = revert_sql("git_branches", &keys, None, Some(&after)).unwrap();
+        assert_eq!(
+            sql,
+            r#"DELETE FROM "git_branches" WHERE "project_id" = ? AND "branch" = ?"#
+        );
+        assert_eq!(params, [json!("p"), json!("main")]);
+
+        let (sql, params) = revert_sql("git_branches", &keys, Some(&before), None).unwrap();
+        assert_eq!(
+            sql,
+            r#"INSERT INTO "git_branches" ("branch", "project_id", "task_id") VALUES (?, ?, ?)"#
+        );
+        assert_eq!(params, [json!("main"), json!("p"), json!("t1")]);
+
+        let (sql, params) = revert_sql("git_branches", &keys, Some(&before), Some(&after)).unwrap();
+        assert_eq!(
+            sql,
+            r#"UPDATE "git_branches" SET "branch" = ?, "project_id" = ?, "task_id" = ? WHERE "project_id" = ? AND "branch" = ?"#
+        );
+        assert_eq!(params.len(), 5);
+        assert_eq!(params[2], json!("t1"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = row(json!({"id": "t", "name": "API", "priority": 1, "due_date": null}));
+        let after = row(json!({"id": "t", "name": "API", "priority": 2, "due_date": "2024-01-31"}));
+        assert_eq!(
+            diff(&before, &after),
+            [
+                ("due_date".to_string(), Value::Null, json!("2024-01-31")),
+                ("priority".to_string(), json!(1), json!(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_undo_replays_cascade() {
+        let pool = testutil::pool().await;
+        let project = testutil::project(&pool, "Acme").await;
+        let task = testutil::task(&pool, &project, "design").await;
+        let subtask = services::task::NewTask {
+            name: "mockups".into(),
+            parent_id: Some(task.clone()),
+            ..Default::default()
+        };
+        let subtask = services::task::create(&pool, &project, &subtask)
+            .await
+            .unwrap();
+        testutil::entry(&pool, &task, "2024-07-01T09:00:00Z", "2024-07-01T10:00:00Z").await;
+        testutil::entry(
+            &pool,
+            &subtask,
+            "2024-07-01T10:00:00Z",
+            "2024-07-01T11:00:00Z",
+        )
+        .await;
+        services::timer::start(&pool, &subtask, None).await.unwrap();
+
+        // A plain delete rather than the trash, so undo replays the whole cascade
+        let mut tx = begin(&pool, "raw delete", &project).await.unwrap();
+        sqlx::query("DELETE FROM projects WHERE id = ?")
+            .bind(&project)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(testutil::count(&pool, "time_entries").await, 0);
+
+        undo(&pool, 1).await.unwrap();
+        assert_eq!(testutil::count(&pool, "projects").await, 1);
+        assert_eq!(testutil::count(&pool, "tasks").await, 2);
+        assert_eq!(testutil::count(&pool, "time_entries").await, 3);
+        let timer = services::timer::current(&pool).await.unwrap().unwrap();
+        assert_eq!(timer.task_id, subtask);
+        let violations: Vec<(String,)> =
+            sqlx::query_as("SELECT \"table\" FROM pragma_foreign_key_check")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert!(violations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_undo_skips_automatic_operations() {
+        let pool = testutil::pool().await;
+        let doomed = testutil::project(&pool, "Acme").await;
+        testutil::project(&pool, "Tools").await;
+        services::project::delete(&pool, &doomed).await.unwrap();
+
+        let heartbeat = services::heartbeat::Heartbeat {
+            file: "/src/tools/main.rs".into(),
+            project: Some("Tools".into()),
+            task: None,
+            time: Some("2024-07-01T09:00:00Z".into()),
+        };
+        services::heartbeat::record(&pool, &heartbeat)
+            .await
+            .unwrap();
+        assert!(history(&pool, 1).await.unwrap()[0].automatic);
+
+        let undone = undo(&pool, 1).await.unwrap();
+        assert_eq!(undone[0].action, "project delete");
+        assert_eq!(testutil::count(&pool, "projects").await, 2);
+        // The heartbeat's entry is still there
+        assert_eq!(testutil::count(&pool, "time_entries").await, 1);
+    }
+}
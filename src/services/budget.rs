@@ -0,0 +1,288 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// Reject a negative or non-finite budget, rate or estimate
+pub fn check_amount(what: &str, value: Option<f64>) -> anyhow::Result<()> {
+    match value {
+        Some(v) if !v.is_finite() || v < 0.0 => {
+            anyhow::bail!("{what} must be a number of at least 0, got {v}")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check the values of a project budget before storing any of them
+pub fn check_budget(
+    budget_hours: Option<f64>,
+    budget_amount: Option<f64>,
+    hourly_rate: Option<f64>,
+) -> anyhow::Result<()> {
+    check_amount("budget hours", budget_hours)?;
+    check_amount("budget amount", budget_amount)?;
+    check_amount("hourly rate", hourly_rate)
+}
+
+/// Spent vs. planned effort for a project (budget) or a task (estimate).
+#[derive(Debug, Serialize)]
+pub struct BudgetStatus {
+    pub name: String,
+    pub budget_seconds: Option<i64>,
+    pub budget_amount: Option<f64>,
+    pub hourly_rate: Option<f64>,
+    pub spent_seconds: i64,
+}
+
+impl BudgetStatus {
+    pub fn remaining_seconds(&self) -> Option<i64> {
+        self.budget_seconds.map(|b| b - self.spent_seconds)
+    }
+
+    pub fn spent_amount(&self) -> Option<f64> {
+        self.hourly_rate
+            .map(|rate| rate * self.spent_seconds as f64 / 3600.0)
+    }
+
+    pub fn remaining_amount(&self) -> Option<f64> {
+        Some(self.budget_amount? - self.spent_amount()?)
+    }
+
+    /// Percentage of the budget used. Hours take precedence over money when both are set.
+    pub fn percent_used(&self) -> Option<f64> {
+        match (self.budget_seconds, self.budget_amount, self.spent_amount()) {
+            (Some(b), _, _) if b > 0 => Some(self.spent_seconds as f64 * 100.0 / b as f64),
+            (_, Some(b), Some(spent)) if b > 0.0 => Some(spent * 100.0 / b),
+            _ => None,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.percent_used().is_some_and(|p| p > 100.0)
+    }
+}
+
+/// Budget status of a single project. Time on its sub-projects counts against its budget.
+pub async fn project_status(pool: &SqlitePool, project_id: &str) -> anyhow::Result<BudgetStatus> {
+    let r = sqlx::query!(
+        r#"WITH RECURSIVE sub(id) AS (
+             SELECT ? UNION SELECT p.id FROM projects p JOIN sub ON p.parent_id = sub.id
+           )
+           SELECT p.name, p.budget_seconds, p.budget_amount, p.hourly_rate,
+                  (SELECT COALESCE(SUM(e.duration_seconds), 0)
+                   FROM time_entries e JOIN tasks t ON t.id = e.task_id
+                   WHERE t.project_id IN (SELECT id FROM sub)
+                     AND e.end_time IS NOT NULL) as "spent!: i64"
+           FROM projects p
+           WHERE p.id = ?"#,
+        project_id,
+        project_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("project {project_id} not found"))?;
+
+    Ok(BudgetStatus {
+        name: r.name,
+        budget_seconds: r.budget_seconds,
+        budget_amount: r.budget_amount,
+        hourly_rate: r.hourly_rate,
+        spent_seconds: r.spent,
+    })
+}
+
+/// Budget status of every project that has a time or money budget, counting time on
+/// sub-projects like [`project_status`]
+pub async fn projects(pool: &SqlitePool) -> anyhow::Result<Vec<BudgetStatus>> {
+    let rows = sqlx::query!(
+        r#"WITH RECURSIVE sub(root, id) AS (
+             SELECT id, id FROM projects
+             WHERE budget_seconds IS NOT NULL OR budget_amount IS NOT NULL
+             UNION SELECT sub.root, p.id FROM projects p JOIN sub ON p.parent_id = sub.id
+           )
+           SELECT p.name, p.budget_seconds, p.budget_amount, p.hourly_rate,
+                  (SELECT COALESCE(SUM(e.duration_seconds), 0)
+                   FROM time_entries e JOIN tasks t ON t.id = e.task_id
+                   WHERE t.project_id IN (SELECT id FROM sub WHERE root = p.id)
+                     AND e.end_time IS NOT NULL) as "spent!: i64"
+           FROM projects p
+           WHERE p.budget_seconds IS NOT NULL OR p.budget_amount IS NOT NULL
+           ORDER BY p.name"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| BudgetStatus {
+            name: r.name,
+            budget_seconds: r.budget_seconds,
+            budget_amount: r.budget_amount,
+            hourly_rate: r.hourly_rate,
+            spent_seconds: r.spent,
+        })
+        .collect())
+}
+
+/// Estimate status of the tasks of a project that have an estimate
+pub async fn tasks(pool: &SqlitePool, project_id: &str) -> anyhow::Result<Vec<BudgetStatus>> {
+    let rows = sqlx::query!(
+        r#"SELECT t.name, t.estimate_seconds,
+                  COALESCE(SUM(e.duration_seconds), 0) as "spent!: i64"
+           FROM tasks t
+           LEFT JOIN time_entries e ON e.task_id = t.id AND e.end_time IS NOT NULL
+           WHERE t.project_id = ? AND t.estimate_seconds IS NOT NULL
+           GROUP BY t.id
+           ORDER BY t.created_at"#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| BudgetStatus {
+            name: r.name,
+            budget_seconds: r.estimate_seconds,
+            budget_amount: None,
+            hourly_rate: None,
+            spent_seconds: r.spent,
+        })
+        .collect())
+}
+
+/// Warnings for the task about to be started: its own estimate and its project's budget.
+pub async fn overruns(pool: &SqlitePool, task_id: &str) -> anyhow::Result<Vec<String>> {
+    let r = sqlx::query!(
+        r#"SELECT t.name, t.project_id, t.estimate_seconds,
+                  COALESCE(SUM(e.duration_seconds), 0) as "spent!: i64"
+           FROM tasks t
+           LEFT JOIN time_entries e ON e.task_id = t.id AND e.end_time IS NOT NULL
+           WHERE t.id = ?
+           GROUP BY t.id"#,
+        task_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(r) = r else {
+        return Ok(vec![]);
+    };
+
+    let mut warnings = vec![];
+    let task = BudgetStatus {
+        name: r.name,
+        budget_seconds: r.estimate_seconds,
+        budget_amount: None,
+        hourly_rate: None,
+        spent_seconds: r.spent,
+    };
+    if task.is_over() {
+        warnings.push(format!(
+            "task '{}' is over its estimate ({:.0}% used)",
+            task.name,
+            task.percent_used().unwrap_or_default()
+        ));
+    }
+    let project = project_status(pool, &r.project_id).await?;
+    if project.is_over() {
+        warnings.push(format!(
+            "project '{}' is over budget ({:.0}% used)",
+            project.name,
+            project.percent_used().unwrap_or_default()
+        ));
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::project, testutil};
+
+    fn status(
+        budget_seconds: Option<i64>,
+        budget_amount: Option<f64>,
+        rate: Option<f64>,
+        spent: i64,
+    ) -> BudgetStatus {
+        BudgetStatus {
+            name: "Project".into(),
+            budget_seconds,
+            budget_amount,
+            hourly_rate: rate,
+            spent_seconds: spent,
+        }
+    }
+
+    #[test]
+    fn test_percent_used_from_hours() {
+        let s = status(Some(36000), None, None, 9000);
+        assert_eq!(s.percent_used(), Some(25.0));
+        assert_eq!(s.remaining_seconds(), Some(27000));
+        assert!(!s.is_over());
+    }
+
+    #[test]
+    fn test_percent_used_from_amount() {
+        // 3h at 100/h against a budget of 1000
+        let s = status(None, Some(1000.0), Some(100.0), 10800);
+        assert_eq!(s.spent_amount(), Some(300.0));
+        assert_eq!(s.remaining_amount(), Some(700.0));
+        assert_eq!(s.percent_used(), Some(30.0));
+    }
+
+    #[test]
+    fn test_amount_without_rate_has_no_percent() {
+        let s = status(None, Some(1000.0), None, 10800);
+        assert_eq!(s.percent_used(), None);
+        assert_eq!(s.remaining_amount(), None);
+        assert!(!s.is_over());
+    }
+
+    #[test]
+    fn test_check_amount() {
+        assert!(check_amount("estimate", Some(0.0)).is_ok());
+        assert!(check_amount("estimate", Some(7.5)).is_ok());
+        assert!(check_amount("estimate", None).is_ok());
+        assert!(check_amount("estimate", Some(-1.0)).is_err());
+        assert!(check_amount("estimate", Some(f64::NAN)).is_err());
+        assert!(check_amount("estimate", Some(f64::INFINITY)).is_err());
+        assert!(check_budget(Some(40.0), Some(5000.0), Some(-125.0)).is_err());
+    }
+
+    #[test]
+    fn test_over_budget() {
+        let s = status(Some(3600), None, None, 3601);
+        assert!(s.is_over());
+        assert_eq!(s.remaining_seconds(), Some(-1));
+    }
+
+    #[tokio::test]
+    async fn test_project_budget_counts_sub_projects() {
+        let pool = testutil::pool().await;
+        let parent = testutil::project(&pool, "Acme").await;
+        let child = project::add(&pool, "Acme web", None, Some(&parent))
+            .await
+            .unwrap();
+        let grandchild = project::add(&pool, "Acme web api", None, Some(&child))
+            .await
+            .unwrap();
+        project::set_budget(&pool, &parent, Some(10.0), None, None)
+            .await
+            .unwrap();
+        let own = testutil::task(&pool, &parent, "planning").await;
+        let deep = testutil::task(&pool, &grandchild, "endpoints").await;
+        testutil::entry(&pool, &own, "2024-07-01T09:00:00Z", "2024-07-01T10:00:00Z").await;
+        testutil::entry(&pool, &deep, "2024-07-01T10:00:00Z", "2024-07-01T12:00:00Z").await;
+
+        assert_eq!(
+            project_status(&pool, &parent).await.unwrap().spent_seconds,
+            10800
+        );
+        assert_eq!(
+            project_status(&pool, &child).await.unwrap().spent_seconds,
+            7200
+        );
+        let all = projects(&pool).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].spent_seconds, 10800);
+    }
+}
@@ -1,5 +1,8 @@
+use crate::services::{fields, timeutil::*};
 use serde::Serialize;
+use serde_json::Value;
 use sqlx::SqlitePool;
+use std::collections::BTreeMap;
 
 #[derive(Serialize)]
 struct Entry {
@@ -9,16 +12,19 @@ struct Entry {
     end_time: Option<String>,
     duration_seconds: Option<i64>,
     local_date: String,
+    note: Option<String>,
+    /// Custom field values from the entry, its task and its project
+    #[serde(flatten)]
+    fields: BTreeMap<String, Value>,
 }
 
-pub async fn export_json(
+async fn entries(
     pool: &SqlitePool,
-    out: &str,
     from: Option<&str>,
     to: Option<&str>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Vec<Entry>> {
     let rows = sqlx::query!(
-        r#"SELECT id, task_id, start_time, end_time, duration_seconds, local_date
+        r#"SELECT id, task_id, start_time, end_time, duration_seconds, local_date, note
        FROM time_entries
        WHERE (? IS NULL OR local_date >= ?)
          AND (? IS NULL OR local_date <= ?)
@@ -30,18 +36,183 @@ pub async fn export_json(
     )
     .fetch_all(pool)
     .await?;
+    let mut values = fields::entry_values(pool).await?;
 
-    let entries: Vec<Entry> = rows
+    Ok(rows
         .into_iter()
         .map(|row| Entry {
+            fields: row
+                .id
+                .as_ref()
+                .and_then(|id| values.remove(id))
+                .unwrap_or_default(),
             id: row.id.unwrap(),
             task_id: row.task_id,
             start_time: row.start_time,
             end_time: row.end_time,
             duration_seconds: row.duration_seconds,
             local_date: row.local_date,
+            note: row.note,
         })
-        .collect();
+        .collect())
+}
+
+pub async fn export_json(
+    pool: &SqlitePool,
+    out: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> anyhow::Result<()> {
+    let entries = entries(pool, from, to).await?;
     std::fs::write(out, serde_json::to_vec_pretty(&entries)?)?;
     Ok(())
 }
+
+/// Quote a CSV field when it contains a delimiter, quote or line break (RFC 4180)
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub async fn export_csv(
+    pool: &SqlitePool,
+    out: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> anyhow::Result<()> {
+    // Every declared field gets a column, empty where an entry has no value
+    let names: Vec<String> = fields::list(pool)
+        .await?
+        .into_iter()
+        .map(|f| f.name)
+        .collect();
+    let mut csv = String::from("id,task_id,start_time,end_time,duration_seconds,local_date,note");
+    for name in &names {
+        csv.push(',');
+        csv.push_str(&csv_field(name));
+    }
+    csv.push('\n');
+    for e in entries(pool, from, to).await? {
+        let custom = names.iter().map(|name| match e.fields.get(name) {
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        });
+        let fields = [
+            e.id,
+            e.task_id,
+            e.start_time,
+            e.end_time.unwrap_or_default(),
+            e.duration_seconds
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            e.local_date,
+            e.note.unwrap_or_default(),
+        ];
+        let line: Vec<String> = fields
+            .into_iter()
+            .chain(custom)
+            .map(|f| csv_field(&f))
+            .collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+    std::fs::write(out, csv)?;
+    Ok(())
+}
+
+/// Escape a TEXT value (RFC 5545 §3.3.11)
+fn ics_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// RFC3339 UTC timestamp to the iCalendar basic format, e.g. `20240115T093000Z`
+fn ics_time(rfc3339: &str) -> anyhow::Result<String> {
+    let t = parse_rfc3339(rfc3339)?.to_offset(time::UtcOffset::UTC);
+    Ok(t.format(time::macros::format_description!(
+        "[year][month][day]T[hour][minute][second]Z"
+    ))?)
+}
+
+/// Export finished entries as calendar events, titled `project / task` with the note as description
+pub async fn export_ics(
+    pool: &SqlitePool,
+    out: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> anyhow::Result<()> {
+    let rows = sqlx::query!(
+        r#"SELECT e.id as "id!", e.start_time, e.end_time as "end_time!", e.note,
+                  t.name as task_name, p.name as project_name
+       FROM time_entries e
+       JOIN tasks t ON t.id = e.task_id
+       JOIN projects p ON p.id = t.project_id
+       WHERE e.end_time IS NOT NULL
+         AND (? IS NULL OR e.local_date >= ?)
+         AND (? IS NULL OR e.local_date <= ?)
+       ORDER BY e.start_time"#,
+        from,
+        from,
+        to,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let stamp = ics_time(&to_rfc3339(now_utc()))?;
+    let mut ics = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//rustytime//EN\r\n");
+    for r in rows {
+        ics.push_str("BEGIN:VEVENT\r\n");
+        ics.push_str(&format!("UID:{}@rustytime\r\n", r.id));
+        ics.push_str(&format!("DTSTAMP:{stamp}\r\n"));
+        ics.push_str(&format!("DTSTART:{}\r\n", ics_time(&r.start_time)?));
+        ics.push_str(&format!("DTEND:{}\r\n", ics_time(&r.end_time)?));
+        ics.push_str(&format!(
+            "SUMMARY:{}\r\n",
+            ics_text(&format!("{} / {}", r.project_name, r.task_name))
+        ));
+        if let Some(note) = r.note.filter(|n| !n.is_empty()) {
+            ics.push_str(&format!("DESCRIPTION:{}\r\n", ics_text(&note)));
+        }
+        ics.push_str("END:VEVENT\r\n");
+    }
+    ics.push_str("END:VCALENDAR\r\n");
+    std::fs::write(out, ics)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_ics_text_escaping() {
+        assert_eq!(ics_text("a, b; c\\d\ne"), "a\\, b\\; c\\\\d\\ne");
+    }
+
+    #[test]
+    fn test_ics_time() {
+        assert_eq!(
+            ics_time("2024-01-15T09:30:00Z").unwrap(),
+            "20240115T093000Z"
+        );
+        assert_eq!(
+            ics_time("2024-07-15T18:30:00.5+02:00").unwrap(),
+            "20240715T163000Z"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Custom fields: typed metadata on projects, tasks or entries.
+//!
+//! A field is declared once with the kind of item it applies to and a type; values are checked
+//! and normalized against that type when set, so reports can filter by plain text equality.
+
+use crate::{
+    models::{CustomField, FieldTarget, FieldType},
+    services::{audit, timeutil::*},
+};
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+
+/// Columns of the entry export, which fields cannot shadow
+const RESERVED: [&str; 7] = [
+    "id",
+    "task_id",
+    "start_time",
+    "end_time",
+    "duration_seconds",
+    "local_date",
+    "note",
+];
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        anyhow::bail!("field names use lowercase letters, digits, '_' and '-', got '{name}'");
+    }
+    if RESERVED.contains(&name) {
+        anyhow::bail!("'{name}' is an export column and cannot be a field name");
+    }
+    Ok(())
+}
+
+/// The stored form of `value`, or why the field does not accept it
+pub fn normalize(field: &CustomField, value: &str) -> anyhow::Result<String> {
+    let value = value.trim();
+    match field.field_type {
+        FieldType::Text if value.is_empty() => anyhow::bail!("{} cannot be empty", field.name),
+        FieldType::Text => Ok(value.to_string()),
+        FieldType::Number => match value.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(n.to_string()),
+            _ => anyhow::bail!("{} takes a number, got '{value}'", field.name),
+        },
+        FieldType::Date => Ok(format_date(parse_date(value)?)),
+        FieldType::Enum if field.options.iter().any(|o| o == value) => Ok(value.to_string()),
+        FieldType::Enum => anyhow::bail!(
+            "{} takes one of {}, got '{value}'",
+            field.name,
+            field.options.join(", ")
+        ),
+    }
+}
+
+/// A stored value as JSON: numbers as numbers, everything else as strings
+fn to_json(field_type: FieldType, value: &str) -> Value {
+    match field_type {
+        FieldType::Number => value
+            .parse::<f64>()
+            .map_or_else(|_| json!(value), |n| json!(n)),
+        _ => json!(value),
+    }
+}
+
+/// Split a `NAME=VALUE` filter
+fn split_filter(filter: &str) -> anyhow::Result<(&str, &str)> {
+    filter
+        .split_once('=')
+        .map(|(name, value)| (name.trim(), value))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("expected NAME=VALUE, got '{filter}'"))
+}
+
+pub async fn add(
+    pool: &SqlitePool,
+    name: &str,
+    target: FieldTarget,
+    field_type: FieldType,
+    options: &[String],
+) -> anyhow::Result<()> {
+    check_name(name)?;
+    let options: Vec<&str> = options
+        .iter()
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+        .collect();
+    let options = match (field_type, options.is_empty()) {
+        (FieldType::Enum, true) => anyhow::bail!("enum fields need --options"),
+        (FieldType::Enum, false) => Some(json!(options).to_string()),
+        (_, true) => None,
+        (_, false) => anyhow::bail!("only enum fields take --options"),
+    };
+    let mut tx = audit::begin(pool, "field add", name).await?;
+    let exists = sqlx::query_scalar!("SELECT name FROM custom_fields WHERE name = ?", name)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if exists.is_some() {
+        anyhow::bail!("field {name} already exists");
+    }
+    let now = to_rfc3339(now_utc());
+    sqlx::query!(
+        "INSERT INTO custom_fields(name, target, type, options, created_at) VALUES(?, ?, ?, ?, ?)",
+        name,
+        target,
+        field_type,
+        options,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<CustomField>> {
+    let rows = sqlx::query!(
+        r#"SELECT name as "name!", target as "target: FieldTarget", type as "field_type: FieldType",
+                  options, created_at
+           FROM custom_fields ORDER BY name"#
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(CustomField {
+                name: r.name,
+                target: r.target,
+                field_type: r.field_type,
+                options: r
+                    .options
+                    .map(|o| serde_json::from_str(&o))
+                    .transpose()?
+                    .unwrap_or_default(),
+                created_at: r.created_at,
+            })
+        })
+        .collect()
+}
+
+async fn get(pool: &SqlitePool, name: &str) -> anyhow::Result<CustomField> {
+    list(pool)
+        .await?
+        .into_iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| anyhow::anyhow!("no field {name}; see `field list`"))
+}
+
+/// Drop a field and every value set for it
+pub async fn remove(pool: &SqlitePool, name: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "field remove", name).await?;
+    let removed = sqlx::query!("DELETE FROM custom_fields WHERE name = ?", name)
+        .execute(&mut *tx)
+        .await?;
+    if removed.rows_affected() == 0 {
+        anyhow::bail!("no field {name}");
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn column(target: FieldTarget) -> (&'static str, &'static str) {
+    match target {
+        FieldTarget::Project => ("projects", "project_id"),
+        FieldTarget::Task => ("tasks", "task_id"),
+        FieldTarget::Entry => ("time_entries", "entry_id"),
+    }
+}
+
+/// Set field `name` on the project, task or entry `item_id`
+pub async fn set(pool: &SqlitePool, name: &str, item_id: &str, value: &str) -> anyhow::Result<()> {
+    let field = get(pool, name).await?;
+    let value = normalize(&field, value)?;
+    let (table, column) = column(field.target);
+
+    let mut tx = audit::begin(pool, "field set", &format!("{item_id} {name}={value}")).await?;
+    let exists: bool = sqlx::query_scalar(&format!(
+        "SELECT EXISTS(SELECT 1 FROM {table} WHERE id = ?)"
+    ))
+    .bind(item_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        let target = field.target.as_str();
+        anyhow::bail!("no {target} {item_id}; {name} is a {target} field");
+    }
+    let updated = sqlx::query(&format!(
+        "UPDATE custom_values SET value = ? WHERE field = ? AND {column} = ?"
+    ))
+    .bind(&value)
+    .bind(name)
+    .bind(item_id)
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        sqlx::query(&format!(
+            "INSERT INTO custom_values(field, {column}, value) VALUES(?, ?, ?)"
+        ))
+        .bind(name)
+        .bind(item_id)
+        .bind(&value)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Clear field `name` on `item_id`
+pub async fn unset(pool: &SqlitePool, name: &str, item_id: &str) -> anyhow::Result<()> {
+    let field = get(pool, name).await?;
+    let (_, column) = column(field.target);
+    let mut tx = audit::begin(pool, "field unset", &format!("{item_id} {name}")).await?;
+    sqlx::query(&format!(
+        "DELETE FROM custom_values WHERE field = ? AND {column} = ?"
+    ))
+    .bind(name)
+    .bind(item_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Carry the field values of `duplicate` over to `survivor` when merging two projects or tasks.
+/// Values the survivor lacks are moved; a field set differently on both fails the merge, naming
+/// the fields, rather than losing one of the values.
+pub async fn merge_tx(
+    tx: &mut audit::Tx,
+    target: FieldTarget,
+    survivor: &str,
+    duplicate: &str,
+) -> anyhow::Result<()> {
+    let (_, column) = column(target);
+    let conflicts: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT d.field FROM custom_values d
+         JOIN custom_values s ON s.field = d.field AND s.{column} = ?
+         WHERE d.{column} = ? AND s.value <> d.value
+         ORDER BY d.field"
+    ))
+    .bind(survivor)
+    .bind(duplicate)
+    .fetch_all(&mut **tx)
+    .await?;
+    if !conflicts.is_empty() {
+        let target = target.as_str();
+        anyhow::bail!(
+            "{target} {duplicate} and {survivor} have different values for {}; \
+             set them alike or unset one before merging",
+            conflicts.join(", ")
+        );
+    }
+    // Values equal on both stay behind and go with the duplicate
+    sqlx::query(&format!(
+        "UPDATE OR IGNORE custom_values SET {column} = ? WHERE {column} = ?"
+    ))
+    .bind(survivor)
+    .bind(duplicate)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Fields set on a project, task or entry, by name
+pub async fn values(pool: &SqlitePool, item_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let rows = sqlx::query!(
+        r#"SELECT field, value FROM custom_values
+           WHERE project_id = ? OR task_id = ? OR entry_id = ?
+           ORDER BY field"#,
+        item_id,
+        item_id,
+        item_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.field, r.value)).collect())
+}
+
+/// Report filter for `NAME=VALUE` pairs, as the JSON the report queries take; `None` when
+/// there are no filters. An entry matches when its own value, its task's or its project's
+/// equals the given one, for every pair.
+pub async fn filter(pool: &SqlitePool, filters: &[String]) -> anyhow::Result<Option<String>> {
+    if filters.is_empty() {
+        return Ok(None);
+    }
+    let fields = list(pool).await?;
+    let mut pairs = vec![];
+    for filter in filters {
+        let (name, value) = split_filter(filter)?;
+        let field = fields
+            .iter()
+            .find(|f| f.name == name)
+            .ok_or_else(|| anyhow::anyhow!("no field {name}; see `field list`"))?;
+        pairs.push(json!([name, normalize(field, value)?]));
+    }
+    Ok(Some(Value::Array(pairs).to_string()))
+}
+
+/// Every field value reaching each entry from the entry itself, its task and its project,
+/// by entry id and field name
+pub async fn entry_values(
+    pool: &SqlitePool,
+) -> anyhow::Result<HashMap<String, BTreeMap<String, Value>>> {
+    let rows = sqlx::query!(
+        r#"SELECT v.entry_id as "entry_id!", f.name as "name!", f.type as "field_type: FieldType",
+                  v.value as "value!"
+           FROM entry_field_values v
+           JOIN custom_fields f ON f.name = v.field"#
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut out: HashMap<String, BTreeMap<String, Value>> = HashMap::new();
+    for r in rows {
+        out.entry(r.entry_id)
+            .or_default()
+            .insert(r.name, to_json(r.field_type, &r.value));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(field_type: FieldType, options: &[&str]) -> CustomField {
+        CustomField {
+            name: "f".into(),
+            target: FieldTarget::Task,
+            field_type,
+            options: options.iter().map(|o| o.to_string()).collect(),
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let text = field(FieldType::Text, &[]);
+        assert_eq!(normalize(&text, " PROJ-12 ").unwrap(), "PROJ-12");
+        assert!(normalize(&text, " ").is_err());
+
+        let number = field(FieldType::Number, &[]);
+        assert_eq!(normalize(&number, "12.50").unwrap(), "12.5");
+        assert_eq!(normalize(&number, "7").unwrap(), "7");
+        assert!(normalize(&number, "seven").is_err());
+        assert!(normalize(&number, "inf").is_err());
+
+        let date = field(FieldType::Date, &[]);
+        assert_eq!(normalize(&date, "2024-01-31").unwrap(), "2024-01-31");
+        assert!(normalize(&date, "31.01.2024").is_err());
+
+        let choice = field(FieldType::Enum, &["cc-100", "cc-200"]);
+        assert_eq!(normalize(&choice, "cc-200").unwrap(), "cc-200");
+        assert!(normalize(&choice, "cc-300").is_err());
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("cost_center").is_ok());
+        assert!(check_name("client-po2").is_ok());
+        assert!(check_name("Cost Center").is_err());
+        assert!(check_name("").is_err());
+        assert!(check_name("note").is_err());
+    }
+
+    #[test]
+    fn test_split_filter() {
+        assert_eq!(split_filter("ticket=PROJ-1").unwrap(), ("ticket", "PROJ-1"));
+        assert_eq!(split_filter("expr=a=b").unwrap(), ("expr", "a=b"));
+        assert!(split_filter("ticket").is_err());
+        assert!(split_filter("=x").is_err());
+    }
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(to_json(FieldType::Number, "12.5"), json!(12.5));
+        assert_eq!(to_json(FieldType::Text, "12.5"), json!("12.5"));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::services::{self, audit};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const HOOKS: [&str; 2] = ["post-checkout", "post-commit"];
+const MARKER: &str = "# rustytime";
+
+/// A repository mapped to a project
+pub struct Repo {
+    pub repo_path: String,
+    pub project_name: String,
+}
+
+/// Run git in `dir` and return its trimmed stdout, or `None` when it fails
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// The work tree containing `dir`
+pub fn repo_root(dir: &Path) -> anyhow::Result<PathBuf> {
+    git(dir, &["rev-parse", "--show-toplevel"])
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("{} is not inside a git repository", dir.display()))
+}
+
+/// The checked out branch; `None` on a detached HEAD
+fn current_branch(repo: &Path) -> Option<String> {
+    git(repo, &["symbolic-ref", "--short", "-q", "HEAD"])
+}
+
+/// The hook script after adding `command` to `existing`; `None` when it is already installed.
+/// Existing hooks are kept and the command is appended to them.
+fn hook_script(existing: Option<&str>, command: &str) -> Option<String> {
+    let line = format!("{command} || true {MARKER}\n");
+    match existing {
+        Some(script) if script.contains(MARKER) => None,
+        Some(script) => {
+            let mut script = script.to_string();
+            if !script.ends_with('\n') {
+                script.push('\n');
+            }
+            script.push_str(&line);
+            Some(script)
+        }
+        None => Some(format!("#!/bin/sh\n{line}")),
+    }
+}
+
+/// Map the repository at `repo` to a project: `project_id`, or a project named after the
+/// repository directory (created when missing). Returns the project id.
+pub async fn map_repo(
+    pool: &SqlitePool,
+    repo: &Path,
+    project_id: Option<&str>,
+) -> anyhow::Result<String> {
+    let project_id = match project_id {
+        Some(id) => id.to_string(),
+        None => {
+            let name = repo
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow::anyhow!("cannot name a project after {}", repo.display()))?;
+            services::project::find_or_add(pool, &name).await?
+        }
+    };
+    let repo_path = repo.to_string_lossy();
+    let mut tx = audit::begin(pool, "git map", &repo_path).await?;
+    sqlx::query(
+        "INSERT INTO git_repos(repo_path, project_id) VALUES(?, ?)
+         ON CONFLICT(repo_path) DO UPDATE SET project_id = excluded.project_id",
+    )
+    .bind(repo_path.as_ref())
+    .bind(&project_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(project_id)
+}
+
+/// Write the post-checkout and post-commit hooks of `repo`, running `command` (the rustytime
+/// invocation, e.g. with `--db`) with `git hook <name>`. Returns the hooks that were changed.
+pub fn install_hooks(repo: &Path, command: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let hooks_dir = git(
+        repo,
+        &["rev-parse", "--path-format=absolute", "--git-path", "hooks"],
+    )
+    .map(PathBuf::from)
+    .ok_or_else(|| anyhow::anyhow!("cannot locate the hooks directory of {}", repo.display()))?;
+    std::fs::create_dir_all(&hooks_dir)?;
+    let mut installed = vec![];
+    for hook in HOOKS {
+        let path = hooks_dir.join(hook);
+        let existing = std::fs::read_to_string(&path).ok();
+        let run = format!("{command} git hook {hook} \"$@\"");
+        if let Some(script) = hook_script(existing.as_deref(), &run) {
+            std::fs::write(&path, script)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            }
+            installed.push(path);
+        }
+    }
+    Ok(installed)
+}
+
+pub async fn repos(pool: &SqlitePool) -> anyhow::Result<Vec<Repo>> {
+    let rows = sqlx::query_as!(
+        Repo,
+        r#"SELECT r.repo_path as "repo_path!", p.name as project_name
+           FROM git_repos r JOIN projects p ON p.id = r.project_id
+           ORDER BY r.repo_path"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+async fn repo_project(pool: &SqlitePool, repo: &Path) -> anyhow::Result<Option<String>> {
+    let repo_path = repo.to_string_lossy();
+    let project_id = sqlx::query_scalar("SELECT project_id FROM git_repos WHERE repo_path = ?")
+        .bind(repo_path.as_ref())
+        .fetch_optional(pool)
+        .await?;
+    Ok(project_id)
+}
+
+/// The task tracking `branch`: the one it was tracked on before, an existing task of that
+/// name, or a new one
+async fn branch_task(tx: &mut audit::Tx, project_id: &str, branch: &str) -> anyhow::Result<String> {
+    let mapped: Option<String> =
+        sqlx::query_scalar("SELECT task_id FROM git_branches WHERE project_id = ? AND branch = ?")
+            .bind(project_id)
+            .bind(branch)
+            .fetch_optional(&mut **tx)
+            .await?;
+    if let Some(task_id) = mapped {
+        return Ok(task_id);
+    }
+    let task_id = services::task::find_or_add_tx(tx, project_id, branch).await?;
+    sqlx::query("INSERT INTO git_branches(project_id, branch, task_id) VALUES(?, ?, ?)")
+        .bind(project_id)
+        .bind(branch)
+        .bind(&task_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(task_id)
+}
+
+/// Handle a git hook run in `dir`; returns a line to show in git's output, if any.
+///
+/// `post-checkout` moves a running timer to the task of the checked out branch (a new entry);
+/// `post-commit` appends the commit to the note of the running entry. Both leave alone a timer
+/// running on a project other than the repository's, and log their writes as automatic
+/// operations, which `undo` skips.
+pub async fn run_hook(
+    pool: &SqlitePool,
+    dir: &Path,
+    hook: &str,
+    args: &[String],
+) -> anyhow::Result<Option<String>> {
+    let repo = repo_root(dir)?;
+    let Some(project_id) = repo_project(pool, &repo).await? else {
+        return Ok(None);
+    };
+    let Some(timer) = services::timer::current(pool).await? else {
+        return Ok(None);
+    };
+    // A timer running on another project is not this repository's work
+    let timer_project: String = sqlx::query_scalar("SELECT project_id FROM tasks WHERE id = ?")
+        .bind(&timer.task_id)
+        .fetch_one(pool)
+        .await?;
+    if timer_project != project_id {
+        return Ok(None);
+    }
+    match hook {
+        "post-checkout" => {
+            // The third argument is 1 for a branch checkout, 0 for a file checkout
+            if args.get(2).map(String::as_str) != Some("1") {
+                return Ok(None);
+            }
+            let Some(branch) = current_branch(&repo) else {
+                return Ok(None);
+            };
+            let mut tx = audit::begin_automatic(pool, "git checkout", &branch).await?;
+            let task_id = branch_task(&mut tx, &project_id, &branch).await?;
+            let switched = task_id != timer.task_id;
+            if switched {
+                services::timer::start_tx(&mut tx, &task_id, None).await?;
+            }
+            tx.commit().await?;
+            Ok(switched.then(|| format!("rustytime: now tracking {branch}")))
+        }
+        "post-commit" => {
+            let Some(commit) = git(&repo, &["log", "-1", "--format=%h %s"]) else {
+                return Ok(None);
+            };
+            let text = format!("commit {commit}");
+            let mut tx = audit::begin_automatic(pool, "git commit", &text).await?;
+            services::timer::note_tx(&mut tx, &text).await?;
+            tx.commit().await?;
+            Ok(None)
+        }
+        _ => anyhow::bail!("unknown hook '{hook}'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_script() {
+        let cmd = "'/usr/bin/rustytime' git hook post-commit \"$@\"";
+        let fresh = hook_script(None, cmd).unwrap();
+        assert!(fresh.starts_with("#!/bin/sh\n"));
+        assert!(fresh.contains(cmd));
+        assert_eq!(hook_script(Some(&fresh), cmd), None);
+
+        let appended = hook_script(Some("#!/bin/sh\nmake lint"), cmd).unwrap();
+        assert!(appended.starts_with("#!/bin/sh\nmake lint\n"));
+        assert!(appended.ends_with(&format!("{MARKER}\n")));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::services::{audit, report, timeutil::*};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use time::{Date, Weekday};
+
+const WEEK: [Weekday; 7] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+    Weekday::Saturday,
+    Weekday::Sunday,
+];
+
+/// Parse a weekday selector: `mon`..`sun` (or full names), `workdays`, `weekend` or `all`.
+/// Several selectors can be combined with commas, e.g. `mon,wed,fri`.
+pub fn parse_days(s: &str) -> anyhow::Result<Vec<Weekday>> {
+    let mut days = vec![];
+    for part in s.split(',') {
+        let part = part.trim().to_lowercase();
+        match part.as_str() {
+            "all" => days.extend(WEEK),
+            "workdays" => days.extend(&WEEK[..5]),
+            "weekend" => days.extend(&WEEK[5..]),
+            _ => {
+                let day = WEEK
+                    .iter()
+                    .find(|d| {
+                        let name = d.to_string().to_lowercase();
+                        part.len() >= 3 && name.starts_with(&part)
+                    })
+                    .ok_or_else(|| anyhow::anyhow!("unknown weekday '{part}'"))?;
+                days.push(*day);
+            }
+        }
+    }
+    days.sort_by_key(|d| d.number_days_from_monday());
+    days.dedup();
+    Ok(days)
+}
+
+/// Weekdays as `mon,tue`, for the change log
+fn day_names(days: &[Weekday]) -> String {
+    days.iter()
+        .map(|d| d.to_string()[..3].to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Set the daily target for the given weekdays
+pub async fn set(pool: &SqlitePool, days: &[Weekday], hours: f64) -> anyhow::Result<()> {
+    let secs = hours_to_seconds(hours);
+    let target = format!("{} {hours}h", day_names(days));
+    let mut tx = audit::begin(pool, "goal set", &target).await?;
+    for day in days {
+        let weekday = day.number_days_from_monday();
+        sqlx::query!(
+            "INSERT INTO goals(weekday, target_seconds) VALUES(?, ?)
+             ON CONFLICT(weekday) DO UPDATE SET target_seconds = excluded.target_seconds",
+            weekday,
+            secs
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Remove the daily target for the given weekdays
+pub async fn clear(pool: &SqlitePool, days: &[Weekday]) -> anyhow::Result<()> {
+    let target = day_names(days);
+    let mut tx = audit::begin(pool, "goal clear", &target).await?;
+    for day in days {
+        let weekday = day.number_days_from_monday();
+        sqlx::query!("DELETE FROM goals WHERE weekday = ?", weekday)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Configured targets, Monday first
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<(Weekday, i64)>> {
+    let rows = sqlx::query!("SELECT weekday, target_seconds FROM goals ORDER BY weekday")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (WEEK[r.weekday as usize], r.target_seconds))
+        .collect())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DayProgress {
+    pub date: Date,
+    pub tracked_seconds: i64,
+    pub target_seconds: i64,
+    /// Today or later and the target not met yet; left out of the balance and streaks
+    pub pending: bool,
+}
+
+impl DayProgress {
+    pub fn balance(&self) -> i64 {
+        self.tracked_seconds - self.target_seconds
+    }
+
+    pub fn met(&self) -> bool {
+        self.tracked_seconds >= self.target_seconds
+    }
+}
+
+#[derive(Debug)]
+pub struct GoalReport {
+    pub days: Vec<DayProgress>,
+    /// Overtime (positive) or undertime (negative) accumulated over the range
+    pub balance_seconds: i64,
+    pub current_streak: u32,
+    pub longest_streak: u32,
+}
+
+/// Compare daily totals against weekday targets for every day in `from..=to`.
+///
+/// Days without a target are neutral for streaks; any time tracked on them counts as overtime.
+/// Days from `today` on count only once their target is met, so an unfinished day neither
+/// breaks the streak nor shows up as undertime.
+pub fn progress(
+    from: Date,
+    to: Date,
+    today: Date,
+    totals: &HashMap<Date, i64>,
+    targets: &HashMap<Weekday, i64>,
+) -> GoalReport {
+    let mut days = vec![];
+    let mut date = from;
+    while date <= to {
+        let tracked_seconds = totals.get(&date).copied().unwrap_or(0);
+        let target_seconds = targets.get(&date.weekday()).copied().unwrap_or(0);
+        days.push(DayProgress {
+            date,
+            tracked_seconds,
+            target_seconds,
+            pending: date >= today && tracked_seconds < target_seconds,
+        });
+        match date.next_day() {
+            Some(next) => date = next,
+            None => break,
+        }
+    }
+
+    let counted = || days.iter().filter(|d| !d.pending);
+    let balance_seconds = counted().map(DayProgress::balance).sum();
+    let (mut current_streak, mut longest_streak) = (0, 0);
+    for day in counted().filter(|d| d.target_seconds > 0) {
+        if day.met() {
+            current_streak += 1;
+            longest_streak = longest_streak.max(current_streak);
+        } else {
+            current_streak = 0;
+        }
+    }
+
+    GoalReport {
+        days,
+        balance_seconds,
+        current_streak,
+        longest_streak,
+    }
+}
+
+/// Goal progress between two dates (inclusive)
+pub async fn report(pool: &SqlitePool, from: Date, to: Date) -> anyhow::Result<GoalReport> {
+    let totals = report::daily_range(pool, &format_date(from), &format_date(to))
+        .await?
+        .into_iter()
+        .map(|(date, total)| Ok((parse_date(&date)?, total)))
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    let targets = list(pool).await?.into_iter().collect();
+    Ok(progress(from, to, today_local(), &totals, &targets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    fn date(day: u8) -> Date {
+        // 2024-01-01 is a Monday
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    #[test]
+    fn test_parse_days() {
+        assert_eq!(parse_days("mon").unwrap(), vec![Weekday::Monday]);
+        assert_eq!(
+            parse_days("fri,Monday").unwrap(),
+            vec![Weekday::Monday, Weekday::Friday]
+        );
+        assert_eq!(parse_days("workdays").unwrap().len(), 5);
+        assert_eq!(
+            parse_days("weekend").unwrap(),
+            vec![Weekday::Saturday, Weekday::Sunday]
+        );
+        assert_eq!(parse_days("all,mon").unwrap().len(), 7);
+        assert!(parse_days("mo").is_err());
+        assert!(parse_days("someday").is_err());
+    }
+
+    #[test]
+    fn test_progress_balance_and_streaks() {
+        let targets: HashMap<_, _> = WEEK[..5].iter().map(|d| (*d, 3600)).collect();
+        let totals: HashMap<_, _> = [
+            (date(1), 3600), // Mon met
+            (date(2), 1800), // Tue short by 30m
+            (date(3), 3600), // Wed met
+            (date(4), 7200), // Thu met, 1h over
+            (date(6), 900),  // Sat, no target: overtime
+        ]
+        .into_iter()
+        .collect();
+
+        // Fri 5th has nothing tracked and breaks the streak; Sun 7th is neutral
+        let report = progress(date(1), date(7), date(8), &totals, &targets);
+        assert_eq!(report.days.len(), 7);
+        assert_eq!(report.balance_seconds, -1800 + 3600 - 3600 + 900);
+        assert_eq!(report.longest_streak, 2);
+        assert_eq!(report.current_streak, 0);
+    }
+
+    #[test]
+    fn test_progress_streak_skips_days_without_target() {
+        let targets: HashMap<_, _> = [(Weekday::Friday, 3600), (Weekday::Monday, 3600)]
+            .into_iter()
+            .collect();
+        let totals: HashMap<_, _> = [(date(5), 3600), (date(8), 4000)].into_iter().collect();
+
+        let report = progress(date(5), date(8), date(9), &totals, &targets);
+        assert_eq!(report.current_streak, 2);
+        assert_eq!(report.longest_streak, 2);
+        assert_eq!(report.balance_seconds, 400);
+    }
+
+    #[test]
+    fn test_progress_leaves_unfinished_today_out() {
+        let targets: HashMap<_, _> = WEEK[..5].iter().map(|d| (*d, 3600)).collect();
+        let mut totals: HashMap<_, _> = [(date(1), 3600), (date(2), 600)].into_iter().collect();
+
+        // Tuesday is today and short so far: the streak stands and there is no undertime
+        let report = progress(date(1), date(2), date(2), &totals, &targets);
+        assert!(report.days[1].pending);
+        assert_eq!(report.current_streak, 1);
+        assert_eq!(report.balance_seconds, 0);
+
+        // Once met it counts, overtime included
+        totals.insert(date(2), 4000);
+        let report = progress(date(1), date(2), date(2), &totals, &targets);
+        assert!(!report.days[1].pending);
+        assert_eq!(report.current_streak, 2);
+        assert_eq!(report.balance_seconds, 400);
+
+        // A day that is over counts whether met or not
+        totals.insert(date(2), 600);
+        let report = progress(date(1), date(2), date(3), &totals, &targets);
+        assert_eq!(report.current_streak, 0);
+        assert_eq!(report.balance_seconds, -3000);
+    }
+}
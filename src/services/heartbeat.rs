@@ -0,0 +1,336 @@
+use crate::services::{self, audit, timeutil::*};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+const DEFAULT_GAP_MINUTES: i64 = 15;
+const DEFAULT_TASK: &str = "coding";
+
+/// Activity reported by an editor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub file: String,
+    /// Project name or id; found from the rules when omitted
+    pub project: Option<String>,
+    /// Task name within the project (default `coding`)
+    pub task: Option<String>,
+    /// RFC3339, HH:MM or YYYY-MM-DD HH:MM local time, or Unix seconds; now when omitted
+    pub time: Option<String>,
+}
+
+/// A directory tracked on a project
+#[derive(Debug)]
+pub struct Rule {
+    pub path_prefix: String,
+    pub project_name: String,
+}
+
+/// Heartbeats at most this far apart join into one entry, from `RUSTYTIME_HEARTBEAT_GAP_MINUTES` (default 15)
+pub fn gap_seconds() -> i64 {
+    std::env::var("RUSTYTIME_HEARTBEAT_GAP_MINUTES")
+        .ok()
+        .and_then(|m| m.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_GAP_MINUTES)
+        * 60
+}
+
+/// Parse a heartbeat time: Unix seconds (fractional allowed) or anything `stop --at` accepts
+pub fn parse_time(s: &str) -> anyhow::Result<OffsetDateTime> {
+    if let Ok(secs) = s.parse::<f64>() {
+        let nanos = (secs * 1e9) as i128;
+        return Ok(OffsetDateTime::from_unix_timestamp_nanos(nanos)?);
+    }
+    parse_local_datetime(s, today_local())
+}
+
+/// The prefix among `prefixes` that contains `file`, preferring the longest.
+/// Prefixes match whole path components: `/src/app` contains `/src/app/main.rs` but not `/src/apple`.
+fn longest_prefix<'a>(file: &str, prefixes: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    prefixes
+        .filter(|prefix| Path::new(file).starts_with(prefix))
+        .max_by_key(|prefix| prefix.len())
+}
+
+/// The entry span after a heartbeat at `at`, when `at` is within `gap` seconds of `span`
+fn extend(
+    span: (OffsetDateTime, OffsetDateTime),
+    at: OffsetDateTime,
+    gap: i64,
+) -> Option<(OffsetDateTime, OffsetDateTime)> {
+    let gap = Duration::seconds(gap);
+    let (start, end) = span;
+    (at >= start - gap && at <= end + gap).then(|| (start.min(at), end.max(at)))
+}
+
+pub async fn add_rule(
+    pool: &SqlitePool,
+    path_prefix: &str,
+    project_id: &str,
+) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "heartbeat rule add", path_prefix).await?;
+    sqlx::query(
+        "INSERT INTO heartbeat_rules(path_prefix, project_id) VALUES(?, ?)
+         ON CONFLICT(path_prefix) DO UPDATE SET project_id = excluded.project_id",
+    )
+    .bind(path_prefix)
+    .bind(project_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_rule(pool: &SqlitePool, path_prefix: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "heartbeat rule remove", path_prefix).await?;
+    sqlx::query("DELETE FROM heartbeat_rules WHERE path_prefix = ?")
+        .bind(path_prefix)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn rules(pool: &SqlitePool) -> anyhow::Result<Vec<Rule>> {
+    let rows = sqlx::query_as!(
+        Rule,
+        r#"SELECT r.path_prefix as "path_prefix!", p.name as project_name
+           FROM heartbeat_rules r JOIN projects p ON p.id = r.project_id
+           ORDER BY r.path_prefix"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// The project a heartbeat belongs to: the given project id or name, else the longest matching
+/// rule, else the git repository the file is in (see `git install-hooks`)
+async fn resolve_project(pool: &SqlitePool, hb: &Heartbeat) -> anyhow::Result<String> {
+    if let Some(project) = &hb.project {
+        let found: Option<String> =
+            sqlx::query_scalar("SELECT id FROM projects WHERE id = ? OR name = ?")
+                .bind(project)
+                .bind(project)
+                .fetch_optional(pool)
+                .await?;
+        return found.ok_or_else(|| anyhow::anyhow!("no project {project}"));
+    }
+    let prefixes: Vec<(String, String)> = sqlx::query_as(
+        "SELECT path_prefix, project_id FROM heartbeat_rules
+         UNION ALL
+         SELECT repo_path, project_id FROM git_repos",
+    )
+    .fetch_all(pool)
+    .await?;
+    let prefix =
+        longest_prefix(&hb.file, prefixes.iter().map(|(p, _)| p.as_str())).ok_or_else(|| {
+            anyhow::anyhow!(
+                "no project for {}; pass a project or add a rule with `heartbeat rule add`",
+                hb.file
+            )
+        })?;
+    Ok(prefixes
+        .iter()
+        .find(|(p, _)| p == prefix)
+        .map(|(_, id)| id.clone())
+        .expect("prefix comes from the list"))
+}
+
+/// Record a heartbeat, extending the heartbeat entry of its task it falls within the gap of,
+/// or starting a new one. Returns the entry id.
+pub async fn record(pool: &SqlitePool, hb: &Heartbeat) -> anyhow::Result<String> {
+    let at = match &hb.time {
+        Some(time) => parse_time(time)?,
+        None => now_utc(),
+    };
+    let project_id = resolve_project(pool, hb).await?;
+    let task_name = hb.task.as_deref().unwrap_or(DEFAULT_TASK);
+    let gap = gap_seconds();
+
+    let mut tx = audit::begin_automatic(pool, "heartbeat", &hb.file).await?;
+    let task_id = services::task::find_or_add_tx(&mut tx, &project_id, task_name).await?;
+    let day = local_date(at);
+    let from = format_date(day.previous_day().unwrap_or(day));
+    let to = format_date(day.next_day().unwrap_or(day));
+    let candidates: Vec<(String, String, String)> = sqlx::query_as(
+        "SELECT id, start_time, end_time FROM time_entries
+         WHERE task_id = ? AND source = 'heartbeat' AND end_time IS NOT NULL
+           AND local_date BETWEEN ? AND ?",
+    )
+    .bind(&task_id)
+    .bind(&from)
+    .bind(&to)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for (id, start, end) in candidates {
+        let span = (parse_rfc3339(&start)?, parse_rfc3339(&end)?);
+        if let Some((start, end)) = extend(span, at, gap) {
+            if (start, end) != span {
+                sqlx::query(
+                    "UPDATE time_entries
+                     SET start_time = ?, end_time = ?, duration_seconds = ?, local_date = ?
+                     WHERE id = ?",
+                )
+                .bind(to_rfc3339(start))
+                .bind(to_rfc3339(end))
+                .bind((end - start).whole_seconds())
+                .bind(local_date_str(start))
+                .bind(&id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            return Ok(id);
+        }
+    }
+
+    let id = Uuid::now_v7().to_string();
+    let at_s = to_rfc3339(at);
+    sqlx::query(
+        "INSERT INTO time_entries(id, task_id, start_time, end_time, duration_seconds, local_date, created_at, source)
+         VALUES(?, ?, ?, ?, 0, ?, ?, 'heartbeat')",
+    )
+    .bind(&id)
+    .bind(&task_id)
+    .bind(&at_s)
+    .bind(&at_s)
+    .bind(local_date_str(at))
+    .bind(to_rfc3339(now_utc()))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_longest_prefix() {
+        let prefixes = ["/src", "/src/app", "/src/apple"];
+        assert_eq!(
+            longest_prefix("/src/app/main.rs", prefixes.into_iter()),
+            Some("/src/app")
+        );
+        assert_eq!(
+            longest_prefix("/src/lib/x.rs", prefixes.into_iter()),
+            Some("/src")
+        );
+        assert_eq!(
+            longest_prefix("/src/applesauce/x.rs", prefixes.into_iter()),
+            Some("/src")
+        );
+        assert_eq!(longest_prefix("/other/x.rs", prefixes.into_iter()), None);
+    }
+
+    #[test]
+    fn test_extend() {
+        let span = (
+            datetime!(2024-01-15 9:00 UTC),
+            datetime!(2024-01-15 9:30 UTC),
+        );
+        assert_eq!(
+            extend(span, datetime!(2024-01-15 9:10 UTC), 900),
+            Some(span)
+        );
+        assert_eq!(
+            extend(span, datetime!(2024-01-15 9:40 UTC), 900),
+            Some((span.0, datetime!(2024-01-15 9:40 UTC)))
+        );
+        assert_eq!(
+            extend(span, datetime!(2024-01-15 8:50 UTC), 900),
+            Some((datetime!(2024-01-15 8:50 UTC), span.1))
+        );
+        assert_eq!(extend(span, datetime!(2024-01-15 9:50 UTC), 900), None);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("1705309200.5").unwrap(),
+            datetime!(2024-01-15 9:00:00.5 UTC)
+        );
+        assert_eq!(
+            parse_time("2024-01-15T09:00:00Z").unwrap(),
+            datetime!(2024-01-15 9:00 UTC)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_joins_heartbeats_within_the_gap() {
+        let pool = testutil::pool().await;
+        let acme = testutil::project(&pool, "Acme").await;
+        let app = testutil::project(&pool, "Acme app").await;
+        add_rule(&pool, "/src/acme", &acme).await.unwrap();
+        add_rule(&pool, "/src/acme/app", &app).await.unwrap();
+        let beat = |file: &str, task: Option<&str>, time: &str| Heartbeat {
+            file: file.into(),
+            project: None,
+            task: task.map(String::from),
+            time: Some(time.into()),
+        };
+
+        let first = record(
+            &pool,
+            &beat("/src/acme/lib.rs", None, "2024-07-01T09:00:00Z"),
+        )
+        .await
+        .unwrap();
+        for time in [
+            "2024-07-01T09:10:00Z",
+            "2024-07-01T09:20:00Z",
+            "2024-07-01T08:50:00Z",
+        ] {
+            let id = record(&pool, &beat("/src/acme/lib.rs", None, time))
+                .await
+                .unwrap();
+            assert_eq!(id, first);
+        }
+        // Past the gap, on another task or in another project: new entries
+        let later = record(
+            &pool,
+            &beat("/src/acme/lib.rs", None, "2024-07-01T09:40:00Z"),
+        )
+        .await
+        .unwrap();
+        assert_ne!(later, first);
+        let review = record(
+            &pool,
+            &beat("/src/acme/lib.rs", Some("review"), "2024-07-01T09:05:00Z"),
+        )
+        .await
+        .unwrap();
+        let nested = record(
+            &pool,
+            &beat("/src/acme/app/ui.rs", None, "2024-07-01T09:05:00Z"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(testutil::count(&pool, "time_entries").await, 4);
+
+        let entry = |id: String| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_as::<_, (String, i64, String)>(
+                    "SELECT e.start_time, e.duration_seconds, t.project_id
+                     FROM time_entries e JOIN tasks t ON t.id = e.task_id WHERE e.id = ?",
+                )
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        let (start, duration, project) = entry(first).await;
+        assert_eq!(start, "2024-07-01T08:50:00Z");
+        assert_eq!(duration, 1800);
+        assert_eq!(project, acme);
+        assert_eq!(entry(review).await.1, 0);
+        assert_eq!(entry(nested).await.2, app);
+    }
+}
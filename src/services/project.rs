@@ -1,19 +1,95 @@
-use crate::{models::Project, services::timeutil::*};
+use crate::{
+    models::{FieldTarget, Project},
+    services::{self, audit, budget, timeutil::*, trash},
+};
+use serde::Deserialize;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
-pub async fn add(pool: &SqlitePool, name: &str, desc: Option<&str>) -> anyhow::Result<String> {
+/// A project to create with [`create`]
+#[derive(Debug, Default, Deserialize)]
+pub struct NewProject {
+    pub name: String,
+    pub description: Option<String>,
+    /// Create as a sub-project of this project
+    pub parent_id: Option<String>,
+    pub budget_hours: Option<f64>,
+    pub budget_amount: Option<f64>,
+    pub hourly_rate: Option<f64>,
+}
+
+/// Add a project with its budget as one operation, so a failure leaves nothing behind and a
+/// single `undo` takes it all back
+pub async fn create(pool: &SqlitePool, project: &NewProject) -> anyhow::Result<String> {
+    budget::check_budget(
+        project.budget_hours,
+        project.budget_amount,
+        project.hourly_rate,
+    )?;
+    let mut tx = audit::begin(pool, "project add", &project.name).await?;
+    let id = add_tx(
+        &mut tx,
+        &project.name,
+        project.description.as_deref(),
+        project.parent_id.as_deref(),
+    )
+    .await?;
+    set_budget_tx(
+        &mut tx,
+        &id,
+        project.budget_hours,
+        project.budget_amount,
+        project.hourly_rate,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub async fn add(
+    pool: &SqlitePool,
+    name: &str,
+    desc: Option<&str>,
+    parent_id: Option<&str>,
+) -> anyhow::Result<String> {
+    let mut tx = audit::begin(pool, "project add", name).await?;
+    let id = add_tx(&mut tx, name, desc, parent_id).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub async fn add_tx(
+    tx: &mut audit::Tx,
+    name: &str,
+    desc: Option<&str>,
+    parent_id: Option<&str>,
+) -> anyhow::Result<String> {
     let id = Uuid::now_v7().to_string();
     let now = to_rfc3339(now_utc());
-    sqlx::query("INSERT INTO projects(id,name,description,archived,created_at) VALUES(?,?,?,?,?)")
-        .bind(&id)
+    sqlx::query(
+        "INSERT INTO projects(id,name,description,archived,created_at,parent_id) VALUES(?,?,?,?,?,?)",
+    )
+    .bind(&id)
+    .bind(name)
+    .bind(desc)
+    .bind(0)
+    .bind(now)
+    .bind(parent_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(id)
+}
+
+/// The id of the project named `name`, adding it when there is none
+pub async fn find_or_add(pool: &SqlitePool, name: &str) -> anyhow::Result<String> {
+    let existing: Option<String> = sqlx::query_scalar("SELECT id FROM projects WHERE name = ?")
         .bind(name)
-        .bind(desc)
-        .bind(0)
-        .bind(now)
-        .execute(pool)
+        .fetch_optional(pool)
         .await?;
-    Ok(id)
+    match existing {
+        Some(id) => Ok(id),
+        None => add(pool, name, None, None).await,
+    }
 }
 
 pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<Project>> {
@@ -25,7 +101,11 @@ pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<Project>> {
           name as "name!",
           description,
           archived as "archived!: bool",
-          created_at as "created_at!"
+          created_at as "created_at!",
+          budget_seconds,
+          budget_amount,
+          hourly_rate,
+          parent_id
         FROM projects
         ORDER BY created_at
         "#
@@ -35,16 +115,386 @@ pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<Project>> {
     Ok(rows)
 }
 
+pub async fn edit(
+    pool: &SqlitePool,
+    id: &str,
+    name: Option<&str>,
+    desc: Option<&str>,
+) -> anyhow::Result<()> {
+    if name.is_none() && desc.is_none() {
+        return Ok(()); // nothing to update
+    }
+
+    let mut sets = vec![];
+    if name.is_some() {
+        sets.push("name = ?");
+    }
+    if desc.is_some() {
+        sets.push("description = ?");
+    }
+    let query = format!("UPDATE projects SET {} WHERE id = ?", sets.join(", "));
+
+    let mut q = sqlx::query(&query);
+    if let Some(n) = name {
+        q = q.bind(n);
+    }
+    if let Some(d) = desc {
+        q = q.bind(d);
+    }
+    let mut tx = audit::begin(pool, "project edit", id).await?;
+    q.bind(id).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Move a project under `parent_id`, or to the top level with `None`.
+/// A project can't be moved under itself or one of its sub-projects.
+pub async fn set_parent(
+    pool: &SqlitePool,
+    id: &str,
+    parent_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "project parent", id).await?;
+    if let Some(parent) = parent_id {
+        let cycle: bool = sqlx::query_scalar(
+            "WITH RECURSIVE ancestors(id) AS (
+               SELECT ?
+               UNION
+               SELECT p.parent_id FROM projects p JOIN ancestors a ON p.id = a.id
+               WHERE p.parent_id IS NOT NULL
+             )
+             SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?)",
+        )
+        .bind(parent)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if cycle {
+            anyhow::bail!("cannot move project {id} under itself or one of its sub-projects");
+        }
+    }
+    let updated = sqlx::query("UPDATE projects SET parent_id = ? WHERE id = ?")
+        .bind(parent_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    if updated.rows_affected() == 0 {
+        anyhow::bail!("no project {id}");
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Set the time and/or money budget of a project. `None` leaves a value untouched.
+pub async fn set_budget(
+    pool: &SqlitePool,
+    id: &str,
+    budget_hours: Option<f64>,
+    budget_amount: Option<f64>,
+    hourly_rate: Option<f64>,
+) -> anyhow::Result<()> {
+    if budget_hours.is_none() && budget_amount.is_none() && hourly_rate.is_none() {
+        return Ok(());
+    }
+    let mut tx = audit::begin(pool, "project budget", id).await?;
+    set_budget_tx(&mut tx, id, budget_hours, budget_amount, hourly_rate).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_budget_tx(
+    tx: &mut audit::Tx,
+    id: &str,
+    budget_hours: Option<f64>,
+    budget_amount: Option<f64>,
+    hourly_rate: Option<f64>,
+) -> anyhow::Result<()> {
+    budget::check_budget(budget_hours, budget_amount, hourly_rate)?;
+    let budget_seconds = budget_hours.map(hours_to_seconds);
+    sqlx::query!(
+        "UPDATE projects
+         SET budget_seconds = COALESCE(?, budget_seconds),
+             budget_amount = COALESCE(?, budget_amount),
+             hourly_rate = COALESCE(?, hourly_rate)
+         WHERE id = ?",
+        budget_seconds,
+        budget_amount,
+        hourly_rate,
+        id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Merge duplicate projects into `survivor` atomically. Their tasks move to the survivor, and
+/// tasks named like one of its own are merged into that one; sub-projects, repositories and
+/// heartbeat rules follow. The duplicates are then deleted. Returns the number of entries moved.
+pub async fn merge(
+    pool: &SqlitePool,
+    survivor: &str,
+    duplicates: &[String],
+) -> anyhow::Result<u64> {
+    let mut tx = audit::begin(pool, "project merge", survivor).await?;
+    let exists: Option<String> = sqlx::query_scalar("SELECT id FROM projects WHERE id = ?")
+        .bind(survivor)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if exists.is_none() {
+        anyhow::bail!("no project {survivor}");
+    }
+    let mut moved = 0;
+    for duplicate in duplicates {
+        if duplicate == survivor {
+            anyhow::bail!("cannot merge project {survivor} into itself");
+        }
+        let below: bool = sqlx::query_scalar(
+            "WITH RECURSIVE ancestors(id) AS (
+               SELECT ?
+               UNION
+               SELECT p.parent_id FROM projects p JOIN ancestors a ON p.id = a.id
+               WHERE p.parent_id IS NOT NULL
+             )
+             SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?)",
+        )
+        .bind(survivor)
+        .bind(duplicate)
+        .fetch_one(&mut *tx)
+        .await?;
+        if below {
+            anyhow::bail!("cannot merge project {duplicate} into one of its own sub-projects");
+        }
+        let exists: Option<String> = sqlx::query_scalar("SELECT id FROM projects WHERE id = ?")
+            .bind(duplicate)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            anyhow::bail!("no project {duplicate}");
+        }
+
+        // Deepest tasks first, so a task's subtasks are already in place when it moves or merges
+        let tasks: Vec<(String, String)> = sqlx::query_as(
+            "WITH RECURSIVE depth(id, level) AS (
+               SELECT id, 0 FROM tasks WHERE project_id = ? AND parent_id IS NULL
+               UNION ALL
+               SELECT t.id, d.level + 1 FROM tasks t JOIN depth d ON t.parent_id = d.id
+             )
+             SELECT t.id, t.name FROM depth d JOIN tasks t ON t.id = d.id
+             ORDER BY d.level DESC",
+        )
+        .bind(duplicate)
+        .fetch_all(&mut *tx)
+        .await?;
+        for (task_id, name) in tasks {
+            let same_name: Option<String> =
+                sqlx::query_scalar("SELECT id FROM tasks WHERE project_id = ? AND name = ?")
+                    .bind(survivor)
+                    .bind(&name)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            match same_name {
+                Some(existing) => {
+                    moved += services::task::merge_tx(&mut tx, &existing, &[task_id]).await?;
+                }
+                None => {
+                    moved += sqlx::query_scalar::<_, i64>(
+                        "SELECT COUNT(*) FROM time_entries WHERE task_id = ?",
+                    )
+                    .bind(&task_id)
+                    .fetch_one(&mut *tx)
+                    .await? as u64;
+                    sqlx::query("UPDATE tasks SET project_id = ? WHERE id = ?")
+                        .bind(survivor)
+                        .bind(&task_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+
+        for table in ["git_repos", "heartbeat_rules"] {
+            sqlx::query(&format!(
+                "UPDATE {table} SET project_id = ? WHERE project_id = ?"
+            ))
+            .bind(survivor)
+            .bind(duplicate)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // A branch mapped in both keeps the survivor's task; the other mapping goes with the project
+        sqlx::query("UPDATE OR IGNORE git_branches SET project_id = ? WHERE project_id = ?")
+            .bind(survivor)
+            .bind(duplicate)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE projects SET parent_id = ? WHERE parent_id = ?")
+            .bind(survivor)
+            .bind(duplicate)
+            .execute(&mut *tx)
+            .await?;
+        services::fields::merge_tx(&mut tx, FieldTarget::Project, survivor, duplicate).await?;
+        sqlx::query("DELETE FROM projects WHERE id = ?")
+            .bind(duplicate)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(moved)
+}
+
 pub async fn archive(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "project archive", id).await?;
     sqlx::query!("UPDATE projects SET archived = 1 WHERE id = ?", id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
     Ok(())
 }
 
 pub async fn delete(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
-    sqlx::query!("DELETE FROM projects WHERE id=?", id)
-        .execute(pool)
-        .await?;
+    let mut tx = audit::begin(pool, "project delete", id).await?;
+    trash::delete(&mut tx, trash::Kind::Project, id).await?;
+    tx.commit().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[tokio::test]
+    async fn test_create_is_one_operation() {
+        let pool = testutil::pool().await;
+        let project = NewProject {
+            name: "Acme".into(),
+            budget_hours: Some(10.0),
+            hourly_rate: Some(100.0),
+            ..NewProject::default()
+        };
+        create(&pool, &project).await.unwrap();
+        let projects = list(&pool).await.unwrap();
+        assert_eq!(projects[0].budget_seconds, Some(36000));
+        assert_eq!(audit::history(&pool, 10).await.unwrap().len(), 1);
+
+        audit::undo(&pool, 1).await.unwrap();
+        assert_eq!(testutil::count(&pool, "projects").await, 0);
+
+        let invalid = NewProject {
+            name: "Bad".into(),
+            budget_amount: Some(-1.0),
+            ..NewProject::default()
+        };
+        assert!(create(&pool, &invalid).await.is_err());
+        assert_eq!(testutil::count(&pool, "projects").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_merge_carries_everything_over() {
+        use crate::models::FieldType;
+        use services::{fields, task};
+
+        let pool = testutil::pool().await;
+        let survivor = testutil::project(&pool, "Acme").await;
+        let duplicate = testutil::project(&pool, "ACME").await;
+        let design = testutil::task(&pool, &survivor, "design").await;
+        let dup_design = testutil::task(&pool, &duplicate, "design").await;
+        let subtask = task::NewTask {
+            name: "mockups".into(),
+            parent_id: Some(dup_design.clone()),
+            ..Default::default()
+        };
+        let mockups = task::create(&pool, &duplicate, &subtask).await.unwrap();
+        let deploy = testutil::task(&pool, &duplicate, "deploy").await;
+        testutil::entry(
+            &pool,
+            &dup_design,
+            "2024-07-01T09:00:00Z",
+            "2024-07-01T10:00:00Z",
+        )
+        .await;
+        testutil::entry(
+            &pool,
+            &mockups,
+            "2024-07-01T10:00:00Z",
+            "2024-07-01T11:00:00Z",
+        )
+        .await;
+        sqlx::query(
+            "INSERT INTO git_branches(project_id, branch, task_id) VALUES(?, 'feature/ui', ?)",
+        )
+        .bind(&duplicate)
+        .bind(&dup_design)
+        .execute(&pool)
+        .await
+        .unwrap();
+        fields::add(&pool, "client", FieldTarget::Project, FieldType::Text, &[])
+            .await
+            .unwrap();
+        fields::add(&pool, "ticket", FieldTarget::Task, FieldType::Text, &[])
+            .await
+            .unwrap();
+        fields::set(&pool, "client", &duplicate, "Initech")
+            .await
+            .unwrap();
+        fields::set(&pool, "ticket", &dup_design, "UI-7")
+            .await
+            .unwrap();
+
+        assert_eq!(merge(&pool, &survivor, &[duplicate]).await.unwrap(), 2);
+
+        assert_eq!(testutil::count(&pool, "projects").await, 1);
+        let tasks = task::list(&pool, &survivor).await.unwrap();
+        let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&"deploy") && names.contains(&"mockups"));
+        assert!(tasks.iter().any(|t| t.id == deploy));
+        let mockups = tasks.iter().find(|t| t.id == mockups).unwrap();
+        assert_eq!(mockups.parent_id.as_deref(), Some(design.as_str()));
+        let on_design: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM time_entries WHERE task_id = ?")
+                .bind(&design)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(on_design, 1);
+        let branch: (String, String) = sqlx::query_as(
+            "SELECT project_id, task_id FROM git_branches WHERE branch = 'feature/ui'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(branch, (survivor.clone(), design.clone()));
+        let client = ("client".to_string(), "Initech".to_string());
+        assert_eq!(
+            fields::values(&pool, &survivor).await.unwrap(),
+            vec![client]
+        );
+        let ticket = ("ticket".to_string(), "UI-7".to_string());
+        assert_eq!(fields::values(&pool, &design).await.unwrap(), vec![ticket]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_refuses_conflicting_fields() {
+        use crate::models::FieldType;
+        use services::fields;
+
+        let pool = testutil::pool().await;
+        let survivor = testutil::project(&pool, "Acme").await;
+        let duplicate = testutil::project(&pool, "ACME").await;
+        fields::add(&pool, "client", FieldTarget::Project, FieldType::Text, &[])
+            .await
+            .unwrap();
+        fields::set(&pool, "client", &survivor, "Initech")
+            .await
+            .unwrap();
+        fields::set(&pool, "client", &duplicate, "Initrode")
+            .await
+            .unwrap();
+
+        let err = merge(&pool, &survivor, &[duplicate]).await.unwrap_err();
+        assert!(err.to_string().contains("client"));
+        assert_eq!(testutil::count(&pool, "projects").await, 2);
+        assert_eq!(testutil::count(&pool, "custom_values").await, 2);
+    }
+}
@@ -1,16 +1,408 @@
 // src/services/report.rs
+use crate::services::tree;
+use serde::Serialize;
 use sqlx::SqlitePool;
-pub async fn daily(pool: &SqlitePool, date: Option<&str>) -> anyhow::Result<Vec<(String, i64)>> {
-    if let Some(d) = date {
-        let rows = sqlx::query!("SELECT local_date, COALESCE(SUM(duration_seconds), 0) as 'total: i64' FROM time_entries WHERE end_time IS NOT NULL AND local_date=? GROUP BY local_date", d)
-            .fetch_all(pool).await?;
-        Ok(rows
-            .into_iter()
-            .map(|r| (r.local_date, r.total.unwrap_or(0)))
-            .collect())
-    } else {
-        let rows = sqlx::query!("SELECT local_date, COALESCE(SUM(duration_seconds), 0) as 'total: i64' FROM time_entries WHERE end_time IS NOT NULL GROUP BY local_date ORDER BY local_date DESC")
-            .fetch_all(pool).await?;
-        Ok(rows.into_iter().map(|r| (r.local_date, r.total)).collect())
+
+/// A single time entry with its task and project, as listed in reports
+#[derive(Debug, Serialize)]
+pub struct EntryLine {
+    pub id: String,
+    pub project_name: String,
+    pub task_name: String,
+    pub local_date: String,
+    pub start_time: String,
+    pub duration_seconds: Option<i64>,
+    pub note: Option<String>,
+}
+
+/// A project or task in a tree report, with the time tracked on it directly and including
+/// everything below it
+#[derive(Debug, Serialize)]
+pub struct TreeLine {
+    pub kind: &'static str,
+    pub id: String,
+    pub name: String,
+    pub depth: usize,
+    pub own_seconds: i64,
+    pub total_seconds: i64,
+}
+
+/// A tree report node before the roll-up
+struct Node {
+    kind: &'static str,
+    id: String,
+    name: String,
+    parent: Option<String>,
+    own: i64,
+}
+
+impl Node {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+}
+
+fn tree_lines(nodes: &[Node]) -> Vec<TreeLine> {
+    let (id, parent) = (Node::id, Node::parent);
+    let totals = tree::roll_up(nodes, id, parent, |n| n.own);
+    tree::walk(nodes, id, parent)
+        .into_iter()
+        .map(|(depth, n)| TreeLine {
+            kind: n.kind,
+            id: n.id.clone(),
+            name: n.name.clone(),
+            depth,
+            own_seconds: n.own,
+            total_seconds: totals[&n.id],
+        })
+        .collect()
+}
+
+/// Daily totals, newest first, or the total of one day. `fields` is a custom field filter
+/// from [`crate::services::fields::filter`]; the other reports take it too.
+pub async fn daily(
+    pool: &SqlitePool,
+    date: Option<&str>,
+    fields: Option<&str>,
+) -> anyhow::Result<Vec<(String, i64)>> {
+    let rows = sqlx::query!(
+        r#"SELECT e.local_date, COALESCE(SUM(e.duration_seconds), 0) as "total!: i64"
+           FROM time_entries e
+           WHERE e.end_time IS NOT NULL
+             AND (? IS NULL OR e.local_date = ?)
+             AND NOT EXISTS (
+                   SELECT 1 FROM json_each(?) f
+                   WHERE (json_extract(f.value, '$[0]'), json_extract(f.value, '$[1]'))
+                     NOT IN (SELECT field, value FROM entry_field_values WHERE entry_id = e.id))
+           GROUP BY e.local_date ORDER BY e.local_date DESC"#,
+        date,
+        date,
+        fields
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.local_date, r.total)).collect())
+}
+
+/// Daily totals between two dates (inclusive), oldest first. Days without entries are omitted.
+pub async fn daily_range(
+    pool: &SqlitePool,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<Vec<(String, i64)>> {
+    let rows = sqlx::query!(
+        r#"SELECT local_date, COALESCE(SUM(duration_seconds), 0) as "total!: i64"
+           FROM time_entries
+           WHERE end_time IS NOT NULL AND local_date >= ? AND local_date <= ?
+           GROUP BY local_date ORDER BY local_date"#,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.local_date, r.total)).collect())
+}
+
+/// Total tracked on a project, including its sub-projects
+pub async fn project(
+    pool: &SqlitePool,
+    project_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> anyhow::Result<i64> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(e.duration_seconds), 0) as "total!: i64"
+           FROM time_entries e JOIN tasks t ON t.id = e.task_id
+           WHERE t.project_id IN (
+                   WITH RECURSIVE sub(id) AS (
+                     SELECT ? UNION SELECT p.id FROM projects p JOIN sub ON p.parent_id = sub.id
+                   )
+                   SELECT id FROM sub
+                 )
+             AND e.end_time IS NOT NULL
+             AND (? IS NULL OR e.local_date >= ?)
+             AND (? IS NULL OR e.local_date <= ?)"#,
+        project_id,
+        from,
+        from,
+        to,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(total)
+}
+
+/// Total tracked on a task, including its subtasks
+pub async fn task(
+    pool: &SqlitePool,
+    task_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> anyhow::Result<i64> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(duration_seconds), 0) as "total!: i64"
+           FROM time_entries
+           WHERE task_id IN (
+                   WITH RECURSIVE sub(id) AS (
+                     SELECT ? UNION SELECT t.id FROM tasks t JOIN sub ON t.parent_id = sub.id
+                   )
+                   SELECT id FROM sub
+                 )
+             AND end_time IS NOT NULL
+             AND (? IS NULL OR local_date >= ?)
+             AND (? IS NULL OR local_date <= ?)"#,
+        task_id,
+        from,
+        from,
+        to,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(total)
+}
+
+/// A project with its tasks and sub-projects, each with its own time and the time rolled up
+/// from below. Tasks come before the sub-projects of a project.
+pub async fn project_tree(
+    pool: &SqlitePool,
+    project_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    fields: Option<&str>,
+) -> anyhow::Result<Vec<TreeLine>> {
+    let projects = sqlx::query!(
+        r#"WITH RECURSIVE sub(id) AS (
+             SELECT ? UNION SELECT p.id FROM projects p JOIN sub ON p.parent_id = sub.id
+           )
+           SELECT p.id as "id!", p.name, p.parent_id FROM projects p
+           WHERE p.id IN (SELECT id FROM sub)
+           ORDER BY p.created_at"#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tasks = sqlx::query!(
+        r#"WITH RECURSIVE sub(id) AS (
+             SELECT ? UNION SELECT p.id FROM projects p JOIN sub ON p.parent_id = sub.id
+           )
+           SELECT t.id as "id!", t.name, t.parent_id, t.project_id,
+                  COALESCE(SUM(e.duration_seconds), 0) as "own!: i64"
+           FROM tasks t
+           LEFT JOIN time_entries e ON e.task_id = t.id AND e.end_time IS NOT NULL
+             AND (? IS NULL OR e.local_date >= ?)
+             AND (? IS NULL OR e.local_date <= ?)
+             AND NOT EXISTS (
+                   SELECT 1 FROM json_each(?) f
+                   WHERE (json_extract(f.value, '$[0]'), json_extract(f.value, '$[1]'))
+                     NOT IN (SELECT field, value FROM entry_field_values WHERE entry_id = e.id))
+           WHERE t.project_id IN (SELECT id FROM sub)
+           GROUP BY t.id
+           ORDER BY t.created_at"#,
+        project_id,
+        from,
+        from,
+        to,
+        to,
+        fields
+    )
+    .fetch_all(pool)
+    .await?;
+    if projects.is_empty() {
+        anyhow::bail!("no project {project_id}");
+    }
+
+    let mut nodes: Vec<Node> = tasks
+        .into_iter()
+        .map(|t| Node {
+            kind: "task",
+            id: t.id,
+            name: t.name,
+            parent: Some(t.parent_id.unwrap_or(t.project_id)),
+            own: t.own,
+        })
+        .collect();
+    nodes.extend(projects.into_iter().map(|p| Node {
+        kind: "project",
+        // The requested project is the root even when it has a parent
+        parent: p.parent_id.filter(|_| p.id != project_id),
+        id: p.id,
+        name: p.name,
+        own: 0,
+    }));
+    Ok(tree_lines(&nodes))
+}
+
+/// A task with its subtasks, each with its own time and the time rolled up from below
+pub async fn task_tree(
+    pool: &SqlitePool,
+    task_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    fields: Option<&str>,
+) -> anyhow::Result<Vec<TreeLine>> {
+    let tasks = sqlx::query!(
+        r#"WITH RECURSIVE sub(id) AS (
+             SELECT ? UNION SELECT t.id FROM tasks t JOIN sub ON t.parent_id = sub.id
+           )
+           SELECT t.id as "id!", t.name, t.parent_id,
+                  COALESCE(SUM(e.duration_seconds), 0) as "own!: i64"
+           FROM tasks t
+           LEFT JOIN time_entries e ON e.task_id = t.id AND e.end_time IS NOT NULL
+             AND (? IS NULL OR e.local_date >= ?)
+             AND (? IS NULL OR e.local_date <= ?)
+             AND NOT EXISTS (
+                   SELECT 1 FROM json_each(?) f
+                   WHERE (json_extract(f.value, '$[0]'), json_extract(f.value, '$[1]'))
+                     NOT IN (SELECT field, value FROM entry_field_values WHERE entry_id = e.id))
+           WHERE t.id IN (SELECT id FROM sub)
+           GROUP BY t.id
+           ORDER BY t.created_at"#,
+        task_id,
+        from,
+        from,
+        to,
+        to,
+        fields
+    )
+    .fetch_all(pool)
+    .await?;
+    if tasks.is_empty() {
+        anyhow::bail!("no task {task_id}");
+    }
+    let nodes: Vec<Node> = tasks
+        .into_iter()
+        .map(|t| Node {
+            kind: "task",
+            parent: t.parent_id.filter(|_| t.id != task_id),
+            id: t.id,
+            name: t.name,
+            own: t.own,
+        })
+        .collect();
+    Ok(tree_lines(&nodes))
+}
+
+/// Entries between two dates (inclusive), optionally for one task, oldest first
+pub async fn entries(
+    pool: &SqlitePool,
+    task_id: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    fields: Option<&str>,
+) -> anyhow::Result<Vec<EntryLine>> {
+    let rows = sqlx::query_as!(
+        EntryLine,
+        r#"SELECT e.id as "id!", p.name as project_name, t.name as task_name,
+                  e.local_date, e.start_time, e.duration_seconds, e.note
+           FROM time_entries e
+           JOIN tasks t ON t.id = e.task_id
+           JOIN projects p ON p.id = t.project_id
+           WHERE (? IS NULL OR e.task_id = ?)
+             AND (? IS NULL OR e.local_date >= ?)
+             AND (? IS NULL OR e.local_date <= ?)
+             AND NOT EXISTS (
+                   SELECT 1 FROM json_each(?) f
+                   WHERE (json_extract(f.value, '$[0]'), json_extract(f.value, '$[1]'))
+                     NOT IN (SELECT field, value FROM entry_field_values WHERE entry_id = e.id))
+           ORDER BY e.start_time"#,
+        task_id,
+        task_id,
+        from,
+        from,
+        to,
+        to,
+        fields
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{FieldTarget, FieldType},
+        services::fields,
+        testutil,
+    };
+
+    #[tokio::test]
+    async fn test_daily_filters_on_inherited_fields() {
+        let pool = testutil::pool().await;
+        let acme = testutil::project(&pool, "Acme").await;
+        let tools = testutil::project(&pool, "Tools").await;
+        let build = testutil::task(&pool, &acme, "build").await;
+        let meet = testutil::task(&pool, &acme, "meetings").await;
+        let chores = testutil::task(&pool, &tools, "chores").await;
+        let first = testutil::entry(
+            &pool,
+            &build,
+            "2024-07-01T09:00:00Z",
+            "2024-07-01T10:00:00Z",
+        )
+        .await;
+        testutil::entry(
+            &pool,
+            &build,
+            "2024-07-02T09:00:00Z",
+            "2024-07-02T09:30:00Z",
+        )
+        .await;
+        testutil::entry(&pool, &meet, "2024-07-01T11:00:00Z", "2024-07-01T11:15:00Z").await;
+        testutil::entry(
+            &pool,
+            &chores,
+            "2024-07-01T12:00:00Z",
+            "2024-07-01T14:00:00Z",
+        )
+        .await;
+
+        fields::add(&pool, "client", FieldTarget::Project, FieldType::Text, &[])
+            .await
+            .unwrap();
+        fields::add(&pool, "billable", FieldTarget::Task, FieldType::Text, &[])
+            .await
+            .unwrap();
+        fields::add(&pool, "focus", FieldTarget::Entry, FieldType::Number, &[])
+            .await
+            .unwrap();
+        fields::set(&pool, "client", &acme, "Initech")
+            .await
+            .unwrap();
+        fields::set(&pool, "billable", &build, "yes").await.unwrap();
+        fields::set(&pool, "focus", &first, "3").await.unwrap();
+
+        let daily_with = |filters: &[&str]| {
+            let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+            let pool = pool.clone();
+            async move {
+                let filter = fields::filter(&pool, &filters).await.unwrap();
+                daily(&pool, None, filter.as_deref()).await.unwrap()
+            }
+        };
+        let day = |date: &str, seconds: i64| (date.to_string(), seconds);
+
+        assert_eq!(
+            daily_with(&[]).await,
+            [day("2024-07-02", 1800), day("2024-07-01", 11700)]
+        );
+        // The project's value applies to the entries of all its tasks
+        assert_eq!(
+            daily_with(&["client=Initech"]).await,
+            [day("2024-07-02", 1800), day("2024-07-01", 4500)]
+        );
+        // Every pair must match, each through the entry, its task or its project
+        assert_eq!(
+            daily_with(&["client=Initech", "billable=yes", "focus=3.0"]).await,
+            [day("2024-07-01", 3600)]
+        );
+        assert!(daily_with(&["client=Initrode"]).await.is_empty());
     }
 }
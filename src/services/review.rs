@@ -0,0 +1,159 @@
+use crate::services::{audit, timeutil::*, trash};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// A stopped entry that ran longer than the maximum session length
+#[derive(Debug)]
+pub struct FlaggedEntry {
+    pub id: String,
+    pub project_name: String,
+    pub task_name: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub duration_seconds: i64,
+}
+
+/// Entries awaiting review, oldest first
+pub async fn flagged(pool: &SqlitePool) -> anyhow::Result<Vec<FlaggedEntry>> {
+    let rows = sqlx::query!(
+        r#"SELECT e.id as "id!", p.name as project_name, t.name as task_name,
+                  e.start_time, e.end_time as "end_time!", e.duration_seconds as "duration_seconds!"
+           FROM time_entries e
+           JOIN tasks t ON t.id = e.task_id
+           JOIN projects p ON p.id = t.project_id
+           WHERE e.flagged = 1 AND e.end_time IS NOT NULL
+           ORDER BY e.start_time"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| FlaggedEntry {
+            id: r.id,
+            project_name: r.project_name,
+            task_name: r.task_name,
+            start_time: r.start_time,
+            end_time: r.end_time,
+            duration_seconds: r.duration_seconds,
+        })
+        .collect())
+}
+
+/// Start and end of a stopped entry
+async fn span(tx: &mut audit::Tx, id: &str) -> anyhow::Result<(OffsetDateTime, OffsetDateTime)> {
+    let r = sqlx::query!(
+        "SELECT start_time, end_time FROM time_entries WHERE id = ?",
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("time entry {id} not found"))?;
+    let end = r
+        .end_time
+        .ok_or_else(|| anyhow::anyhow!("time entry {id} is still running"))?;
+    Ok((parse_rfc3339(&r.start_time)?, parse_rfc3339(&end)?))
+}
+
+/// Start time of an entry, used to resolve bare `HH:MM` arguments against its day
+pub async fn start_time(pool: &SqlitePool, id: &str) -> anyhow::Result<OffsetDateTime> {
+    let start: String = sqlx::query_scalar!("SELECT start_time FROM time_entries WHERE id = ?", id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("time entry {id} not found"))?;
+    parse_rfc3339(&start)
+}
+
+/// Accept the entry as recorded
+pub async fn keep(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "review keep", id).await?;
+    let updated = sqlx::query!("UPDATE time_entries SET flagged = 0 WHERE id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    if updated.rows_affected() == 0 {
+        anyhow::bail!("time entry {id} not found");
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Move the end of an entry back to `end`
+pub async fn trim(pool: &SqlitePool, id: &str, end: OffsetDateTime) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "review trim", id).await?;
+    let (start, old_end) = span(&mut tx, id).await?;
+    if end <= start || end > old_end {
+        anyhow::bail!(
+            "new end must be after {} and not after {}",
+            to_rfc3339(start),
+            to_rfc3339(old_end)
+        );
+    }
+    let end_s = to_rfc3339(end);
+    let dur = (end - start).whole_seconds();
+    sqlx::query!(
+        "UPDATE time_entries SET end_time = ?, duration_seconds = ?, flagged = 0 WHERE id = ?",
+        end_s,
+        dur,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Split an entry in two at `at`; returns the id of the second part
+pub async fn split(pool: &SqlitePool, id: &str, at: OffsetDateTime) -> anyhow::Result<String> {
+    let mut tx = audit::begin(pool, "review split", id).await?;
+    let (start, end) = span(&mut tx, id).await?;
+    if at <= start || at >= end {
+        anyhow::bail!(
+            "split point must be between {} and {}",
+            to_rfc3339(start),
+            to_rfc3339(end)
+        );
+    }
+
+    let at_s = to_rfc3339(at);
+    let first = (at - start).whole_seconds();
+    sqlx::query!(
+        "UPDATE time_entries SET end_time = ?, duration_seconds = ?, flagged = 0 WHERE id = ?",
+        at_s,
+        first,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let new_id = Uuid::now_v7().to_string();
+    let end_s = to_rfc3339(end);
+    let second = (end - at).whole_seconds();
+    let date = local_date_str(at);
+    let now = to_rfc3339(now_utc());
+    sqlx::query!(
+        "INSERT INTO time_entries(id, task_id, start_time, end_time, duration_seconds, local_date, created_at)
+         SELECT ?, task_id, ?, ?, ?, ?, ? FROM time_entries WHERE id = ?",
+        new_id,
+        at_s,
+        end_s,
+        second,
+        date,
+        now,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(new_id)
+}
+
+/// Move a stopped entry to the trash
+pub async fn discard(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "review discard", id).await?;
+    span(&mut tx, id).await?;
+    trash::delete(&mut tx, trash::Kind::Entry, id).await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::services::report::EntryLine;
+use sqlx::SqlitePool;
+
+/// Turn free text into an FTS5 query: every word must match, as a prefix.
+/// Words are quoted so punctuation in user input can't break the query syntax.
+pub fn fts_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Entries whose note, task or project matches `query`, best matches first.
+///
+/// A match on a project or task name/description returns all of its entries.
+pub async fn entries(pool: &SqlitePool, query: &str, limit: i64) -> anyhow::Result<Vec<EntryLine>> {
+    let Some(fts) = fts_query(query) else {
+        return Ok(vec![]);
+    };
+    let rows = sqlx::query_as!(
+        EntryLine,
+        r#"WITH hits AS MATERIALIZED (
+             SELECT kind, ref_id, rank
+             FROM search_index WHERE search_index MATCH ?
+           )
+           SELECT e.id as "id!", p.name as "project_name!", t.name as "task_name!",
+                  e.local_date as "local_date!", e.start_time as "start_time!",
+                  e.duration_seconds, e.note
+           FROM time_entries e
+           JOIN tasks t ON t.id = e.task_id
+           JOIN projects p ON p.id = t.project_id
+           JOIN hits h ON (h.kind = 'entry' AND h.ref_id = e.id)
+                       OR (h.kind = 'task' AND h.ref_id = t.id)
+                       OR (h.kind = 'project' AND h.ref_id = p.id)
+           GROUP BY e.id
+           ORDER BY MIN(h.rank), e.start_time DESC
+           LIMIT ?"#,
+        fts,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(
+            fts_query("login bug").as_deref(),
+            Some("\"login\"* \"bug\"*")
+        );
+        assert_eq!(fts_query("  march  ").as_deref(), Some("\"march\"*"));
+        assert_eq!(
+            fts_query("say \"hi\"").as_deref(),
+            Some("\"say\"* \"\"\"hi\"\"\"*")
+        );
+        assert_eq!(fts_query("   "), None);
+    }
+}
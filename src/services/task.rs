@@ -1,46 +1,168 @@
-use crate::services::timeutil::*;
+use crate::{
+    models::{FieldTarget, Priority, Task, TaskSort, TaskStatus},
+    services::{audit, budget, fields, timeutil::*, trash},
+};
+use serde::Deserialize;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
-#[derive(Debug)]
-pub struct Task {
-    pub id: String,
-    pub project_id: String,
+/// Which tasks `task list` shows; empty lists match everything
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub statuses: Vec<TaskStatus>,
+    pub priorities: Vec<Priority>,
+    /// Due on or before this date (YYYY-MM-DD)
+    pub due_by: Option<String>,
+    /// Past due and not done
+    pub overdue: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, task: &Task, today: &str) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&task.status))
+            && (self.priorities.is_empty() || self.priorities.contains(&task.priority))
+            && self
+                .due_by
+                .as_deref()
+                .is_none_or(|by| task.due_date.as_deref().is_some_and(|due| due <= by))
+            && (!self.overdue || is_overdue(task, today))
+    }
+}
+
+/// Due before `today` (YYYY-MM-DD) and not done
+pub fn is_overdue(task: &Task, today: &str) -> bool {
+    task.status != TaskStatus::Done && task.due_date.as_deref().is_some_and(|due| due < today)
+}
+
+/// Sort tasks in place; ties keep their creation order
+pub fn sort(tasks: &mut [Task], by: TaskSort) {
+    match by {
+        TaskSort::Created => {}
+        TaskSort::Status => tasks.sort_by_key(|t| t.status),
+        TaskSort::Priority => tasks.sort_by_key(|t| std::cmp::Reverse(t.priority)),
+        TaskSort::Due => tasks.sort_by(|a, b| match (&a.due_date, &b.due_date) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        }),
+    }
+}
+
+/// Fail unless `parent_id` is a task of `project_id`
+async fn check_parent<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    project_id: &str,
+    parent_id: &str,
+) -> anyhow::Result<()> {
+    let parent_project: Option<String> =
+        sqlx::query_scalar("SELECT project_id FROM tasks WHERE id = ?")
+            .bind(parent_id)
+            .fetch_optional(db)
+            .await?;
+    match parent_project {
+        None => anyhow::bail!("no task {parent_id}"),
+        Some(p) if p != project_id => {
+            anyhow::bail!("a subtask must be in the same project as its parent task")
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// A task to create with [`create`]
+#[derive(Debug, Default, Deserialize)]
+pub struct NewTask {
     pub name: String,
     pub description: Option<String>,
-    pub archived: bool,
-    pub created_at: String,
+    /// Create as a subtask of this task of the project
+    pub parent_id: Option<String>,
+    pub estimate_hours: Option<f64>,
+    pub priority: Option<Priority>,
+    /// YYYY-MM-DD
+    pub due_date: Option<String>,
 }
 
-/// Add a new task under a project
-pub async fn add(
-    pool: &SqlitePool,
+/// Add a task with its estimate, priority and due date as one operation, so a failure leaves
+/// nothing behind and a single `undo` takes it all back
+pub async fn create(pool: &SqlitePool, project_id: &str, task: &NewTask) -> anyhow::Result<String> {
+    budget::check_amount("estimate", task.estimate_hours)?;
+    if let Some(due) = &task.due_date {
+        parse_date(due)?;
+    }
+    let mut tx = audit::begin(pool, "task add", &task.name).await?;
+    let id = add_tx(
+        &mut tx,
+        project_id,
+        &task.name,
+        task.description.as_deref(),
+        task.parent_id.as_deref(),
+    )
+    .await?;
+    if let Some(hours) = task.estimate_hours {
+        set_estimate_tx(&mut tx, &id, hours).await?;
+    }
+    if let Some(priority) = task.priority {
+        set_priority_tx(&mut tx, &id, priority).await?;
+    }
+    if task.due_date.is_some() {
+        set_due_tx(&mut tx, &id, task.due_date.as_deref()).await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Add a new task under a project, optionally as a subtask of another task of that project
+pub async fn add_tx(
+    tx: &mut audit::Tx,
     project_id: &str,
     name: &str,
     desc: Option<&str>,
+    parent_id: Option<&str>,
 ) -> anyhow::Result<String> {
+    if let Some(parent) = parent_id {
+        check_parent(&mut **tx, project_id, parent).await?;
+    }
     let id = Uuid::now_v7().to_string();
     let now = to_rfc3339(now_utc());
-
     sqlx::query!(
-        "INSERT INTO tasks (id, project_id, name, description, archived, created_at)
-         VALUES (?, ?, ?, ?, 0, ?)",
+        "INSERT INTO tasks (id, project_id, name, description, archived, created_at, parent_id)
+         VALUES (?, ?, ?, ?, 0, ?, ?)",
         id,
         project_id,
         name,
         desc,
-        now
+        now,
+        parent_id
     )
-    .execute(pool)
+    .execute(&mut **tx)
     .await?;
-
     Ok(id)
 }
 
+/// The id of the task named `name` in a project, adding it when there is none
+pub async fn find_or_add_tx(
+    tx: &mut audit::Tx,
+    project_id: &str,
+    name: &str,
+) -> anyhow::Result<String> {
+    let existing: Option<String> =
+        sqlx::query_scalar("SELECT id FROM tasks WHERE project_id = ? AND name = ?")
+            .bind(project_id)
+            .bind(name)
+            .fetch_optional(&mut **tx)
+            .await?;
+    match existing {
+        Some(id) => Ok(id),
+        None => add_tx(tx, project_id, name, None, None).await,
+    }
+}
+
 /// List all tasks for a project
 pub async fn list(pool: &SqlitePool, project_id: &str) -> anyhow::Result<Vec<Task>> {
     let rows = sqlx::query!(
-        r#"SELECT id, project_id, name, description, archived as "archived: bool", created_at
+        r#"SELECT id, project_id, name, description, archived as "archived: bool", created_at,
+                  estimate_seconds, parent_id, status as "status: TaskStatus",
+                  priority as "priority: Priority", due_date
            FROM tasks WHERE project_id = ? ORDER BY created_at"#,
         project_id
     )
@@ -56,10 +178,44 @@ pub async fn list(pool: &SqlitePool, project_id: &str) -> anyhow::Result<Vec<Tas
             description: r.description,
             archived: r.archived,
             created_at: r.created_at,
+            estimate_seconds: r.estimate_seconds,
+            parent_id: r.parent_id,
+            status: r.status,
+            priority: r.priority,
+            due_date: r.due_date,
         })
         .collect())
 }
 
+/// A task offered by the interactive picker
+#[derive(Debug)]
+pub struct PickableTask {
+    pub id: String,
+    pub name: String,
+    pub project_name: String,
+    pub last_used: Option<String>,
+    pub entries: i64,
+}
+
+/// Non-archived tasks of non-archived projects, most recently used first
+pub async fn pickable(pool: &SqlitePool) -> anyhow::Result<Vec<PickableTask>> {
+    let rows = sqlx::query_as!(
+        PickableTask,
+        r#"SELECT t.id as "id!", t.name as "name!", p.name as "project_name!",
+                  MAX(e.start_time) as "last_used: String",
+                  COUNT(e.id) as "entries!: i64"
+           FROM tasks t
+           JOIN projects p ON p.id = t.project_id
+           LEFT JOIN time_entries e ON e.task_id = t.id
+           WHERE t.archived = 0 AND p.archived = 0
+           GROUP BY t.id
+           ORDER BY MAX(e.start_time) IS NULL, MAX(e.start_time) DESC, p.name, t.name"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
 /// Edit a task's name/description
 pub async fn edit(
     pool: &SqlitePool,
@@ -91,22 +247,420 @@ pub async fn edit(
     }
     q = q.bind(id);
 
-    q.execute(pool).await?;
+    let mut tx = audit::begin(pool, "task edit", id).await?;
+    q.execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Make a task a subtask of `parent_id`, or a top-level task with `None`.
+/// The parent must be in the same project and can't be the task itself or one of its subtasks.
+pub async fn set_parent(
+    pool: &SqlitePool,
+    id: &str,
+    parent_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task parent", id).await?;
+    let project_id: String = sqlx::query_scalar("SELECT project_id FROM tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("no task {id}"))?;
+    if let Some(parent) = parent_id {
+        check_parent(&mut *tx, &project_id, parent).await?;
+        let cycle: bool = sqlx::query_scalar(
+            "WITH RECURSIVE ancestors(id) AS (
+               SELECT ?
+               UNION
+               SELECT t.parent_id FROM tasks t JOIN ancestors a ON t.id = a.id
+               WHERE t.parent_id IS NOT NULL
+             )
+             SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?)",
+        )
+        .bind(parent)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if cycle {
+            anyhow::bail!("cannot make task {id} a subtask of itself or one of its subtasks");
+        }
+    }
+    sqlx::query("UPDATE tasks SET parent_id = ? WHERE id = ?")
+        .bind(parent_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Set the estimated effort of a task, in hours
+pub async fn set_estimate(pool: &SqlitePool, id: &str, hours: f64) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task estimate", id).await?;
+    set_estimate_tx(&mut tx, id, hours).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_estimate_tx(tx: &mut audit::Tx, id: &str, hours: f64) -> anyhow::Result<()> {
+    budget::check_amount("estimate", Some(hours))?;
+    let secs = hours_to_seconds(hours);
+    sqlx::query!(
+        "UPDATE tasks SET estimate_seconds = ? WHERE id = ?",
+        secs,
+        id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Move a task to another workflow status
+pub async fn set_status(pool: &SqlitePool, id: &str, status: TaskStatus) -> anyhow::Result<()> {
+    let status = status.as_str();
+    let mut tx = audit::begin(pool, "task status", id).await?;
+    let updated = sqlx::query!("UPDATE tasks SET status = ? WHERE id = ?", status, id)
+        .execute(&mut *tx)
+        .await?;
+    if updated.rows_affected() == 0 {
+        anyhow::bail!("no task {id}");
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_priority(pool: &SqlitePool, id: &str, priority: Priority) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task priority", id).await?;
+    set_priority_tx(&mut tx, id, priority).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_priority_tx(
+    tx: &mut audit::Tx,
+    id: &str,
+    priority: Priority,
+) -> anyhow::Result<()> {
+    let priority = priority as i32;
+    sqlx::query!("UPDATE tasks SET priority = ? WHERE id = ?", priority, id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Set the due date (YYYY-MM-DD) of a task; `None` clears it
+pub async fn set_due(pool: &SqlitePool, id: &str, due: Option<&str>) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task due", id).await?;
+    set_due_tx(&mut tx, id, due).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_due_tx(tx: &mut audit::Tx, id: &str, due: Option<&str>) -> anyhow::Result<()> {
+    let due = due.map(parse_date).transpose()?.map(format_date);
+    sqlx::query!("UPDATE tasks SET due_date = ? WHERE id = ?", due, id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Move a task and its subtasks inside a transaction; fails when a task of the same name is
+/// already in the target project
+async fn move_tx(tx: &mut audit::Tx, task_id: &str, project_id: &str) -> anyhow::Result<()> {
+    let subtree: Vec<(String, String)> = sqlx::query_as(
+        "WITH RECURSIVE sub(id) AS (
+           SELECT ? UNION SELECT t.id FROM tasks t JOIN sub ON t.parent_id = sub.id
+         )
+         SELECT t.id, t.name FROM tasks t WHERE t.id IN (SELECT id FROM sub)",
+    )
+    .bind(task_id)
+    .fetch_all(&mut **tx)
+    .await?;
+    for (id, name) in subtree {
+        let clash: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM tasks WHERE project_id = ? AND name = ? AND id <> ?",
+        )
+        .bind(project_id)
+        .bind(&name)
+        .bind(&id)
+        .fetch_optional(&mut **tx)
+        .await?;
+        if let Some(clash) = clash {
+            anyhow::bail!(
+                "the project already has a task named '{name}' ({clash}); use `task merge` to combine them"
+            );
+        }
+        sqlx::query("UPDATE tasks SET project_id = ? WHERE id = ?")
+            .bind(project_id)
+            .bind(&id)
+            .execute(&mut **tx)
+            .await?;
+    }
     Ok(())
 }
 
+/// Move a task with its subtasks to another project, where it becomes a top-level task
+pub async fn move_to(pool: &SqlitePool, id: &str, project_id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task move", id).await?;
+    let project: Option<String> = sqlx::query_scalar("SELECT id FROM projects WHERE id = ?")
+        .bind(project_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if project.is_none() {
+        anyhow::bail!("no project {project_id}");
+    }
+    let task: Option<String> = sqlx::query_scalar("SELECT id FROM tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if task.is_none() {
+        anyhow::bail!("no task {id}");
+    }
+    move_tx(&mut tx, id, project_id).await?;
+    sqlx::query("UPDATE tasks SET parent_id = NULL WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Merge `duplicates` into `survivor` inside a transaction: their entries, branch mappings and
+/// subtasks go to the survivor and they are deleted. Returns the number of entries moved.
+pub async fn merge_tx(
+    tx: &mut audit::Tx,
+    survivor: &str,
+    duplicates: &[String],
+) -> anyhow::Result<u64> {
+    let project_id: String = sqlx::query_scalar("SELECT project_id FROM tasks WHERE id = ?")
+        .bind(survivor)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("no task {survivor}"))?;
+    let mut moved = 0;
+    for duplicate in duplicates {
+        if duplicate == survivor {
+            anyhow::bail!("cannot merge task {survivor} into itself");
+        }
+        let below: bool = sqlx::query_scalar(
+            "WITH RECURSIVE ancestors(id) AS (
+               SELECT ?
+               UNION
+               SELECT t.parent_id FROM tasks t JOIN ancestors a ON t.id = a.id
+               WHERE t.parent_id IS NOT NULL
+             )
+             SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?)",
+        )
+        .bind(survivor)
+        .bind(duplicate)
+        .fetch_one(&mut **tx)
+        .await?;
+        if below {
+            anyhow::bail!("cannot merge task {duplicate} into one of its own subtasks");
+        }
+        let exists: Option<String> = sqlx::query_scalar("SELECT id FROM tasks WHERE id = ?")
+            .bind(duplicate)
+            .fetch_optional(&mut **tx)
+            .await?;
+        if exists.is_none() {
+            anyhow::bail!("no task {duplicate}");
+        }
+
+        moved += sqlx::query("UPDATE time_entries SET task_id = ? WHERE task_id = ?")
+            .bind(survivor)
+            .bind(duplicate)
+            .execute(&mut **tx)
+            .await?
+            .rows_affected();
+        sqlx::query("UPDATE git_branches SET task_id = ? WHERE task_id = ?")
+            .bind(survivor)
+            .bind(duplicate)
+            .execute(&mut **tx)
+            .await?;
+        let children: Vec<(String, String)> =
+            sqlx::query_as("SELECT id, project_id FROM tasks WHERE parent_id = ?")
+                .bind(duplicate)
+                .fetch_all(&mut **tx)
+                .await?;
+        for (child, child_project) in children {
+            if child_project != project_id {
+                move_tx(tx, &child, &project_id).await?;
+            }
+            sqlx::query("UPDATE tasks SET parent_id = ? WHERE id = ?")
+                .bind(survivor)
+                .bind(&child)
+                .execute(&mut **tx)
+                .await?;
+        }
+        fields::merge_tx(tx, FieldTarget::Task, survivor, duplicate).await?;
+        sqlx::query("DELETE FROM tasks WHERE id = ?")
+            .bind(duplicate)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(moved)
+}
+
+/// Merge duplicate tasks into `survivor` atomically; returns the number of entries moved
+pub async fn merge(
+    pool: &SqlitePool,
+    survivor: &str,
+    duplicates: &[String],
+) -> anyhow::Result<u64> {
+    let mut tx = audit::begin(pool, "task merge", survivor).await?;
+    let moved = merge_tx(&mut tx, survivor, duplicates).await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
 /// Archive a task
 pub async fn archive(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "task archive", id).await?;
     sqlx::query!("UPDATE tasks SET archived = 1 WHERE id = ?", id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
     Ok(())
 }
 
-/// Delete a task (cascades to time_entries)
+/// Move a task to the trash, with its subtasks and time entries
 pub async fn delete(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
-    sqlx::query!("DELETE FROM tasks WHERE id = ?", id)
-        .execute(pool)
-        .await?;
+    let mut tx = audit::begin(pool, "task delete", id).await?;
+    trash::delete(&mut tx, trash::Kind::Task, id).await?;
+    tx.commit().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[tokio::test]
+    async fn test_create_rolls_back_on_failure() {
+        let pool = testutil::pool().await;
+        let project = testutil::project(&pool, "Acme").await;
+        let other = testutil::project(&pool, "Other").await;
+        let foreign = testutil::task(&pool, &other, "elsewhere").await;
+        let ops = audit::history(&pool, 10).await.unwrap().len();
+
+        let task = NewTask {
+            name: "sub".into(),
+            parent_id: Some(foreign),
+            estimate_hours: Some(2.0),
+            priority: Some(Priority::High),
+            due_date: Some("2024-03-01".into()),
+            ..NewTask::default()
+        };
+        assert!(create(&pool, &project, &task).await.is_err());
+        assert!(list(&pool, &project).await.unwrap().is_empty());
+        assert_eq!(audit::history(&pool, 10).await.unwrap().len(), ops);
+
+        let task = NewTask {
+            parent_id: None,
+            ..task
+        };
+        assert!(
+            create(
+                &pool,
+                &project,
+                &NewTask {
+                    due_date: Some("soon".into()),
+                    ..NewTask::default()
+                }
+            )
+            .await
+            .is_err()
+        );
+        create(&pool, &project, &task).await.unwrap();
+        let created = &list(&pool, &project).await.unwrap()[0];
+        assert_eq!(created.estimate_seconds, Some(7200));
+        assert_eq!(created.priority, Priority::High);
+        assert_eq!(created.due_date.as_deref(), Some("2024-03-01"));
+        audit::undo(&pool, 1).await.unwrap();
+        assert!(list(&pool, &project).await.unwrap().is_empty());
+    }
+
+    fn task(id: &str, status: TaskStatus, priority: Priority, due: Option<&str>) -> Task {
+        Task {
+            id: id.into(),
+            project_id: "p".into(),
+            name: id.into(),
+            description: None,
+            archived: false,
+            created_at: String::new(),
+            estimate_seconds: None,
+            parent_id: None,
+            status,
+            priority,
+            due_date: due.map(String::from),
+        }
+    }
+
+    fn tasks() -> Vec<Task> {
+        vec![
+            task("a", TaskStatus::Done, Priority::Low, Some("2024-01-10")),
+            task("b", TaskStatus::Todo, Priority::Urgent, None),
+            task(
+                "c",
+                TaskStatus::Blocked,
+                Priority::Normal,
+                Some("2024-01-20"),
+            ),
+            task(
+                "d",
+                TaskStatus::InProgress,
+                Priority::Urgent,
+                Some("2024-01-12"),
+            ),
+        ]
+    }
+
+    fn ids(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|t| t.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let today = "2024-01-15";
+        let matching = |filter: &Filter| -> Vec<String> {
+            tasks()
+                .into_iter()
+                .filter(|t| filter.matches(t, today))
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(matching(&Filter::default()), ["a", "b", "c", "d"]);
+        let open = Filter {
+            statuses: vec![TaskStatus::Todo, TaskStatus::InProgress],
+            ..Default::default()
+        };
+        assert_eq!(matching(&open), ["b", "d"]);
+        let urgent = Filter {
+            priorities: vec![Priority::Urgent],
+            ..Default::default()
+        };
+        assert_eq!(matching(&urgent), ["b", "d"]);
+        let due = Filter {
+            due_by: Some("2024-01-12".into()),
+            ..Default::default()
+        };
+        assert_eq!(matching(&due), ["a", "d"]);
+        let overdue = Filter {
+            overdue: true,
+            ..Default::default()
+        };
+        assert_eq!(matching(&overdue), ["d"]);
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut t = tasks();
+        sort(&mut t, TaskSort::Status);
+        assert_eq!(ids(&t), ["b", "d", "c", "a"]);
+        sort(&mut t, TaskSort::Priority);
+        assert_eq!(ids(&t), ["b", "d", "c", "a"]);
+        sort(&mut t, TaskSort::Due);
+        assert_eq!(ids(&t), ["a", "d", "c", "b"]);
+    }
+}
@@ -1,71 +1,487 @@
-use crate::services::timeutil::*;
+use crate::services::{audit, timeutil::*};
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
-pub async fn start(pool: &SqlitePool, task_id: &str) -> anyhow::Result<()> {
-    let mut tx = pool.begin().await?;
-    // If active, stop it
-    if let Some(id) = sqlx::query_scalar::<_, Option<String>>(
-        "SELECT time_entry_id FROM active_timer WHERE singleton=1",
-    )
-    .fetch_one(&mut *tx)
-    .await?
+const DEFAULT_MAX_SESSION_HOURS: f64 = 10.0;
+
+/// The entry closed by a stop
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Stopped {
+    pub entry_id: String,
+    pub duration_seconds: i64,
+    /// The entry ran longer than the maximum session length and awaits `review`
+    pub flagged: bool,
+}
+
+/// A task recently tracked, for `recent`/`continue`
+#[derive(Debug, Serialize)]
+pub struct RecentTask {
+    pub task_id: String,
+    pub task_name: String,
+    pub project_name: String,
+    pub last_start: String,
+}
+
+/// The running entry with its task and project
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActiveTimer {
+    pub entry_id: String,
+    pub task_id: String,
+    pub task_name: String,
+    pub project_name: String,
+    pub start_time: String,
+    pub note: Option<String>,
+}
+
+impl ActiveTimer {
+    pub fn elapsed_seconds(&self) -> i64 {
+        parse_rfc3339(&self.start_time)
+            .map(|start| (now_utc() - start).whole_seconds().max(0))
+            .unwrap_or(0)
+    }
+}
+
+/// Longest session not considered suspicious, from `RUSTYTIME_MAX_SESSION_HOURS` (default 10h)
+pub fn max_session_seconds() -> i64 {
+    let hours = std::env::var("RUSTYTIME_MAX_SESSION_HOURS")
+        .ok()
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(DEFAULT_MAX_SESSION_HOURS);
+    hours_to_seconds(hours)
+}
+
+/// The file mirroring the running timer for `prompt`, next to the database file
+pub fn state_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("state")
+}
+
+/// The running timer as last mirrored to the state file, without touching the database.
+/// `None` when the file is missing or unreadable.
+pub fn read_state(db_path: &Path) -> Option<Option<ActiveTimer>> {
+    let bytes = std::fs::read(state_path(db_path)).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Mirror the running timer to the state file. A file that can't be written is removed,
+/// so `prompt` falls back to the database instead of showing a stale timer. Databases
+/// without a file, such as in-memory ones, get no state file.
+pub async fn write_state(pool: &SqlitePool) -> anyhow::Result<()> {
+    let options = pool.connect_options();
+    let db_path = options.get_filename();
+    if !db_path.is_file() {
+        return Ok(());
+    }
+    let path = state_path(db_path);
+    let timer = match current(pool).await {
+        Ok(timer) => timer,
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+    };
+    let json = serde_json::to_vec(&timer)?;
+    let tmp = path.with_extension("state.tmp");
+    if std::fs::write(&tmp, json)
+        .and_then(|()| std::fs::rename(&tmp, &path))
+        .is_err()
     {
-        stop_tx(&mut tx, &id).await?;
+        let _ = std::fs::remove_file(&path);
     }
+    Ok(())
+}
+
+pub async fn start(pool: &SqlitePool, task_id: &str, note: Option<&str>) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "start", task_id).await?;
+    start_tx(&mut tx, task_id, note).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Start a new entry on `task_id`, stopping the running one first
+pub async fn start_tx(tx: &mut audit::Tx, task_id: &str, note: Option<&str>) -> anyhow::Result<()> {
+    stop_running(tx, None).await?;
 
     let id = Uuid::now_v7().to_string();
     let now = now_utc();
-    let date = local_date_warsaw(now);
+    let date = local_date_str(now);
     let now_s = to_rfc3339(now);
 
-    sqlx::query("INSERT INTO time_entries(id, task_id, start_time, end_time, duration_seconds, local_date, created_at)
-                 VALUES(?, ?, ?, NULL, NULL, ?, ?)")
-        .bind(&id).bind(task_id).bind(&now_s).bind(&date).bind(&now_s)
-        .execute(&mut *tx).await?;
+    sqlx::query("INSERT INTO time_entries(id, task_id, start_time, end_time, duration_seconds, local_date, created_at, note)
+                 VALUES(?, ?, ?, NULL, NULL, ?, ?, ?)")
+        .bind(&id).bind(task_id).bind(&now_s).bind(&date).bind(&now_s).bind(note)
+        .execute(&mut **tx).await?;
 
     sqlx::query("UPDATE active_timer SET time_entry_id=? WHERE singleton=1")
         .bind(&id)
+        .execute(&mut **tx)
+        .await?;
+    mark_in_progress(tx, task_id).await
+}
+
+/// The running timer, if any
+pub async fn current(pool: &SqlitePool) -> anyhow::Result<Option<ActiveTimer>> {
+    let row = sqlx::query_as!(
+        ActiveTimer,
+        r#"SELECT e.id as "entry_id!", t.id as "task_id!", t.name as task_name,
+                  p.name as project_name, e.start_time, e.note
+           FROM active_timer a
+           JOIN time_entries e ON e.id = a.time_entry_id
+           JOIN tasks t ON t.id = e.task_id
+           JOIN projects p ON p.id = t.project_id
+           WHERE a.singleton = 1"#
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// The `limit` most recently started distinct tasks, most recent first
+pub async fn recent(pool: &SqlitePool, limit: i64) -> anyhow::Result<Vec<RecentTask>> {
+    let rows = sqlx::query_as!(
+        RecentTask,
+        r#"SELECT t.id as "task_id!", t.name as "task_name!", p.name as "project_name!",
+                  MAX(e.start_time) as "last_start!: String"
+           FROM time_entries e
+           JOIN tasks t ON t.id = e.task_id
+           JOIN projects p ON p.id = t.project_id
+           GROUP BY t.id
+           ORDER BY MAX(e.start_time) DESC
+           LIMIT ?"#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Resolve a task reference given to `start`: a 1-based index into `recent`, or a task id
+pub async fn resolve_task(pool: &SqlitePool, reference: &str) -> anyhow::Result<String> {
+    let Ok(index) = reference.parse::<i64>() else {
+        return Ok(reference.to_string());
+    };
+    if index < 1 {
+        anyhow::bail!("recent task index must start at 1");
+    }
+    recent(pool, index)
+        .await?
+        .into_iter()
+        .nth(index as usize - 1)
+        .map(|r| r.task_id)
+        .ok_or_else(|| anyhow::anyhow!("no recent task #{index}; see `rustytime recent`"))
+}
+
+/// Start a new entry for the task of the most recently stopped entry; returns the task id
+pub async fn resume_last(pool: &SqlitePool) -> anyhow::Result<String> {
+    let running: Option<String> =
+        sqlx::query_scalar("SELECT time_entry_id FROM active_timer WHERE singleton=1")
+            .fetch_one(pool)
+            .await?;
+    if running.is_some() {
+        anyhow::bail!("a timer is already running");
+    }
+    let task_id: String = sqlx::query_scalar(
+        "SELECT task_id FROM time_entries WHERE end_time IS NOT NULL
+         ORDER BY start_time DESC LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("nothing to continue: no stopped entries yet"))?;
+    start(pool, &task_id, None).await?;
+    Ok(task_id)
+}
+
+/// Discard the running timer without recording anything; returns the deleted entry id
+pub async fn cancel(pool: &SqlitePool) -> anyhow::Result<Option<String>> {
+    let mut tx = audit::begin(pool, "cancel", "").await?;
+    let active: Option<String> =
+        sqlx::query_scalar("SELECT time_entry_id FROM active_timer WHERE singleton=1")
+            .fetch_one(&mut *tx)
+            .await?;
+    if let Some(id) = &active {
+        sqlx::query("UPDATE active_timer SET time_entry_id=NULL WHERE singleton=1")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM time_entries WHERE id=?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(active)
+}
+
+/// Append a line to the note of the running entry
+pub async fn note(pool: &SqlitePool, text: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "note", text).await?;
+    note_tx(&mut tx, text).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn note_tx(tx: &mut audit::Tx, text: &str) -> anyhow::Result<()> {
+    let updated = sqlx::query(
+        "UPDATE time_entries
+         SET note = CASE WHEN note IS NULL OR note = '' THEN ? ELSE note || char(10) || ? END
+         WHERE id = (SELECT time_entry_id FROM active_timer WHERE singleton=1)",
+    )
+    .bind(text)
+    .bind(text)
+    .execute(&mut **tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        anyhow::bail!("no timer running to add a note to");
+    }
+    Ok(())
+}
+
+/// Replace the note of any entry; an empty note clears it
+pub async fn set_note(pool: &SqlitePool, entry_id: &str, note: &str) -> anyhow::Result<()> {
+    let note = Some(note).filter(|n| !n.is_empty());
+    let mut tx = audit::begin(pool, "note", entry_id).await?;
+    sqlx::query("UPDATE time_entries SET note = ? WHERE id = ?")
+        .bind(note)
+        .bind(entry_id)
         .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Move the start and end of an entry; `end` must be `None` for the running entry
+pub async fn retime(
+    pool: &SqlitePool,
+    entry_id: &str,
+    start: OffsetDateTime,
+    end: Option<OffsetDateTime>,
+) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "retime", entry_id).await?;
+    let running: bool =
+        sqlx::query_scalar("SELECT end_time IS NULL FROM time_entries WHERE id = ?")
+            .bind(entry_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("time entry {entry_id} not found"))?;
+    let now = now_utc();
+    match end {
+        Some(_) if running => anyhow::bail!("time entry {entry_id} is still running"),
+        None if !running => anyhow::bail!("time entry {entry_id} needs an end"),
+        Some(end) if end <= start => anyhow::bail!(
+            "end {} must be after start {}",
+            to_rfc3339(end),
+            to_rfc3339(start)
+        ),
+        _ => {}
+    }
+    if end.unwrap_or(start) > now {
+        anyhow::bail!("{} is in the future", to_rfc3339(end.unwrap_or(start)));
+    }
+    let duration = end.map(|end| (end - start).whole_seconds());
+    let flagged = duration.is_some_and(|d| d > max_session_seconds());
+    sqlx::query(
+        "UPDATE time_entries
+         SET start_time = ?, end_time = ?, duration_seconds = ?, local_date = ?, flagged = ?
+         WHERE id = ?",
+    )
+    .bind(to_rfc3339(start))
+    .bind(end.map(to_rfc3339))
+    .bind(duration)
+    .bind(local_date_str(start))
+    .bind(flagged)
+    .bind(entry_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
 
+/// Move any entry to another task; moving the running entry puts the task in progress
+pub async fn set_task(pool: &SqlitePool, entry_id: &str, task_id: &str) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "entry task", entry_id).await?;
+    let task: Option<String> = sqlx::query_scalar("SELECT id FROM tasks WHERE id = ?")
+        .bind(task_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if task.is_none() {
+        anyhow::bail!("no task {task_id}");
+    }
+    let running: bool =
+        sqlx::query_scalar("SELECT end_time IS NULL FROM time_entries WHERE id = ?")
+            .bind(entry_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("time entry {entry_id} not found"))?;
+    sqlx::query("UPDATE time_entries SET task_id = ? WHERE id = ?")
+        .bind(task_id)
+        .bind(entry_id)
+        .execute(&mut *tx)
+        .await?;
+    if running {
+        mark_in_progress(&mut tx, task_id).await?;
+    }
     tx.commit().await?;
     Ok(())
 }
 
-pub async fn stop(pool: &SqlitePool) -> anyhow::Result<()> {
-    let mut tx = pool.begin().await?;
+/// Reassign the running entry to another task, keeping its start time. A `note` replaces the
+/// entry's note; without one the note is kept
+pub async fn switch(pool: &SqlitePool, task_id: &str, note: Option<&str>) -> anyhow::Result<()> {
+    let mut tx = audit::begin(pool, "switch", task_id).await?;
     let active: Option<String> =
         sqlx::query_scalar("SELECT time_entry_id FROM active_timer WHERE singleton=1")
             .fetch_one(&mut *tx)
             .await?;
-    if let Some(id) = active {
-        stop_tx(&mut tx, &id).await?;
+    let Some(id) = active else {
+        anyhow::bail!("no timer running to switch");
+    };
+    sqlx::query("UPDATE time_entries SET task_id=?, note=COALESCE(?, note) WHERE id=?")
+        .bind(task_id)
+        .bind(note)
+        .bind(&id)
+        .execute(&mut *tx)
+        .await?;
+    mark_in_progress(&mut tx, task_id).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stop the running timer, now or at an earlier point `at`
+pub async fn stop(
+    pool: &SqlitePool,
+    at: Option<OffsetDateTime>,
+) -> anyhow::Result<Option<Stopped>> {
+    let mut tx = audit::begin(pool, "stop", "").await?;
+    let stopped = stop_running(&mut tx, at).await?;
+    tx.commit().await?;
+    Ok(stopped)
+}
+
+/// Stop the running timer at `at` because the user went idle. The daemon does this on its own,
+/// so it is an automatic operation that `undo` skips.
+pub async fn stop_idle(pool: &SqlitePool, at: OffsetDateTime) -> anyhow::Result<Option<Stopped>> {
+    let mut tx = audit::begin_automatic(pool, "idle stop", "").await?;
+    let stopped = stop_running(&mut tx, Some(at)).await?;
+    tx.commit().await?;
+    Ok(stopped)
+}
+
+async fn stop_running(
+    tx: &mut audit::Tx,
+    at: Option<OffsetDateTime>,
+) -> anyhow::Result<Option<Stopped>> {
+    let active: Option<String> =
+        sqlx::query_scalar("SELECT time_entry_id FROM active_timer WHERE singleton=1")
+            .fetch_one(&mut **tx)
+            .await?;
+    match active {
+        Some(id) => Ok(Some(stop_tx(tx, &id, at).await?)),
+        None => Ok(None),
     }
+}
+
+/// Stop the running timer at `at`, in any format `stop --at` takes; a bare time is resolved
+/// against the day the entry started
+pub async fn stop_at(pool: &SqlitePool, at: &str) -> anyhow::Result<Option<Stopped>> {
+    let mut tx = audit::begin(pool, "stop", "").await?;
+    let active: Option<(String, String)> = sqlx::query_as(
+        "SELECT e.id, e.start_time FROM active_timer a JOIN time_entries e ON e.id = a.time_entry_id
+         WHERE a.singleton=1",
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let stopped = match active {
+        Some((id, start)) => {
+            let at = parse_local_datetime_after(at, parse_rfc3339(&start)?)?;
+            Some(stop_tx(&mut tx, &id, Some(at)).await?)
+        }
+        None => None,
+    };
     tx.commit().await?;
+    Ok(stopped)
+}
+
+/// Working on a task puts it in progress, whatever its status was
+async fn mark_in_progress(tx: &mut audit::Tx, task_id: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE tasks SET status = 'in-progress' WHERE id = ? AND status <> 'in-progress'")
+        .bind(task_id)
+        .execute(&mut **tx)
+        .await?;
     Ok(())
 }
 
-async fn stop_tx<'a>(tx: &mut sqlx::Transaction<'a, sqlx::Sqlite>, id: &str) -> anyhow::Result<()> {
+async fn stop_tx(
+    tx: &mut audit::Tx,
+    id: &str,
+    at: Option<OffsetDateTime>,
+) -> anyhow::Result<Stopped> {
     let start: String = sqlx::query_scalar("SELECT start_time FROM time_entries WHERE id=?")
         .bind(id)
         .fetch_one(&mut **tx)
         .await?;
-    let start_t =
-        time::OffsetDateTime::parse(&start, &time::format_description::well_known::Rfc3339)?;
+    let start_t = parse_rfc3339(&start)?;
     let now = now_utc();
-    let dur = (now - start_t).whole_seconds().max(0);
-    let now_s = to_rfc3339(now);
+    let end = at.unwrap_or(now);
+    if end < start_t {
+        anyhow::bail!(
+            "stop time {} is before the timer started at {start}",
+            to_rfc3339(end)
+        );
+    }
+    if end > now {
+        anyhow::bail!("stop time {} is in the future", to_rfc3339(end));
+    }
+    let dur = (end - start_t).whole_seconds().max(0);
+    let flagged = dur > max_session_seconds();
+    let end_s = to_rfc3339(end);
 
-    sqlx::query("UPDATE time_entries SET end_time=?, duration_seconds=? WHERE id=?")
-        .bind(&now_s)
-        .bind(dur)
-        .bind(id)
-        .execute(&mut **tx)
-        .await?;
+    let updated = sqlx::query(
+        "UPDATE time_entries SET end_time=?, duration_seconds=?, flagged=?
+         WHERE id=? AND end_time IS NULL",
+    )
+    .bind(&end_s)
+    .bind(dur)
+    .bind(flagged)
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        anyhow::bail!("the timer was already stopped");
+    }
 
     sqlx::query("UPDATE active_timer SET time_entry_id=NULL WHERE singleton=1")
         .execute(&mut **tx)
         .await?;
-    Ok(())
+    Ok(Stopped {
+        entry_id: id.to_string(),
+        duration_seconds: dur,
+        flagged,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[tokio::test]
+    async fn test_switch_sets_note() {
+        let pool = testutil::pool().await;
+        let project = testutil::project(&pool, "Acme").await;
+        let first = testutil::task(&pool, &project, "first").await;
+        let second = testutil::task(&pool, &project, "second").await;
+        let third = testutil::task(&pool, &project, "third").await;
+
+        start(&pool, &first, Some("warming up")).await.unwrap();
+        switch(&pool, &second, Some("real work")).await.unwrap();
+        let timer = current(&pool).await.unwrap().unwrap();
+        assert_eq!(timer.task_id, second);
+        assert_eq!(timer.note.as_deref(), Some("real work"));
+
+        switch(&pool, &third, None).await.unwrap();
+        let timer = current(&pool).await.unwrap().unwrap();
+        assert_eq!(timer.task_id, third);
+        assert_eq!(timer.note.as_deref(), Some("real work"));
+    }
 }
@@ -1,23 +1,172 @@
-use time::{OffsetDateTime, macros::format_description};
-use time_tz::{OffsetDateTimeExt, timezones};
+use std::sync::OnceLock;
+use time::{
+    Date, OffsetDateTime, Time, UtcOffset, Weekday, format_description::well_known::Rfc3339,
+    macros::format_description,
+};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt, Tz, timezones};
+
+static TIMEZONE: OnceLock<&'static Tz> = OnceLock::new();
+static WEEK_START: OnceLock<Weekday> = OnceLock::new();
+
+/// Look up an IANA time zone such as `Europe/Warsaw`
+pub fn find_timezone(name: &str) -> anyhow::Result<&'static Tz> {
+    timezones::get_by_name(name).ok_or_else(|| anyhow::anyhow!("unknown time zone '{name}'"))
+}
+
+/// Use `tz` for local dates and times from now on; only the first call has an effect
+pub fn set_timezone(tz: &'static Tz) {
+    let _ = TIMEZONE.set(tz);
+}
+
+/// Start weeks on `day` from now on; only the first call has an effect
+pub fn set_week_start(day: Weekday) {
+    let _ = WEEK_START.set(day);
+}
+
+/// The configured time zone, Europe/Warsaw unless set
+fn tz() -> &'static Tz {
+    TIMEZONE
+        .get()
+        .copied()
+        .unwrap_or(timezones::db::europe::WARSAW)
+}
 
 pub fn now_utc() -> OffsetDateTime {
     OffsetDateTime::now_utc()
 }
 
-pub fn local_date_warsaw(at: OffsetDateTime) -> String {
-    let tz = timezones::db::europe::WARSAW;
-    let local = at.to_timezone(tz);
+/// Local `YYYY-MM-DD`, as stored in `local_date`
+pub fn local_date_str(at: OffsetDateTime) -> String {
+    let local = at.to_timezone(tz());
     let fmt = format_description!("[year]-[month]-[day]");
     local.format(&fmt).unwrap()
 }
 
-pub fn to_rfc3339(t: OffsetDateTime) -> String {
-    t.format(&time::format_description::well_known::Rfc3339)
+/// Local wall-clock `HH:MM`, for listing entries
+pub fn local_time_str(at: OffsetDateTime) -> String {
+    let local = at.to_timezone(tz());
+    local
+        .format(&format_description!("[hour]:[minute]"))
         .unwrap()
 }
 
+pub fn local_date(at: OffsetDateTime) -> Date {
+    at.to_timezone(tz()).date()
+}
+
+pub fn today_local() -> Date {
+    local_date(now_utc())
+}
+
+/// The first day of the week holding `date`; weeks start on Monday unless configured
+pub fn week_start(date: Date) -> Date {
+    week_start_on(date, WEEK_START.get().copied().unwrap_or(Weekday::Monday))
+}
+
+fn week_start_on(date: Date, first: Weekday) -> Date {
+    let back = (date.weekday().number_days_from_monday() + 7 - first.number_days_from_monday()) % 7;
+    date - time::Duration::days(back.into())
+}
+
+/// Parse a `YYYY-MM-DD` date as used in `local_date` and the `--from`/`--to` flags.
+pub fn parse_date(s: &str) -> anyhow::Result<Date> {
+    let fmt = format_description!("[year]-[month]-[day]");
+    Date::parse(s, &fmt)
+        .map_err(|e| anyhow::anyhow!("invalid date '{s}' (expected YYYY-MM-DD): {e}"))
+}
+
+pub fn format_date(d: Date) -> String {
+    let fmt = format_description!("[year]-[month]-[day]");
+    d.format(&fmt).unwrap()
+}
+
+pub fn to_rfc3339(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap()
+}
+
+pub fn parse_rfc3339(s: &str) -> anyhow::Result<OffsetDateTime> {
+    Ok(OffsetDateTime::parse(s, &Rfc3339)?)
+}
+
+/// Parse a point in time given on the command line, in UTC.
+///
+/// Accepts RFC3339, `YYYY-MM-DD HH:MM` or a bare `HH:MM` on `base`; the latter two are
+/// local time, like `local_date`.
+pub fn parse_local_datetime(s: &str, base: Date) -> anyhow::Result<OffsetDateTime> {
+    if let Ok(t) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Ok(t.to_offset(UtcOffset::UTC));
+    }
+    let (date, time) = match s.trim().split_once(' ') {
+        Some((date, time)) => (parse_date(date)?, time),
+        None => (base, s.trim()),
+    };
+    let time = Time::parse(time, &format_description!("[hour]:[minute]"))
+        .or_else(|_| Time::parse(time, &format_description!("[hour]:[minute]:[second]")))
+        .map_err(|_| {
+            anyhow::anyhow!("invalid time '{s}' (expected HH:MM, YYYY-MM-DD HH:MM or RFC3339)")
+        })?;
+    let local = date
+        .with_time(time)
+        .assume_timezone(tz())
+        .take_first()
+        .ok_or_else(|| anyhow::anyhow!("'{s}' does not exist in local time"))?;
+    Ok(local.to_offset(UtcOffset::UTC))
+}
+
+/// Like [`parse_local_datetime`] for a point after `start`: a bare time is on the local day of
+/// `start`, or the day after when that would be before it, so a timer left running overnight
+/// can be stopped at last evening's time the next morning
+pub fn parse_local_datetime_after(
+    s: &str,
+    start: OffsetDateTime,
+) -> anyhow::Result<OffsetDateTime> {
+    let day = local_date(start);
+    let at = parse_local_datetime(s, day)?;
+    let bare_time = !s.trim().contains(' ') && OffsetDateTime::parse(s, &Rfc3339).is_err();
+    match day.next_day() {
+        Some(next) if bare_time && at < start => parse_local_datetime(s, next),
+        _ => Ok(at),
+    }
+}
+
+pub fn hours_to_seconds(hours: f64) -> i64 {
+    (hours * 3600.0).round() as i64
+}
+
+/// Render a number of seconds as `Hh MMm`, e.g. `7h 05m`. Negative values keep their sign.
+pub fn format_duration(secs: i64) -> String {
+    let sign = if secs < 0 { "-" } else { "" };
+    let secs = secs.abs();
+    format!("{sign}{}h {:02}m", secs / 3600, (secs % 3600) / 60)
+}
+
+/// Round `secs` to the nearest multiple of `minutes`, halves up; 0 minutes leaves it as is
+pub fn round_seconds(secs: i64, minutes: i64) -> i64 {
+    if minutes <= 0 {
+        return secs;
+    }
+    let step = minutes * 60;
+    let rounded = (secs.abs() + step / 2) / step * step;
+    rounded * secs.signum()
+}
+
+/// Parse an age such as `30d`, `2w`, `12h` or `90m`
+pub fn parse_age(s: &str) -> anyhow::Result<time::Duration> {
+    let invalid = || anyhow::anyhow!("invalid age '{s}', expected e.g. 30d, 2w, 12h or 90m");
+    let s = s.trim();
+    let (n, unit) = s.split_at(s.char_indices().last().map_or(0, |(i, _)| i));
+    let n: i64 = n.parse().map_err(|_| invalid())?;
+    match unit {
+        "m" => Ok(time::Duration::minutes(n)),
+        "h" => Ok(time::Duration::hours(n)),
+        "d" => Ok(time::Duration::days(n)),
+        "w" => Ok(time::Duration::weeks(n)),
+        _ => Err(invalid()),
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::manual_range_contains)]
 mod tests {
     use super::*;
     use time::{Date, Month, Time, UtcOffset};
@@ -49,7 +198,7 @@ mod tests {
         let time = Time::from_hms(12, 0, 0).unwrap();
         let utc_datetime = date.with_time(time).assume_utc();
 
-        let result = local_date_warsaw(utc_datetime);
+        let result = local_date_str(utc_datetime);
 
         // In summer, Warsaw is UTC+2, so 12:00 UTC = 14:00 CEST, same date
         assert_eq!(result, "2024-07-15");
@@ -63,7 +212,7 @@ mod tests {
         let time = Time::from_hms(12, 0, 0).unwrap();
         let utc_datetime = date.with_time(time).assume_utc();
 
-        let result = local_date_warsaw(utc_datetime);
+        let result = local_date_str(utc_datetime);
 
         // In winter, Warsaw is UTC+1, so 12:00 UTC = 13:00 CET, same date
         assert_eq!(result, "2024-01-15");
@@ -77,7 +226,7 @@ mod tests {
         let time = Time::from_hms(23, 30, 0).unwrap();
         let utc_datetime = date.with_time(time).assume_utc();
 
-        let result = local_date_warsaw(utc_datetime);
+        let result = local_date_str(utc_datetime);
 
         // In winter, Warsaw is UTC+1, so 23:30 UTC = 00:30 CET next day
         assert_eq!(result, "2024-01-01");
@@ -91,7 +240,7 @@ mod tests {
         let time = Time::from_hms(0, 30, 0).unwrap();
         let utc_datetime = date.with_time(time).assume_utc();
 
-        let result = local_date_warsaw(utc_datetime);
+        let result = local_date_str(utc_datetime);
 
         // In winter, Warsaw is UTC+1, so 00:30 UTC = 01:30 CET, same date
         assert_eq!(result, "2024-01-01");
@@ -150,6 +299,90 @@ mod tests {
         assert_eq!(result, "2024-02-29T00:00:00Z");
     }
 
+    #[test]
+    fn test_parse_and_format_date() {
+        let d = parse_date("2024-02-29").unwrap();
+        assert_eq!(
+            d,
+            Date::from_calendar_date(2024, Month::February, 29).unwrap()
+        );
+        assert_eq!(format_date(d), "2024-02-29");
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("29/02/2024").is_err());
+    }
+
+    #[test]
+    fn test_local_time_warsaw() {
+        let date = Date::from_calendar_date(2024, Month::January, 15).unwrap();
+        let utc_datetime = date
+            .with_time(Time::from_hms(8, 5, 0).unwrap())
+            .assume_utc();
+        assert_eq!(local_time_str(utc_datetime), "09:05");
+    }
+
+    #[test]
+    fn test_parse_local_datetime() {
+        let base = Date::from_calendar_date(2024, Month::July, 15).unwrap();
+        let expected = base
+            .with_time(Time::from_hms(16, 30, 0).unwrap())
+            .assume_utc();
+
+        // CEST is UTC+2
+        assert_eq!(parse_local_datetime("18:30", base).unwrap(), expected);
+        assert_eq!(
+            parse_local_datetime("2024-07-15 18:30", Date::MIN).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_local_datetime("2024-07-15T16:30:00Z", Date::MIN).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_local_datetime("2024-07-15T18:30:00+02:00", Date::MIN).unwrap(),
+            expected
+        );
+        assert!(parse_local_datetime("25:00", base).is_err());
+        assert!(parse_local_datetime("yesterday", base).is_err());
+    }
+
+    #[test]
+    fn test_parse_local_datetime_after() {
+        let day = Date::from_calendar_date(2024, Month::July, 15).unwrap();
+        let at = |d: Date, h: u8| d.with_time(Time::from_hms(h, 0, 0).unwrap()).assume_utc();
+        // Started at 09:00 local (07:00 UTC)
+        let start = at(day, 7);
+        assert_eq!(
+            parse_local_datetime_after("18:00", start).unwrap(),
+            at(day, 16)
+        );
+        // Before the start: the next morning
+        let next = day.next_day().unwrap();
+        assert_eq!(
+            parse_local_datetime_after("08:00", start).unwrap(),
+            at(next, 6)
+        );
+        // Explicit dates are taken as given
+        assert_eq!(
+            parse_local_datetime_after("2024-07-15 08:00", start).unwrap(),
+            at(day, 6)
+        );
+    }
+
+    #[test]
+    fn test_hours_to_seconds() {
+        assert_eq!(hours_to_seconds(1.0), 3600);
+        assert_eq!(hours_to_seconds(7.5), 27000);
+        assert_eq!(hours_to_seconds(0.0), 0);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0h 00m");
+        assert_eq!(format_duration(27000), "7h 30m");
+        assert_eq!(format_duration(3659), "1h 00m");
+        assert_eq!(format_duration(-5400), "-1h 30m");
+    }
+
     #[test]
     fn test_integration_now_utc_to_rfc3339() {
         // Integration test: get current time and format it
@@ -178,21 +411,21 @@ mod tests {
     fn test_integration_now_utc_to_warsaw_date() {
         // Integration test: get current time and convert to Warsaw date
         let now = now_utc();
-        let warsaw_date = local_date_warsaw(now);
+        let local_date = local_date_str(now);
 
         // Should be in YYYY-MM-DD format
         assert_eq!(
-            warsaw_date.len(),
+            local_date.len(),
             10,
             "Warsaw date should be 10 characters long"
         );
         assert!(
-            warsaw_date.matches('-').count() == 2,
+            local_date.matches('-').count() == 2,
             "Warsaw date should have exactly 2 dashes"
         );
 
         // Should be parseable as a date
-        let parts: Vec<&str> = warsaw_date.split('-').collect();
+        let parts: Vec<&str> = local_date.split('-').collect();
         assert_eq!(
             parts.len(),
             3,
@@ -216,4 +449,42 @@ mod tests {
         let day: u32 = parts[2].parse().expect("Day should be numeric");
         assert!(day >= 1 && day <= 31, "Day should be between 1 and 31");
     }
+
+    #[test]
+    fn test_round_seconds() {
+        assert_eq!(round_seconds(7 * 60, 15), 0);
+        assert_eq!(round_seconds(8 * 60, 15), 15 * 60);
+        assert_eq!(round_seconds(7 * 60 + 30, 15), 15 * 60);
+        assert_eq!(round_seconds(-8 * 60, 15), -15 * 60);
+        assert_eq!(round_seconds(1234, 0), 1234);
+    }
+
+    #[test]
+    fn test_week_start_on() {
+        // 2024-01-03 is a Wednesday
+        let wed = Date::from_calendar_date(2024, Month::January, 3).unwrap();
+        let mon = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let sun = Date::from_calendar_date(2023, Month::December, 31).unwrap();
+        assert_eq!(week_start_on(wed, Weekday::Monday), mon);
+        assert_eq!(week_start_on(wed, Weekday::Sunday), sun);
+        assert_eq!(week_start_on(wed, Weekday::Wednesday), wed);
+        assert_eq!(week_start_on(sun, Weekday::Sunday), sun);
+    }
+
+    #[test]
+    fn test_find_timezone() {
+        assert!(find_timezone("Europe/London").is_ok());
+        assert!(find_timezone("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), time::Duration::days(30));
+        assert_eq!(parse_age("2w").unwrap(), time::Duration::days(14));
+        assert_eq!(parse_age("12h").unwrap(), time::Duration::hours(12));
+        assert_eq!(parse_age("90m").unwrap(), time::Duration::minutes(90));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("").is_err());
+    }
 }
@@ -0,0 +1,353 @@
+//! Trash bin.
+//!
+//! Deleting a project, task or entry moves it here together with every row the delete
+//! cascaded to, as recorded by the change log of the running operation. Trashed rows are gone
+//! from their tables, so no query sees them; [`restore`] puts them back and [`purge`] drops
+//! them for good.
+
+use crate::services::{audit, timeutil::*};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use sqlx::SqlitePool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Project,
+    Task,
+    Entry,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Project => "project",
+            Kind::Task => "task",
+            Kind::Entry => "entry",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            Kind::Project => "projects",
+            Kind::Task => "tasks",
+            Kind::Entry => "time_entries",
+        }
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Kind> {
+        match s {
+            "project" => Ok(Kind::Project),
+            "task" => Ok(Kind::Task),
+            "entry" => Ok(Kind::Entry),
+            _ => anyhow::bail!("unknown trash item kind '{s}'"),
+        }
+    }
+}
+
+/// A trashed project, task or entry
+#[derive(Debug, Serialize)]
+pub struct Item {
+    pub item_id: String,
+    pub kind: String,
+    pub name: String,
+    pub deleted_at: String,
+    /// Rows held by the item, the deleted one included
+    pub rows: i64,
+}
+
+/// The rows to put back, parents first, as `(table, row)`
+fn parse_rows(json: &str) -> anyhow::Result<Vec<(String, Map<String, Value>)>> {
+    let rows: Vec<Value> = serde_json::from_str(json)?;
+    rows.into_iter()
+        .map(|r| match (r.get("table"), r.get("row")) {
+            (Some(Value::String(table)), Some(Value::Object(row))) => {
+                Ok((table.clone(), row.clone()))
+            }
+            _ => anyhow::bail!("malformed trash row {r}"),
+        })
+        .collect()
+}
+
+/// The parent a restored row needs, as `(kind, id)`
+fn required_parents(kind: Kind, row: &Map<String, Value>) -> Vec<(Kind, String)> {
+    let column = |c: &str| row.get(c).and_then(Value::as_str).map(String::from);
+    let parents = match kind {
+        Kind::Project => vec![(Kind::Project, column("parent_id"))],
+        Kind::Task => vec![
+            (Kind::Project, column("project_id")),
+            (Kind::Task, column("parent_id")),
+        ],
+        Kind::Entry => vec![(Kind::Task, column("task_id"))],
+    };
+    parents
+        .into_iter()
+        .filter_map(|(kind, id)| Some((kind, id?)))
+        .collect()
+}
+
+/// The trash item holding row `id` of `table`, as `(item_id, name)`
+async fn holder(
+    tx: &mut audit::Tx,
+    table: &str,
+    id: &str,
+) -> anyhow::Result<Option<(String, String)>> {
+    let holder = sqlx::query_as(
+        "SELECT t.item_id, t.name FROM trash t, json_each(t.rows) r
+         WHERE json_extract(r.value, '$.row.id') = ?
+           AND json_extract(r.value, '$.table') = ?",
+    )
+    .bind(id)
+    .bind(table)
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(holder)
+}
+
+/// Delete a project, task or entry inside `tx`, moving it and everything the delete cascades
+/// to into the trash. The running entry cannot be trashed.
+pub async fn delete(tx: &mut audit::Tx, kind: Kind, id: &str) -> anyhow::Result<()> {
+    let name: Option<String> = match kind {
+        Kind::Project => {
+            sqlx::query_scalar!("SELECT name FROM projects WHERE id = ?", id)
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        Kind::Task => {
+            sqlx::query_scalar!(
+                r#"SELECT p.name || ' / ' || t.name as "name!: String"
+                   FROM tasks t JOIN projects p ON p.id = t.project_id
+                   WHERE t.id = ?"#,
+                id
+            )
+            .fetch_optional(&mut **tx)
+            .await?
+        }
+        Kind::Entry => {
+            sqlx::query_scalar!(
+                r#"SELECT p.name || ' / ' || t.name || ' ' || e.start_time as "name!: String"
+                   FROM time_entries e
+                   JOIN tasks t ON t.id = e.task_id
+                   JOIN projects p ON p.id = t.project_id
+                   WHERE e.id = ?"#,
+                id
+            )
+            .fetch_optional(&mut **tx)
+            .await?
+        }
+    };
+    let Some(name) = name else {
+        anyhow::bail!("{} {id} not found", kind.as_str());
+    };
+
+    let active: Option<String> =
+        sqlx::query_scalar("SELECT time_entry_id FROM active_timer WHERE singleton=1")
+            .fetch_one(&mut **tx)
+            .await?;
+    let last_change: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM audit_changes")
+        .fetch_one(&mut **tx)
+        .await?;
+    sqlx::query(&format!("DELETE FROM {} WHERE id = ?", kind.table()))
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+
+    // Cascades log children before their parent, so newest first puts parents first
+    let deleted: Vec<(String, String)> = sqlx::query_as(
+        "SELECT table_name, before FROM audit_changes
+         WHERE id > ? AND action = 'delete'
+         ORDER BY id DESC",
+    )
+    .bind(last_change)
+    .fetch_all(&mut **tx)
+    .await?;
+    let mut rows = vec![];
+    for (table, before) in deleted {
+        let row: Value = serde_json::from_str(&before)?;
+        if table == "time_entries"
+            && active.is_some()
+            && row.get("id").and_then(Value::as_str) == active.as_deref()
+        {
+            anyhow::bail!("{name} holds the running timer; stop or cancel it first");
+        }
+        rows.push(json!({ "table": table, "row": row }));
+    }
+
+    sqlx::query("INSERT INTO trash(kind, item_id, name, deleted_at, rows) VALUES(?, ?, ?, ?, ?)")
+        .bind(kind.as_str())
+        .bind(id)
+        .bind(&name)
+        .bind(to_rfc3339(now_utc()))
+        .bind(Value::Array(rows).to_string())
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Trashed items, most recently deleted first
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<Item>> {
+    let rows = sqlx::query_as!(
+        Item,
+        r#"SELECT item_id, kind, name, deleted_at, json_array_length(rows) as "rows!: i64"
+           FROM trash
+           ORDER BY deleted_at DESC, id DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Put a trashed item back with everything that was deleted along with it. Refused while the
+/// project or task it belongs to is missing.
+pub async fn restore(pool: &SqlitePool, item_id: &str) -> anyhow::Result<Item> {
+    let mut tx = audit::begin(pool, "trash restore", item_id).await?;
+    let item = sqlx::query!(
+        r#"SELECT id as "id!", kind, name, deleted_at, rows FROM trash WHERE item_id = ?"#,
+        item_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(item) = item else {
+        for kind in [Kind::Project, Kind::Task, Kind::Entry] {
+            if let Some((holder_id, name)) = holder(&mut tx, kind.table(), item_id).await? {
+                anyhow::bail!(
+                    "{} {item_id} is in the trash with {name}; restore {holder_id} instead",
+                    kind.as_str()
+                );
+            }
+        }
+        anyhow::bail!("{item_id} is not in the trash");
+    };
+    let kind = Kind::parse(&item.kind)?;
+    let rows = parse_rows(&item.rows)?;
+
+    let own = rows
+        .iter()
+        .find(|(table, row)| {
+            table == kind.table() && row.get("id").and_then(Value::as_str) == Some(item_id)
+        })
+        .ok_or_else(|| anyhow::anyhow!("trash item {item_id} lacks its own row"))?;
+    for (parent_kind, parent_id) in required_parents(kind, &own.1) {
+        let exists: bool = sqlx::query_scalar(&format!(
+            "SELECT EXISTS(SELECT 1 FROM {} WHERE id = ?)",
+            parent_kind.table()
+        ))
+        .bind(&parent_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if exists {
+            continue;
+        }
+        let trashed = holder(&mut tx, parent_kind.table(), &parent_id).await?;
+        match trashed {
+            Some((trashed_id, name)) => anyhow::bail!(
+                "cannot restore {}: its {} is in the trash with {name}; restore {trashed_id} first",
+                item.name,
+                parent_kind.as_str()
+            ),
+            None => anyhow::bail!(
+                "cannot restore {}: its {} {parent_id} no longer exists",
+                item.name,
+                parent_kind.as_str()
+            ),
+        }
+    }
+
+    for (table, row) in &rows {
+        audit::insert_row(&mut tx, table, row)
+            .await
+            .map_err(|e| anyhow::anyhow!("cannot restore {}: {e}", item.name))?;
+    }
+    sqlx::query!("DELETE FROM trash WHERE id = ?", item.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(Item {
+        item_id: item_id.to_string(),
+        kind: item.kind,
+        name: item.name,
+        deleted_at: item.deleted_at,
+        rows: rows.len() as i64,
+    })
+}
+
+/// Drop items trashed more than `older_than` ago; returns how many
+pub async fn purge(pool: &SqlitePool, older_than: time::Duration) -> anyhow::Result<u64> {
+    let cutoff = to_rfc3339(now_utc() - older_than);
+    let mut tx = audit::begin(pool, "trash purge", &cutoff).await?;
+    let purged = sqlx::query!("DELETE FROM trash WHERE deleted_at < ?", cutoff)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services, testutil};
+
+    #[test]
+    fn test_parse_rows() {
+        let rows = parse_rows(
+            r#"[{"table": "tasks", "row": {"id": "t"}},
+                {"table": "time_entries", "row": {"id": "e", "task_id": "t"}}]"#,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, "tasks");
+        assert_eq!(rows[1].1["task_id"], json!("t"));
+        assert!(parse_rows(r#"[{"table": "tasks"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_required_parents() {
+        let task = json!({"id": "t", "project_id": "p", "parent_id": null});
+        assert_eq!(
+            required_parents(Kind::Task, task.as_object().unwrap()),
+            [(Kind::Project, "p".to_string())]
+        );
+        let project = json!({"id": "p", "parent_id": null});
+        assert!(required_parents(Kind::Project, project.as_object().unwrap()).is_empty());
+        let entry = json!({"id": "e", "task_id": "t"});
+        assert_eq!(
+            required_parents(Kind::Entry, entry.as_object().unwrap()),
+            [(Kind::Task, "t".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_needs_parent() {
+        let pool = testutil::pool().await;
+        let project = testutil::project(&pool, "Acme").await;
+        let task = testutil::task(&pool, &project, "design").await;
+        testutil::entry(&pool, &task, "2024-07-01T09:00:00Z", "2024-07-01T10:00:00Z").await;
+        services::task::delete(&pool, &task).await.unwrap();
+        services::project::delete(&pool, &project).await.unwrap();
+
+        let err = restore(&pool, &task).await.unwrap_err().to_string();
+        assert!(err.contains(&format!("restore {project} first")), "{err}");
+        assert_eq!(list(&pool).await.unwrap().len(), 2);
+        assert_eq!(testutil::count(&pool, "tasks").await, 0);
+
+        restore(&pool, &project).await.unwrap();
+        restore(&pool, &task).await.unwrap();
+        assert_eq!(testutil::count(&pool, "tasks").await, 1);
+        assert_eq!(testutil::count(&pool, "time_entries").await, 1);
+        assert!(list(&pool).await.unwrap().is_empty());
+
+        // A parent deleted for good can't be brought back
+        services::task::delete(&pool, &task).await.unwrap();
+        let mut tx = audit::begin(&pool, "raw delete", &project).await.unwrap();
+        sqlx::query("DELETE FROM projects WHERE id = ?")
+            .bind(&project)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let err = restore(&pool, &task).await.unwrap_err().to_string();
+        assert!(err.contains("no longer exists"), "{err}");
+        assert_eq!(list(&pool).await.unwrap().len(), 1);
+    }
+}
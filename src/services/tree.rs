@@ -0,0 +1,98 @@
+//! Ordering and roll-ups for sub-projects and subtasks.
+//!
+//! Both helpers take the items in sibling order plus accessors for the item id and parent id.
+//! Items whose parent is not among them are treated as roots.
+
+use std::collections::{HashMap, HashSet};
+
+/// The items in depth-first order with their depth, roots at depth 0
+pub fn walk<T>(
+    items: &[T],
+    id: impl Fn(&T) -> &str,
+    parent: impl Fn(&T) -> Option<&str>,
+) -> Vec<(usize, &T)> {
+    let ids: HashSet<&str> = items.iter().map(&id).collect();
+    let mut children: HashMap<Option<&str>, Vec<&T>> = HashMap::new();
+    for item in items {
+        let key = parent(item).filter(|p| ids.contains(p));
+        children.entry(key).or_default().push(item);
+    }
+
+    let mut out = Vec::with_capacity(items.len());
+    let mut visited = HashSet::new();
+    let mut stack: Vec<(usize, &T)> = children
+        .get(&None)
+        .map(|roots| roots.iter().rev().map(|&item| (0, item)).collect())
+        .unwrap_or_default();
+    while let Some((depth, item)) = stack.pop() {
+        if !visited.insert(id(item)) {
+            continue;
+        }
+        out.push((depth, item));
+        if let Some(kids) = children.get(&Some(id(item))) {
+            stack.extend(kids.iter().rev().map(|&kid| (depth + 1, kid)));
+        }
+    }
+    out
+}
+
+/// The total of every item: its own value plus the totals of its children
+pub fn roll_up<T>(
+    items: &[T],
+    id: impl Fn(&T) -> &str,
+    parent: impl Fn(&T) -> Option<&str>,
+    own: impl Fn(&T) -> i64,
+) -> HashMap<String, i64> {
+    let mut totals: HashMap<String, i64> = items
+        .iter()
+        .map(|item| (id(item).to_string(), own(item)))
+        .collect();
+    // Children come after their parent in a walk, so going backwards finishes them first
+    for (_, item) in walk(items, &id, &parent).into_iter().rev() {
+        if let Some(parent) = parent(item).filter(|p| totals.contains_key(*p)) {
+            let total = totals[id(item)];
+            *totals.get_mut(parent).expect("checked above") += total;
+        }
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Vec<(&'static str, Option<&'static str>, i64)> {
+        vec![
+            ("a", None, 1),
+            ("b", Some("a"), 2),
+            ("c", Some("b"), 4),
+            ("d", None, 8),
+            ("e", Some("a"), 16),
+            ("f", Some("gone"), 32),
+        ]
+    }
+
+    #[test]
+    fn test_walk() {
+        let items = items();
+        let order: Vec<(usize, &str)> = walk(&items, |i| i.0, |i| i.1)
+            .into_iter()
+            .map(|(depth, i)| (depth, i.0))
+            .collect();
+        assert_eq!(
+            order,
+            [(0, "a"), (1, "b"), (2, "c"), (1, "e"), (0, "d"), (0, "f")]
+        );
+    }
+
+    #[test]
+    fn test_roll_up() {
+        let items = items();
+        let totals = roll_up(&items, |i| i.0, |i| i.1, |i| i.2);
+        assert_eq!(totals["a"], 23);
+        assert_eq!(totals["b"], 6);
+        assert_eq!(totals["c"], 4);
+        assert_eq!(totals["d"], 8);
+        assert_eq!(totals["f"], 32);
+    }
+}
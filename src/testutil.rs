@@ -0,0 +1,72 @@
+//! Fixtures for tests that run against a real database: a fresh in-memory database with every
+//! migration applied, and shortcuts adding rows through the services.
+
+use crate::services::{self, audit, timeutil::*};
+use sqlx::{
+    SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// An empty, migrated in-memory database. It lives on one connection that is never recycled,
+/// so every query sees the same data.
+pub async fn pool() -> SqlitePool {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+pub async fn project(pool: &SqlitePool, name: &str) -> String {
+    services::project::add(pool, name, None, None)
+        .await
+        .unwrap()
+}
+
+pub async fn task(pool: &SqlitePool, project_id: &str, name: &str) -> String {
+    let task = services::task::NewTask {
+        name: name.into(),
+        ..Default::default()
+    };
+    services::task::create(pool, project_id, &task)
+        .await
+        .unwrap()
+}
+
+/// A finished entry from `start` to `end` (RFC3339)
+pub async fn entry(pool: &SqlitePool, task_id: &str, start: &str, end: &str) -> String {
+    let id = Uuid::now_v7().to_string();
+    let (start_t, end_t) = (parse_rfc3339(start).unwrap(), parse_rfc3339(end).unwrap());
+    let mut tx = audit::begin(pool, "test entry", &id).await.unwrap();
+    sqlx::query(
+        "INSERT INTO time_entries(id, task_id, start_time, end_time, duration_seconds, local_date, created_at)
+         VALUES(?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(task_id)
+    .bind(start)
+    .bind(end)
+    .bind((end_t - start_t).whole_seconds())
+    .bind(local_date_str(start_t))
+    .bind(start)
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    tx.commit().await.unwrap();
+    id
+}
+
+/// Number of rows in `table`
+pub async fn count(pool: &SqlitePool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
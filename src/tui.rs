@@ -0,0 +1,552 @@
+use crate::{
+    models::{Project, Task},
+    services::{self, report::EntryLine, timer::ActiveTimer, timeutil::*},
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+};
+use sqlx::SqlitePool;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// How often data is reloaded from the database, so changes from other processes show up
+const REFRESH_EVERY: Duration = Duration::from_secs(5);
+
+const HELP: &str = "tab pane · ↑↓ select · enter start · w switch · x stop · e note · t times · m move to task · v week · q quit";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+    Projects,
+    Tasks,
+    Entries,
+}
+
+enum Mode {
+    Normal,
+    Edit {
+        entry_id: String,
+        field: Field,
+        buffer: String,
+    },
+}
+
+/// What the status line is editing
+enum Field {
+    Note,
+    /// `HH:MM-HH:MM`, or `HH:MM` for the running entry, on the day of the old start
+    Times {
+        start: OffsetDateTime,
+    },
+}
+
+impl Field {
+    fn label(&self) -> &'static str {
+        match self {
+            Field::Note => "note: ",
+            Field::Times { .. } => "times: ",
+        }
+    }
+
+    async fn save(&self, pool: &SqlitePool, entry_id: &str, input: &str) -> anyhow::Result<()> {
+        match self {
+            Field::Note => services::timer::set_note(pool, entry_id, input).await,
+            Field::Times { start } => {
+                let (from, to) = parse_times(input, *start)?;
+                services::timer::retime(pool, entry_id, from, to).await
+            }
+        }
+    }
+}
+
+struct App {
+    projects: Vec<Project>,
+    tasks: Vec<Task>,
+    entries: Vec<EntryLine>,
+    week: Vec<(String, i64)>,
+    active: Option<ActiveTimer>,
+    project_state: ListState,
+    task_state: ListState,
+    entry_state: ListState,
+    focus: Pane,
+    mode: Mode,
+    show_week: bool,
+    status: String,
+    quit: bool,
+}
+
+/// Keep a list selection inside `0..len`, selecting the first item when there was none
+fn clamp(state: &mut ListState, len: usize) {
+    match (state.selected(), len) {
+        (_, 0) => state.select(None),
+        (None, _) => state.select(Some(0)),
+        (Some(i), len) if i >= len => state.select(Some(len - 1)),
+        _ => {}
+    }
+}
+
+/// `HH:MM-HH:MM` or `HH:MM` on the local day of `start`; an end before the start is the next day
+fn parse_times(
+    input: &str,
+    start: OffsetDateTime,
+) -> anyhow::Result<(OffsetDateTime, Option<OffsetDateTime>)> {
+    let (from, to) = match input.split_once('-') {
+        Some((from, to)) => (from.trim(), Some(to.trim())),
+        None => (input.trim(), None),
+    };
+    let from = parse_local_datetime(from, local_date(start))?;
+    let to = to
+        .map(|to| parse_local_datetime_after(to, from))
+        .transpose()?;
+    Ok((from, to))
+}
+
+fn format_clock(secs: i64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            projects: vec![],
+            tasks: vec![],
+            entries: vec![],
+            week: vec![],
+            active: None,
+            project_state: ListState::default(),
+            task_state: ListState::default(),
+            entry_state: ListState::default(),
+            focus: Pane::Tasks,
+            mode: Mode::Normal,
+            show_week: false,
+            status: HELP.to_string(),
+            quit: false,
+        }
+    }
+
+    async fn refresh(&mut self, pool: &SqlitePool) -> anyhow::Result<()> {
+        self.projects = services::project::list(pool)
+            .await?
+            .into_iter()
+            .filter(|p| !p.archived)
+            .collect();
+        clamp(&mut self.project_state, self.projects.len());
+        self.refresh_tasks(pool).await?;
+
+        let today = today_local();
+        let today_s = format_date(today);
+        self.entries =
+            services::report::entries(pool, None, Some(&today_s), Some(&today_s), None).await?;
+        clamp(&mut self.entry_state, self.entries.len());
+
+        let first = week_start(today);
+        self.week = services::report::daily_range(pool, &format_date(first), &today_s).await?;
+        self.active = services::timer::current(pool).await?;
+        Ok(())
+    }
+
+    async fn refresh_tasks(&mut self, pool: &SqlitePool) -> anyhow::Result<()> {
+        self.tasks = match self.selected_project() {
+            Some(project) => services::task::list(pool, &project.id)
+                .await?
+                .into_iter()
+                .filter(|t| !t.archived)
+                .collect(),
+            None => vec![],
+        };
+        clamp(&mut self.task_state, self.tasks.len());
+        Ok(())
+    }
+
+    fn selected_project(&self) -> Option<&Project> {
+        self.project_state
+            .selected()
+            .and_then(|i| self.projects.get(i))
+    }
+
+    fn selected_task(&self) -> Option<&Task> {
+        self.task_state.selected().and_then(|i| self.tasks.get(i))
+    }
+
+    fn selected_entry(&self) -> Option<&EntryLine> {
+        self.entry_state
+            .selected()
+            .and_then(|i| self.entries.get(i))
+    }
+
+    async fn on_key(&mut self, pool: &SqlitePool, key: KeyEvent) -> anyhow::Result<()> {
+        if let Mode::Edit {
+            entry_id,
+            field,
+            buffer,
+        } = &mut self.mode
+        {
+            match key.code {
+                KeyCode::Enter => {
+                    field.save(pool, entry_id, buffer.trim()).await?;
+                    self.status = match field {
+                        Field::Note => "note saved",
+                        Field::Times { .. } => "times saved",
+                    }
+                    .to_string();
+                    self.mode = Mode::Normal;
+                    self.refresh(pool).await?;
+                }
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.status = HELP.to_string();
+                }
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Char(c) => buffer.push(c),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Pane::Projects => Pane::Tasks,
+                    Pane::Tasks => Pane::Entries,
+                    Pane::Entries => Pane::Projects,
+                }
+            }
+            KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Pane::Projects => Pane::Entries,
+                    Pane::Tasks => Pane::Projects,
+                    Pane::Entries => Pane::Tasks,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(pool, -1).await?,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(pool, 1).await?,
+            KeyCode::Enter | KeyCode::Char('s') => {
+                if let Some(task) = self.selected_task() {
+                    let (id, name) = (task.id.clone(), task.name.clone());
+                    services::timer::start(pool, &id, None).await?;
+                    self.status = format!("started {name}");
+                    for warning in services::budget::overruns(pool, &id).await? {
+                        self.status = format!("warning: {warning}");
+                    }
+                    self.refresh(pool).await?;
+                }
+            }
+            KeyCode::Char('w') => {
+                if let Some(task) = self.selected_task() {
+                    let (id, name) = (task.id.clone(), task.name.clone());
+                    services::timer::switch(pool, &id, None).await?;
+                    self.status = format!("switched running entry to {name}");
+                    self.refresh(pool).await?;
+                }
+            }
+            KeyCode::Char('x') => {
+                self.status = match services::timer::stop(pool, None).await? {
+                    Some(stopped) if stopped.flagged => format!(
+                        "stopped after {}; flagged for review",
+                        format_duration(stopped.duration_seconds)
+                    ),
+                    Some(stopped) => {
+                        format!(
+                            "stopped after {}",
+                            format_duration(stopped.duration_seconds)
+                        )
+                    }
+                    None => "no timer running".to_string(),
+                };
+                self.refresh(pool).await?;
+            }
+            KeyCode::Char('e') => {
+                let entry = match self.focus {
+                    Pane::Entries => self
+                        .selected_entry()
+                        .map(|e| (e.id.clone(), e.note.clone())),
+                    _ => self
+                        .active
+                        .as_ref()
+                        .map(|a| (a.entry_id.clone(), a.note.clone())),
+                };
+                if let Some((entry_id, note)) = entry {
+                    self.mode = Mode::Edit {
+                        entry_id,
+                        field: Field::Note,
+                        buffer: note.unwrap_or_default().replace('\n', "; "),
+                    };
+                }
+            }
+            KeyCode::Char('t') if self.focus == Pane::Entries => {
+                if let Some(entry) = self.selected_entry() {
+                    let start = parse_rfc3339(&entry.start_time)?;
+                    let mut buffer = local_time_str(start);
+                    if let Some(seconds) = entry.duration_seconds {
+                        let end = start + time::Duration::seconds(seconds);
+                        buffer = format!("{buffer}-{}", local_time_str(end));
+                    }
+                    self.mode = Mode::Edit {
+                        entry_id: entry.id.clone(),
+                        field: Field::Times { start },
+                        buffer,
+                    };
+                }
+            }
+            KeyCode::Char('m') if self.focus == Pane::Entries => {
+                if let (Some(entry), Some(task)) = (self.selected_entry(), self.selected_task()) {
+                    let (entry_id, task_id, name) =
+                        (entry.id.clone(), task.id.clone(), task.name.clone());
+                    services::timer::set_task(pool, &entry_id, &task_id).await?;
+                    self.status = format!("moved entry to {name}");
+                    self.refresh(pool).await?;
+                }
+            }
+            KeyCode::Char('v') => self.show_week = !self.show_week,
+            KeyCode::Char('r') => self.refresh(pool).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn move_selection(&mut self, pool: &SqlitePool, delta: isize) -> anyhow::Result<()> {
+        let (state, len) = match self.focus {
+            Pane::Projects => (&mut self.project_state, self.projects.len()),
+            Pane::Tasks => (&mut self.task_state, self.tasks.len()),
+            Pane::Entries => (&mut self.entry_state, self.entries.len()),
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + delta).clamp(0, len as isize - 1) as usize));
+        if self.focus == Pane::Projects {
+            self.task_state.select(Some(0));
+            self.refresh_tasks(pool).await?;
+        }
+        Ok(())
+    }
+
+    fn pane_block(&self, title: &str, pane: Pane) -> Block<'static> {
+        let style = if self.focus == pane {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+        Block::bordered()
+            .title(format!(" {title} "))
+            .border_style(style)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [timer_area, main_area, status_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [projects_area, tasks_area, right_area] = Layout::horizontal([
+            Constraint::Percentage(25),
+            Constraint::Percentage(30),
+            Constraint::Percentage(45),
+        ])
+        .areas(main_area);
+
+        self.draw_timer(frame, timer_area);
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let projects = List::new(self.projects.iter().map(|p| ListItem::new(p.name.clone())))
+            .block(self.pane_block("Projects", Pane::Projects))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(projects, projects_area, &mut self.project_state);
+
+        let running_task = self.active.as_ref().map(|a| a.task_id.as_str());
+        let tasks = List::new(self.tasks.iter().map(|t| {
+            let marker = if Some(t.id.as_str()) == running_task {
+                "● "
+            } else {
+                "  "
+            };
+            ListItem::new(format!("{marker}{}", t.name))
+        }))
+        .block(self.pane_block("Tasks", Pane::Tasks))
+        .highlight_style(highlight);
+        frame.render_stateful_widget(tasks, tasks_area, &mut self.task_state);
+
+        if self.show_week {
+            self.draw_week(frame, right_area);
+        } else {
+            let entries = List::new(self.entries.iter().map(|e| {
+                let start = parse_rfc3339(&e.start_time)
+                    .map(local_time_str)
+                    .unwrap_or_default();
+                let duration = e
+                    .duration_seconds
+                    .map(format_duration)
+                    .unwrap_or_else(|| "running".to_string());
+                let note = e.note.as_deref().unwrap_or("").replace('\n', "; ");
+                ListItem::new(format!(
+                    "{start} {duration:>8}  {} / {}  {note}",
+                    e.project_name, e.task_name
+                ))
+            }))
+            .block(self.pane_block("Today", Pane::Entries))
+            .highlight_style(highlight);
+            frame.render_stateful_widget(entries, right_area, &mut self.entry_state);
+        }
+
+        let status = match &self.mode {
+            Mode::Edit { field, buffer, .. } => Line::from(vec![
+                Span::styled(field.label(), Style::default().fg(Color::Yellow)),
+                Span::raw(format!("{buffer}_")),
+                Span::styled(
+                    "  (enter save · esc cancel)",
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]),
+            Mode::Normal => Line::styled(self.status.clone(), Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn draw_timer(&self, frame: &mut Frame, area: Rect) {
+        let line = match &self.active {
+            Some(active) => {
+                let mut spans = vec![
+                    Span::styled("● ", Style::default().fg(Color::Green)),
+                    Span::styled(
+                        format_clock(active.elapsed_seconds()),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(format!("  {} / {}", active.project_name, active.task_name)),
+                ];
+                if let Some(note) = active.note.as_deref().filter(|n| !n.is_empty()) {
+                    spans.push(Span::styled(
+                        format!("  {}", note.replace('\n', "; ")),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                Line::from(spans)
+            }
+            None => Line::styled("No timer running", Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(
+            Paragraph::new(line).block(Block::bordered().title(" rustytime ")),
+            area,
+        );
+    }
+
+    fn draw_week(&self, frame: &mut Frame, area: Rect) {
+        let mut lines: Vec<Line> = self
+            .week
+            .iter()
+            .map(|(date, total)| {
+                let weekday = parse_date(date)
+                    .map(|d| d.weekday().to_string()[..3].to_string())
+                    .unwrap_or_default();
+                Line::raw(format!("{weekday} {date}  {:>8}", format_duration(*total)))
+            })
+            .collect();
+        let total: i64 = self.week.iter().map(|(_, t)| t).sum();
+        lines.push(Line::raw(""));
+        lines.push(Line::styled(
+            format!("Total          {:>8}", format_duration(total)),
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+        frame.render_widget(
+            Paragraph::new(lines).block(self.pane_block("This week", Pane::Entries)),
+            area,
+        );
+    }
+}
+
+async fn run_app(terminal: &mut DefaultTerminal, pool: &SqlitePool) -> anyhow::Result<()> {
+    let mut app = App::new();
+    app.refresh(pool).await?;
+    let mut last_refresh = Instant::now();
+
+    while !app.quit {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        // Wake up at least once a second so the running clock ticks
+        if event::poll(Duration::from_secs(1))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && let Err(e) = app.on_key(pool, key).await
+        {
+            app.mode = Mode::Normal;
+            app.status = format!("error: {e}");
+        }
+
+        if last_refresh.elapsed() >= REFRESH_EVERY {
+            app.refresh(pool).await?;
+            last_refresh = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+/// Run the dashboard until the user quits
+pub async fn run(pool: &SqlitePool) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, pool).await;
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_clock() {
+        assert_eq!(format_clock(0), "00:00:00");
+        assert_eq!(format_clock(3723), "01:02:03");
+        assert_eq!(format_clock(36000), "10:00:00");
+    }
+
+    #[test]
+    fn test_parse_times() {
+        let day = time::macros::date!(2024 - 07 - 15);
+        let utc = |d: time::Date, h: u8, m: u8| {
+            d.with_time(time::Time::from_hms(h, m, 0).unwrap())
+                .assume_utc()
+        };
+        // Started at 09:00 local (07:00 UTC)
+        let start = utc(day, 7, 0);
+        assert_eq!(
+            parse_times("10:00-11:30", start).unwrap(),
+            (utc(day, 8, 0), Some(utc(day, 9, 30)))
+        );
+        assert_eq!(
+            parse_times("22:00-01:00", start).unwrap(),
+            (utc(day, 20, 0), Some(utc(day, 23, 0)))
+        );
+        assert_eq!(
+            parse_times(" 10:00 ", start).unwrap(),
+            (utc(day, 8, 0), None)
+        );
+        assert!(parse_times("soon", start).is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut state = ListState::default();
+        clamp(&mut state, 3);
+        assert_eq!(state.selected(), Some(0));
+
+        state.select(Some(5));
+        clamp(&mut state, 3);
+        assert_eq!(state.selected(), Some(2));
+
+        clamp(&mut state, 0);
+        assert_eq!(state.selected(), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! Named workspaces: separate databases picked by name.
+//!
+//! Workspaces are registered in the `[workspaces]` table of the configuration file as
+//! `NAME = "path"`, and the `workspace` setting says which one commands use. `default` stands
+//! for the database used without workspaces: the `db` setting, else the built-in location.
+
+use crate::{config::Settings, db};
+use serde::Serialize;
+use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
+
+pub const DEFAULT: &str = "default";
+
+pub fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        anyhow::bail!("workspace names use lowercase letters, digits, '_' and '-', got '{name}'");
+    }
+    Ok(())
+}
+
+/// Where `workspace create` puts a workspace's database: `workspaces/NAME.db` next to the
+/// default database
+pub fn default_path(name: &str) -> PathBuf {
+    let base = db::default_db_path();
+    base.parent()
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join("workspaces")
+        .join(format!("{name}.db"))
+}
+
+/// Every workspace, `default` first, with the database it uses
+pub fn list(settings: &Settings) -> Vec<(String, String)> {
+    let (_, default) = db::resolve_db_url(None, settings.db.as_deref());
+    let mut out = vec![(DEFAULT.to_string(), default.display().to_string())];
+    out.extend(
+        settings
+            .workspaces
+            .iter()
+            .filter(|(name, _)| name.as_str() != DEFAULT)
+            .map(|(name, db)| (name.clone(), db.clone())),
+    );
+    out
+}
+
+/// The selected workspace: `--workspace`, else the `workspace` setting, else `default`. A
+/// setting of `default` selects the database used without workspaces, like no setting.
+pub fn current<'a>(flag: Option<&'a str>, settings: &'a Settings) -> &'a str {
+    flag.or(settings.workspace.as_deref()).unwrap_or(DEFAULT)
+}
+
+/// The database of workspace `name` as a `--db`-style value; `None` for `default`, which
+/// leaves the choice to the `db` setting
+pub fn db(settings: &Settings, name: &str) -> anyhow::Result<Option<String>> {
+    if name == DEFAULT {
+        return Ok(None);
+    }
+    match settings.workspaces.get(name) {
+        Some(db) => Ok(Some(db.clone())),
+        None => anyhow::bail!("no workspace {name}; see `workspace list`"),
+    }
+}
+
+/// Open a workspace database for reading only, without creating or migrating it
+pub async fn open_read_only(name: &str, db: &str) -> anyhow::Result<SqlitePool> {
+    let (url, path) = db::from_value(db);
+    if !path.exists() {
+        anyhow::bail!("workspace {name}: {} does not exist", path.display());
+    }
+    let options = SqliteConnectOptions::from_str(&url)?.read_only(true);
+    Ok(SqlitePool::connect_with(options).await?)
+}
+
+/// One day of a cross-workspace report
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Day {
+    pub date: String,
+    pub total_seconds: i64,
+    /// Time per workspace; workspaces without time that day are left out
+    pub workspaces: BTreeMap<String, i64>,
+}
+
+/// Combine each workspace's daily totals into one list of days, oldest first
+pub fn merge(per_workspace: Vec<(String, Vec<(String, i64)>)>) -> Vec<Day> {
+    let mut days: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for (name, totals) in per_workspace {
+        for (date, seconds) in totals {
+            *days
+                .entry(date)
+                .or_default()
+                .entry(name.clone())
+                .or_default() += seconds;
+        }
+    }
+    days.into_iter()
+        .map(|(date, workspaces)| Day {
+            date,
+            total_seconds: workspaces.values().sum(),
+            workspaces,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            workspace: Some("work".into()),
+            workspaces: [("work".to_string(), "/data/work.db".to_string())]
+                .into_iter()
+                .collect(),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_current_and_db() {
+        let settings = settings();
+        assert_eq!(current(None, &settings), "work");
+        assert_eq!(current(Some("default"), &settings), "default");
+        assert_eq!(current(None, &Settings::default()), "default");
+
+        assert_eq!(
+            db(&settings, "work").unwrap().as_deref(),
+            Some("/data/work.db")
+        );
+        assert_eq!(db(&settings, "default").unwrap(), None);
+        assert!(db(&settings, "home").is_err());
+
+        // A profile switching back to the default over a top-level `workspace`
+        let settings = Settings {
+            workspace: Some("default".into()),
+            ..settings
+        };
+        assert_eq!(current(None, &settings), "default");
+        assert_eq!(db(&settings, current(None, &settings)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("personal").is_ok());
+        assert!(check_name("client-2").is_ok());
+        assert!(check_name("My Work").is_err());
+        assert!(check_name("").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let days = merge(vec![
+            (
+                "personal".into(),
+                vec![("2024-01-01".into(), 600), ("2024-01-02".into(), 300)],
+            ),
+            ("work".into(), vec![("2024-01-02".into(), 3600)]),
+        ]);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, "2024-01-01");
+        assert_eq!(days[0].total_seconds, 600);
+        assert_eq!(days[1].total_seconds, 3900);
+        assert_eq!(days[1].workspaces["work"], 3600);
+    }
+}